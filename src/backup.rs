@@ -0,0 +1,33 @@
+use crate::escape;
+
+// Lets a script redefining fish functions or aliases be reverted. The first
+// time basrs touches a function in a session its old definition is copied
+// aside, `basrs_restore_functions` puts every copy back and erases the
+// functions that did not exist before.
+pub const RESTORE_FUNCTION: &str =
+    "function basrs_restore_functions --description \"Restore the fish functions basrs overwrote\"
+    for name in $__basrs_backups
+        functions -e $name
+        if functions -q __basrs_backup_$name
+            functions --copy __basrs_backup_$name $name
+            functions -e __basrs_backup_$name
+        end
+    end
+    set -e __basrs_backups
+end";
+
+// Fish commands saving the current definition of `name`, to go before
+// whatever changes it
+pub fn backup_lines(name: &str) -> Vec<String> {
+    let quoted = escape(name);
+    vec![
+        format!("if not contains -- {} $__basrs_backups", quoted),
+        format!("    set -g __basrs_backups $__basrs_backups {}", quoted),
+        format!(
+            "    functions -q -- {0}; and functions --copy -- {0} {1}",
+            quoted,
+            escape(&format!("__basrs_backup_{}", name))
+        ),
+        "end".to_string(),
+    ]
+}
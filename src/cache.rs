@@ -0,0 +1,245 @@
+use std::collections::hash_map::DefaultHasher;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::capture::Capture;
+use crate::dirs;
+use crate::shell::Shell;
+
+// Baselines nobody asked for in this long are from sessions that are gone
+const BASELINE_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+// Every edit of a cached script leaves an output behind
+const OUTPUT_MAX_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+// Generated script stored under ~/.cache/basrs, valid as long as the inputs it
+// was generated from are unchanged
+pub struct OutputCache {
+    path: PathBuf,
+}
+
+impl OutputCache {
+    // The key covers the bash command, the files it reads and the inherited
+    // PATH/HOME which the diff is relative to
+    pub fn new(command: &str, files: &[PathBuf]) -> Option<OutputCache> {
+        let mut hasher = DefaultHasher::new();
+        env!("CARGO_PKG_VERSION").hash(&mut hasher);
+        command.hash(&mut hasher);
+        for file in files {
+            file.hash(&mut hasher);
+            file_stamp(file).hash(&mut hasher);
+        }
+        for var in ["PATH", "HOME"] {
+            env::var_os(var).hash(&mut hasher);
+        }
+        let name = format!("{:016x}.fish", hasher.finish());
+        Some(OutputCache {
+            path: dirs::cache_dir()?.join("output").join(name),
+        })
+    }
+
+    // For `basrs source <script>` with --cache-output: keyed by the content of
+    // the script rather than its timestamp, by the arguments basrs was called
+    // with, which hold the options, by the shell binary and by `inputs`, the
+    // contents of the files the options name and the inherited environment
+    pub fn for_script(
+        command: &str,
+        script: &Path,
+        program: &Path,
+        inputs: &[&[u8]],
+    ) -> Option<OutputCache> {
+        let mut hasher = DefaultHasher::new();
+        env!("CARGO_PKG_VERSION").hash(&mut hasher);
+        command.hash(&mut hasher);
+        fs::read(script).ok()?.hash(&mut hasher);
+        stable_args(env::args_os().skip(1)).hash(&mut hasher);
+        // An upgraded shell is a different binary
+        program.hash(&mut hasher);
+        file_stamp(program).hash(&mut hasher);
+        inputs.hash(&mut hasher);
+        let name = format!("{:016x}.fish", hasher.finish());
+        Some(OutputCache {
+            path: dirs::cache_dir()?.join("output").join(name),
+        })
+    }
+
+    pub fn get(&self) -> Option<String> {
+        fs::read_to_string(&self.path).ok()
+    }
+
+    pub fn store(&self, script: &str) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+            prune(dir, OUTPUT_MAX_AGE);
+        }
+        fs::write(&self.path, script)
+    }
+}
+
+// Capture of the empty command, the state every diff starts from. It only
+// depends on the shell, the files it reads on startup and the environment it
+// starts with, so within a session it is the same on every run.
+pub struct BaselineCache {
+    path: PathBuf,
+}
+
+impl BaselineCache {
+    pub fn new(
+        shell: Shell,
+        program: &Path,
+        inherited: &[u8],
+        extra_env: &[(&str, &str)],
+        shell_flags: &[&str],
+        all_vars: bool,
+    ) -> Option<BaselineCache> {
+        let mut hasher = DefaultHasher::new();
+        env!("CARGO_PKG_VERSION").hash(&mut hasher);
+        shell.capture_script().hash(&mut hasher);
+        // An upgraded shell is a different binary
+        program.hash(&mut hasher);
+        file_stamp(program).hash(&mut hasher);
+        shell_flags.hash(&mut hasher);
+        all_vars.hash(&mut hasher);
+        let home = env::var_os("HOME").map(PathBuf::from);
+        let mut rc_files: Vec<PathBuf> = [
+            "/etc/profile",
+            "/etc/bash.bashrc",
+            "/etc/zshenv",
+            "/etc/zsh/zshenv",
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+        if let Some(home) = &home {
+            for file in [".bashrc", ".bash_profile", ".bash_login", ".profile"] {
+                rc_files.push(home.join(file));
+            }
+        }
+        rc_files.extend(env::var_os("BASH_ENV").map(PathBuf::from));
+        // zsh reads .zshenv in every script, the others in login and
+        // interactive shells
+        let zdotdir = env::var_os("ZDOTDIR").map(PathBuf::from).or(home);
+        if let Some(dir) = zdotdir {
+            for file in [".zshenv", ".zprofile", ".zshrc", ".zlogin"] {
+                rc_files.push(dir.join(file));
+            }
+        }
+        for file in &rc_files {
+            file.hash(&mut hasher);
+            file_stamp(file).hash(&mut hasher);
+        }
+        inherited.hash(&mut hasher);
+        extra_env.hash(&mut hasher);
+        let name = format!("{:016x}", hasher.finish());
+        Some(BaselineCache {
+            path: dirs::cache_dir()?.join("baseline").join(name),
+        })
+    }
+
+    pub fn get(&self) -> Option<Capture> {
+        Capture::parse(fs::read(&self.path).ok()?).ok()
+    }
+
+    pub fn store(&self, capture: &Capture) -> io::Result<()> {
+        let dir = self.path.parent().expect("baseline path has a directory");
+        fs::create_dir_all(dir)?;
+        prune(dir, BASELINE_MAX_AGE);
+        fs::write(&self.path, capture.output())
+    }
+}
+
+// The arguments without the paths of the dumps fish passes through `psub`,
+// a new temporary file on every call. Their contents are part of the key.
+fn stable_args(args: impl Iterator<Item = OsString>) -> Vec<OsString> {
+    const FRESH_PATHS: [&str; 2] = ["--fish-dump", "--inherit-fish-env"];
+    let mut stable = Vec::new();
+    let mut args = args.peekable();
+    while let Some(arg) = args.next() {
+        let text = arg.to_string_lossy();
+        if text == "--" {
+            stable.push(arg);
+            stable.extend(args);
+            break;
+        }
+        match text.split_once('=') {
+            Some((flag, _)) if FRESH_PATHS.contains(&flag) => stable.push(flag.into()),
+            _ if FRESH_PATHS.contains(&text.as_ref()) => {
+                stable.push(arg);
+                args.next();
+            }
+            _ => stable.push(arg),
+        }
+    }
+    stable
+}
+
+// Removes the files in `dir` not modified for `max_age`
+fn prune(dir: &Path, max_age: Duration) {
+    let Ok(entries) = dir.read_dir() else {
+        return;
+    };
+    let now = SystemTime::now();
+    for entry in entries.flatten() {
+        let stale = entry
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .is_ok_and(|modified| now.duration_since(modified).unwrap_or_default() > max_age);
+        if stale {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+}
+
+fn file_stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsString;
+    use std::path::Path;
+
+    use super::{stable_args, OutputCache};
+
+    // Editing the config or the policy, or another inherited environment,
+    // makes for another cached output
+    #[test]
+    fn inputs_change_the_key() {
+        let script = Path::new(file!());
+        let program = Path::new("/bin/sh");
+        let key = |config: &[u8]| {
+            OutputCache::for_script("source x", script, program, &[config, b"A=1\0"])
+                .map(|cache| cache.path)
+        };
+        assert_eq!(key(b"wrap = []"), key(b"wrap = []"));
+        assert_ne!(key(b"wrap = []"), key(b"wrap = [\"env\"]"));
+    }
+
+    #[test]
+    fn psub_paths_are_left_out() {
+        let args = |args: &[&str]| stable_args(args.iter().map(OsString::from));
+        assert_eq!(
+            args(&[
+                "--fish-dump",
+                "/tmp/.psub.1",
+                "--inherit-fish-env=/tmp/.psub.2",
+                "-v"
+            ]),
+            args(&[
+                "--fish-dump",
+                "/tmp/.psub.3",
+                "--inherit-fish-env=/tmp/.psub.4",
+                "-v"
+            ])
+        );
+        assert_ne!(
+            args(&["--", "--fish-dump", "a"]),
+            args(&["--", "--fish-dump", "b"])
+        );
+    }
+}
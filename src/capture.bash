@@ -0,0 +1,169 @@
+# basrs capture helper, evaluates "$1" and prints aliases, functions, the exit
+# status, the step a chain of commands stopped at, the number of still running
+# background jobs, where variables were assigned, further shell state and an
+# `env -0` dump as `<byte length>:<bytes>` records, so that a dump cut short
+# is told apart. Regenerated by basrs whenever it differs from the version
+# basrs ships.
+
+__basrs_all_vars=${4-}
+
+# The records go to fd 9, a copy of stdout the command doesn't get. Everything
+# else on stdout, the command's output and whatever traps it sets print, goes
+# to stderr.
+exec 9>&1 >&2
+
+# Prints the records and the dump of the current state
+__basrs_dump() {
+    # Everything is collected before printing, the trailing `.` keeps command
+    # substitution from eating final newlines
+    __basrs_aliases=$(alias; echo .)
+    __basrs_functions=$(unset -f __basrs_dump; declare -f; echo .)
+    # Shell state beyond variables as `<key>=<value>` lines, here the enabled
+    # `set -o` and `shopt` options, the umask, the previous directory, the
+    # directory stack below the current one as `DIRSTACK[<n>]`, whether the
+    # current directory still exists as `pwd_exists`, 1 or 0, the soft
+    # resource limits as `ulimit -<flag>`, the flag taken from lines like
+    # `open files  (-n) 1024`, the programmable completions as
+    # `complete <command>`, and the arrays as `array <name>`, the elements
+    # quoted with %q and separated by spaces, or `assoc <name>` with nothing
+    # for associative ones
+    __basrs_state="shellopts=$SHELLOPTS
+bashopts=$BASHOPTS
+umask=$(umask)
+OLDPWD=${OLDPWD-}"
+    for ((__basrs_i = 1; __basrs_i < ${#DIRSTACK[@]}; __basrs_i++)); do
+        __basrs_state+="
+DIRSTACK[$__basrs_i]=${DIRSTACK[__basrs_i]}"
+    done
+    if [[ -d $PWD ]]; then
+        __basrs_state+="
+pwd_exists=1"
+    else
+        __basrs_state+="
+pwd_exists=0"
+    fi
+    while IFS= read -r __basrs_line; do
+        __basrs_flag=${__basrs_line##*-}
+        __basrs_state+="
+ulimit -${__basrs_flag%%)*}=${__basrs_line##* }"
+    done < <(ulimit -Sa)
+    while IFS= read -r __basrs_line; do
+        __basrs_state+="
+complete ${__basrs_line##* }=$__basrs_line"
+    done < <(complete -p)
+    for __basrs_name in $(compgen -A arrayvar); do
+        case $__basrs_name in
+        __basrs_* | BASH_* | COMP_WORDS | DIRSTACK | FUNCNAME | GROUPS | PIPESTATUS) continue ;;
+        esac
+        if [[ ${!__basrs_name@a} == *A* ]]; then
+            __basrs_state+="
+assoc $__basrs_name="
+            continue
+        fi
+        eval '__basrs_elements=("${'"$__basrs_name"'[@]}")'
+        __basrs_line=
+        for __basrs_element in "${__basrs_elements[@]}"; do
+            printf -v __basrs_element '%q' "$__basrs_element"
+            __basrs_line+=" $__basrs_element"
+        done
+        __basrs_state+="
+array $__basrs_name=${__basrs_line# }"
+    done
+
+    # With $4 set to 1, plain variables the shell doesn't export are exported
+    # for the dump below and listed in the state as `shellvars`. Ones with
+    # attributes, such as arrays, and those bash updates by itself are left out.
+    if [ "$__basrs_all_vars" = 1 ]; then
+        __basrs_shellvars=
+        for __basrs_name in $(compgen -v); do
+            case $__basrs_name in
+            __basrs_* | _ | BASH* | EPOCH* | FUNCNAME | HISTCMD | LINENO | RANDOM | SRANDOM | SECONDS) ;;
+            *)
+                if [ -z "${!__basrs_name@a}" ] && [ -n "${!__basrs_name+set}" ]; then
+                    __basrs_shellvars+=" $__basrs_name"
+                fi
+                ;;
+            esac
+        done
+        __basrs_state+="
+shellvars=${__basrs_shellvars# }"
+        [ -n "$__basrs_shellvars" ] && export $__basrs_shellvars
+    fi
+
+    # LC_ALL=C makes ${#...} count bytes, set in a subshell as it may be
+    # exported
+    (
+        LC_ALL=C
+        for __basrs_record in "${__basrs_aliases%.}" "${__basrs_functions%.}" \
+            "$__basrs_status" "${__basrs_step-}" "${#__basrs_jobs[@]}" "${__basrs_origins-}" \
+            "$__basrs_state"; do
+            printf '%s:%s' "${#__basrs_record}" "$__basrs_record"
+        done
+    )
+    # Printed directly as variables cannot hold the NULs, which keep values
+    # with newlines intact. An env without -0 falls back to lines.
+    __basrs_size=$({ env -0 2>/dev/null || env; } | LC_ALL=C wc -c)
+    printf '%s:' $((__basrs_size))
+    env -0 2>/dev/null || env
+}
+
+# With $5 set to 1, the state before the command comes first, saving basrs a
+# separate run of the empty command. Dumped from a subshell, exporting the
+# unexported variables doesn't carry over to the command.
+if [ "${5-}" = 1 ]; then
+    (
+        __basrs_status=0
+        __basrs_jobs=()
+        __basrs_dump >&9
+    )
+fi
+
+# With $3 set to 1, every assignment in a sourced file or function is noted as
+# `<name> <file>:<line>`, later lines win
+if [ "$3" = 1 ]; then
+    __basrs_self=${BASH_SOURCE[0]}
+    __basrs_nl=$'\n'
+    __basrs_assignment='^(export|declare|typeset|readonly)?[[:space:]]*(-[[:alpha:]]+[[:space:]]+)*([[:alpha:]_][[:alnum:]_]*)\+?='
+    # Left as the command leaves it only if it was already on
+    [[ $- == *T* ]] || __basrs_functrace=off
+    set -T
+    # On one line, a line break in the trap would shift $LINENO
+    __basrs_trace='[[ ${BASH_SOURCE[0]} != "$__basrs_self" && $BASH_COMMAND =~ $__basrs_assignment ]]'
+    __basrs_trace+=' && __basrs_origins+="${BASH_REMATCH[3]} ${BASH_SOURCE[0]}:$LINENO$__basrs_nl"'
+    trap "$__basrs_trace" DEBUG
+fi
+
+# With $6 set to 1, a command ending the shell with `exit` still gets the
+# state dumped, with the exit status as its status. The trap writes through
+# fd 8, a copy of fd 9 the command doesn't get either, or background jobs
+# would hold the capture open. bash sets fd 8 aside for the eval on the
+# lowest free descriptor from 10 on, and an `exit` in a script leaves it
+# there rather than putting it back.
+if [ "${6-}" = 1 ]; then
+    exec 8>&9 9>&-
+    exec {__basrs_aside}>/dev/null {__basrs_aside}>&-
+    trap '__basrs_status=$?; [ -n "${__basrs_done-}" ] || {
+        [ -e /dev/fd/8 ] || exec 8>&"$__basrs_aside"
+        __basrs_jobs=()
+        __basrs_dump >&8
+    }' EXIT
+    eval "$1" 8>&-
+    __basrs_status=$?
+    exec 9>&8 8>&-
+else
+    eval "$1" 9>&-
+    __basrs_status=$?
+fi
+__basrs_done=1
+trap - DEBUG
+[ "${__basrs_functrace-}" = off ] && set +T
+
+# Background jobs get up to $2 tenths of a second to finish
+__basrs_ticks=${2:-0}
+while [ "$__basrs_ticks" -gt 0 ] && [ -n "$(jobs -rp)" ]; do
+    sleep 0.1
+    __basrs_ticks=$((__basrs_ticks - 1))
+done
+__basrs_jobs=($(jobs -rp))
+
+__basrs_dump >&9
@@ -0,0 +1,703 @@
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsString;
+use std::hash::{Hash, Hasher};
+use std::io;
+#[cfg(unix)]
+use std::io::IsTerminal;
+use std::ops::Range;
+#[cfg(unix)]
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use memchr::{memchr, memchr_iter};
+use tracing::{debug, debug_span, info_span};
+
+use crate::child;
+#[cfg(all(unix, feature = "daemon"))]
+use crate::daemon;
+use crate::error::{Error, SECTIONS};
+use crate::helper;
+#[cfg(all(unix, feature = "pty"))]
+use crate::pty::Pty;
+use crate::shell::Shell;
+#[cfg(unix)]
+use crate::signals;
+use crate::ssh;
+use crate::wsl;
+
+// Environment variables, borrowed from the capture output wherever possible
+pub type Env<'a> = HashMap<Cow<'a, str>, Cow<'a, str>>;
+
+// Raw output of the capture script, parsed lazily into a Snapshot
+pub struct Capture {
+    output: Vec<u8>,
+    // Byte ranges of the env, alias and function sections in `output`
+    sections: [Range<usize>; 3],
+    status: i32,
+    step: Option<usize>,
+    jobs: usize,
+    origins: Range<usize>,
+    state: Range<usize>,
+    // What the command printed, unless it went to the terminal
+    stderr: Vec<u8>,
+}
+
+// State of the shell after evaluating a command
+#[derive(Default)]
+pub struct Snapshot<'a> {
+    pub env: Env<'a>,
+    pub aliases: Cow<'a, str>,
+    pub functions: Cow<'a, str>,
+    // Hashes of the raw alias and function sections, equal hashes mean the
+    // section is unchanged and needs no parsing
+    pub aliases_hash: u64,
+    pub functions_hash: u64,
+    // Exit status of the evaluated command itself
+    pub status: i32,
+    // For a chain of commands, how many of them succeeded
+    pub step: Option<usize>,
+    // Background jobs still running when the environment was captured
+    pub jobs: usize,
+    // Where each variable was last assigned, as `<file>:<line>`, when traced
+    pub origins: HashMap<String, String>,
+    // Shell state beyond variables by key, such as the enabled shell options,
+    // empty for shells the helper doesn't read it from
+    pub state: HashMap<String, String>,
+    // Output of the command, when captured
+    pub stderr: Cow<'a, str>,
+}
+
+// Where the output of the command goes. The helper sends all of it to stderr,
+// keeping stdout for the capture.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StderrMode {
+    // Straight to basrs's stderr
+    #[default]
+    Inherit,
+    // Captured, and included in the script as comments
+    Comment,
+    // Captured, and shown only when the command failed
+    Report,
+}
+
+impl StderrMode {
+    pub fn from_name(name: &str) -> Option<StderrMode> {
+        match name {
+            "inherit" => Some(StderrMode::Inherit),
+            "comment" => Some(StderrMode::Comment),
+            "report" => Some(StderrMode::Report),
+            _ => None,
+        }
+    }
+}
+
+// Settings shared by the captures before and after the command
+pub struct Runner<'a> {
+    pub shell: Shell,
+    // The environment the shell starts with, the inherited one if None
+    pub base_env: Option<&'a Env<'a>>,
+    // Set on top of the base environment
+    pub extra_env: &'a [(&'a str, &'a str)],
+    // Output beyond this many bytes is an error rather than buffered without end
+    pub limit: usize,
+    // How long background jobs of the command get to finish
+    pub settle: Duration,
+    // Note where variables get assigned
+    pub trace: bool,
+    // Also capture the variables the shell doesn't export
+    pub all_vars: bool,
+    // Run the command on a pseudo-terminal relayed to the user's one
+    pub pty: bool,
+    // Start the shell as a login shell, reading ~/.bash_profile and the like
+    pub login: bool,
+    // Start the shell as an interactive one, reading ~/.bashrc
+    pub interactive: bool,
+    // More options for the shell, e.g. --norc
+    pub shell_args: &'a [String],
+    // Keep the shell from reading any startup file
+    pub no_startup_files: bool,
+    pub stderr: StderrMode,
+    // The shell and everything it started is killed after that long
+    pub timeout: Option<Duration>,
+    // Run the shell in this WSL distribution, the default one when empty
+    pub wsl: Option<&'a str>,
+    // Run the shell on this host over ssh, in the environment of a login there
+    pub ssh: Option<&'a str>,
+    // Take the state the shell dumps when the command ends it with `exit`
+    // instead of failing
+    pub keep_going: bool,
+}
+
+impl Runner<'_> {
+    // Options the shell is started with, before the helper and its arguments
+    pub fn shell_flags(&self) -> Vec<&str> {
+        let mut flags: Vec<&str> = self.shell_args.iter().map(String::as_str).collect();
+        if self.login {
+            flags.push("-l");
+        }
+        if self.interactive {
+            flags.push("-i");
+        }
+        // sh reads no file without ENV set
+        if self.no_startup_files {
+            match self.shell {
+                Shell::Bash => flags.extend(["--noprofile", "--norc"]),
+                Shell::Zsh => flags.push("-f"),
+                Shell::Sh => {}
+            }
+        }
+        flags
+    }
+}
+
+impl Capture {
+    // A non-empty `wrapper` runs the shell through that command, e.g. `op run --`
+    pub fn run(command: &str, runner: &Runner, wrapper: &[String]) -> io::Result<Capture> {
+        let (stdout, stderr) = Capture::execute(command, runner, wrapper, false)?;
+        let mut capture = Capture::parse(stdout)?;
+        capture.stderr = stderr;
+        Ok(capture)
+    }
+
+    // The state the shell starts in and the one after the command, from a
+    // single run of the shell. Only the bash helper captures both, and only
+    // unwrapped, as the wrapper's changes would be part of the baseline.
+    pub fn run_with_baseline(command: &str, runner: &Runner) -> io::Result<(Capture, Capture)> {
+        let (mut stdout, stderr) = Capture::execute(command, runner, &[], true)?;
+        let after = stdout.split_off(baseline_end(&stdout)?);
+        let mut capture = Capture::parse(after)?;
+        capture.stderr = stderr;
+        Ok((Capture::parse(stdout)?, capture))
+    }
+
+    // Output and stderr of the helper, which is asked to print the state
+    // before the command first when `baseline` is set
+    fn execute(
+        command: &str,
+        runner: &Runner,
+        wrapper: &[String],
+        baseline: bool,
+    ) -> io::Result<(Vec<u8>, Vec<u8>)> {
+        let Runner {
+            shell,
+            base_env,
+            extra_env,
+            limit,
+            settle,
+            trace,
+            all_vars,
+            pty,
+            stderr,
+            timeout,
+            keep_going,
+            ..
+        } = *runner;
+        let _span = info_span!("capture", shell = shell.program(), baseline).entered();
+        let flag = |set: bool| if set { "1" } else { "0" }.to_string();
+        // The command is passed as $1 so that none of it is expanded before the eval
+        let helper_args = [
+            command.to_string(),
+            (settle.as_millis() / 100).to_string(),
+            flag(trace),
+            flag(all_vars),
+            flag(baseline),
+            flag(keep_going),
+        ];
+        let mut shell_command = match runner.ssh {
+            Some(host) => ssh::command(host, wrapper, shell, &runner.shell_flags(), &helper_args),
+            None => {
+                let mut shell_command = match (runner.wsl, wrapper.split_first()) {
+                    (Some(distro), _) => wsl::command(distro, wrapper, shell),
+                    (None, Some((program, args))) => {
+                        let mut wrapped = Command::new(program);
+                        wrapped.args(args).arg(shell.program());
+                        wrapped
+                    }
+                    (None, None) => Command::new(shell.program()),
+                };
+                shell_command.args(runner.shell_flags());
+                match helper::install(shell).and_then(|helper| match runner.wsl {
+                    // Another file system, where the path may not lead to it
+                    Some(_) => Err(io::ErrorKind::Unsupported.into()),
+                    None => Ok(helper),
+                }) {
+                    Ok(helper) => shell_command.arg(helper),
+                    // Without a writable data directory the script is passed inline
+                    Err(_) => shell_command
+                        .arg("-c")
+                        .arg(shell.capture_script())
+                        .arg("basrs"),
+                };
+                shell_command.args(&helper_args);
+                shell_command
+            }
+        };
+        #[cfg(all(unix, feature = "daemon"))]
+        // The daemon relays the output itself
+        // Its workers don't dump the state on `exit`
+        if wrapper.is_empty()
+            && runner.wsl.is_none()
+            && runner.ssh.is_none()
+            && !keep_going
+            && !pty
+            && runner.shell_flags().is_empty()
+            && stderr == StderrMode::Inherit
+            && timeout.is_none()
+        {
+            if let Some(output) = daemon::capture(command, runner, baseline) {
+                debug!("served by the daemon");
+                return Ok((output.map_err(|e| limit_error(e, limit))?, Vec::new()));
+            }
+        }
+        // wsl.exe itself needs the Windows environment, ssh the local one
+        if let Some(base_env) = base_env.filter(|_| runner.wsl.is_none() && runner.ssh.is_none()) {
+            shell_command
+                .env_clear()
+                .envs(base_env.iter().map(|(k, v)| (os_string(k), os_string(v))));
+        }
+        shell_command
+            .envs(extra_env.iter().copied())
+            .stdin(Stdio::inherit())
+            .stdout(Stdio::piped())
+            .stderr(match stderr {
+                StderrMode::Inherit => Stdio::inherit(),
+                StderrMode::Comment | StderrMode::Report => Stdio::piped(),
+            });
+        debug!(
+            program = ?shell_command.get_program(),
+            args = ?shell_command.get_args().collect::<Vec<_>>(),
+            "starting the shell"
+        );
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        // In a group of its own the command can't read from the terminal, it
+        // would be stopped until the deadline. On a pty it leads a session.
+        #[cfg(unix)]
+        if deadline.is_some() && !pty {
+            shell_command.process_group(0);
+            if io::stdin().is_terminal() {
+                shell_command.stdin(Stdio::null());
+            }
+        }
+        let output = if pty {
+            run_on_pty(shell_command, deadline, limit)
+        } else {
+            child::run(&mut shell_command, deadline, Some(limit))
+        };
+        #[cfg(unix)]
+        signals::reraise();
+        let output = output.map_err(|e| match e.kind() {
+            // The shell itself was located beforehand
+            io::ErrorKind::NotFound if !wrapper.is_empty() && runner.ssh.is_none() => {
+                io::Error::new(e.kind(), format!("Wrapper {} not found", wrapper[0]))
+            }
+            io::ErrorKind::TimedOut => Error::TimedOut(timeout.unwrap_or_default()).into(),
+            _ => limit_error(e, limit),
+        })?;
+        debug!(
+            status = %output.status,
+            stdout_bytes = output.stdout.len(),
+            stderr_bytes = output.stderr.len(),
+            "shell finished"
+        );
+
+        // The bash helper dumps the state on `exit`, with the exit status as
+        // the command's
+        if !output.status.success() && keep_going && !output.stdout.is_empty() {
+            eprintln!(
+                "Basrs warning: {} exited with status {} before the end of the command, \
+                 applying what it changed until then",
+                shell.program(),
+                output
+                    .status
+                    .code()
+                    .map_or_else(|| "unknown".to_string(), |code| code.to_string())
+            );
+            return Ok((output.stdout, output.stderr));
+        }
+        if !output.status.success() {
+            return Err(Error::shell_failed(shell.program(), output.status, &output.stderr).into());
+        }
+        Ok((output.stdout, output.stderr))
+    }
+
+    // Records: aliases, functions, the command's status, the chain step, the
+    // running jobs, the assignment locations and the shell state, then the env
+    // dump
+    pub fn parse(output: Vec<u8>) -> io::Result<Capture> {
+        let _span = debug_span!("parse", bytes = output.len()).entered();
+        let mut records = Vec::with_capacity(SECTIONS.len());
+        let mut start = 0;
+        // A dump shorter than its length was cut off, taking it would drop
+        // the variables it misses
+        for section in &SECTIONS {
+            let record = next_record(&output, start).ok_or(Error::MissingSection(section))?;
+            start = record.end;
+            records.push(record);
+        }
+        let status = parse_number(&output[records[2].clone()]).unwrap_or_default();
+        let step = parse_number(&output[records[3].clone()]);
+        let jobs = parse_number(&output[records[4].clone()]).unwrap_or_default();
+        debug!(status, step, jobs, "parsed the records");
+        let sections = [
+            records[7].clone(),
+            trim(&output, records[0].clone()),
+            trim(&output, records[1].clone()),
+        ];
+        Ok(Capture {
+            output,
+            sections,
+            status,
+            step,
+            jobs,
+            origins: records[5].clone(),
+            state: records[6].clone(),
+            stderr: Vec::new(),
+        })
+    }
+
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+
+    // Only variables accepted by `select` are parsed
+    pub fn snapshot(&self, select: &dyn Fn(&[u8]) -> bool) -> Snapshot<'_> {
+        let [env, aliases, functions] = self.sections.clone().map(|s| &self.output[s]);
+        Snapshot {
+            env: parse_env(env, select),
+            aliases: String::from_utf8_lossy(aliases),
+            functions: String::from_utf8_lossy(functions),
+            aliases_hash: hash(aliases),
+            functions_hash: hash(functions),
+            status: self.status,
+            step: self.step,
+            jobs: self.jobs,
+            origins: parse_origins(&self.output[self.origins.clone()]),
+            state: parse_state(&self.output[self.state.clone()]),
+            stderr: String::from_utf8_lossy(&self.stderr),
+        }
+    }
+}
+
+#[cfg(all(unix, feature = "pty"))]
+fn run_on_pty(
+    mut command: Command,
+    deadline: Option<Instant>,
+    limit: usize,
+) -> io::Result<child::Finished> {
+    let pty = Pty::open()?;
+    pty.attach(&mut command)?;
+    pty.relay(move || {
+        let finished = child::run(&mut command, deadline, Some(limit));
+        drop(command);
+        finished
+    })?
+}
+
+#[cfg(not(all(unix, feature = "pty")))]
+fn run_on_pty(
+    _command: Command,
+    _deadline: Option<Instant>,
+    _limit: usize,
+) -> io::Result<child::Finished> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "--pty is not supported by this build",
+    ))
+}
+
+fn limit_error(e: io::Error, limit: usize) -> io::Error {
+    match e.kind() {
+        io::ErrorKind::OutOfMemory => Error::CaptureTooLarge(limit >> 20).into(),
+        _ => e,
+    }
+}
+
+// Where the records of the baseline printed ahead of the capture end
+fn baseline_end(output: &[u8]) -> io::Result<usize> {
+    let mut start = 0;
+    for section in &SECTIONS {
+        start = next_record(output, start)
+            .ok_or(Error::MissingSection(section))?
+            .end;
+    }
+    Ok(start)
+}
+
+// The `<length>:<bytes>` record starting at `start`
+pub fn next_record(output: &[u8], start: usize) -> Option<Range<usize>> {
+    let colon = start + memchr(b':', output.get(start..)?)?;
+    let len: usize = std::str::from_utf8(&output[start..colon])
+        .ok()?
+        .parse()
+        .ok()?;
+    let end = colon
+        .checked_add(1 + len)
+        .filter(|&end| end <= output.len())?;
+    Some(colon + 1..end)
+}
+
+// `<name> <location>` lines, a later line for the same name wins
+fn parse_origins(section: &[u8]) -> HashMap<String, String> {
+    String::from_utf8_lossy(section)
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .map(|(name, location)| (name.to_string(), location.to_string()))
+        .collect()
+}
+
+// `<key>=<value>` lines
+fn parse_state(section: &[u8]) -> HashMap<String, String> {
+    String::from_utf8_lossy(section)
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+fn parse_number<T: FromStr>(record: &[u8]) -> Option<T> {
+    std::str::from_utf8(record).ok()?.parse().ok()
+}
+
+pub fn hash(section: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    section.hash(&mut hasher);
+    hasher.finish()
+}
+
+// Strips the newlines around a section
+fn trim(output: &[u8], range: Range<usize>) -> Range<usize> {
+    let section = &output[range.clone()];
+    let start = section
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(section.len());
+    let end = section
+        .iter()
+        .rposition(|b| !b.is_ascii_whitespace())
+        .map_or(start, |end| end + 1);
+    range.start + start..range.start + end
+}
+
+// An `env -0` dump, or a plain `env` one from an env without -0
+fn parse_env<'a>(section: &'a [u8], select: &dyn Fn(&[u8]) -> bool) -> Env<'a> {
+    let terminator = if memchr(0, section).is_some() {
+        0
+    } else {
+        b'\n'
+    };
+    parse_entries(section, terminator, select)
+}
+
+// An `env` dump, or an `env -0` one with `null_data`
+pub fn parse_dump<'a>(dump: &'a [u8], null_data: bool, select: &dyn Fn(&[u8]) -> bool) -> Env<'a> {
+    parse_entries(dump, if null_data { 0 } else { b'\n' }, select)
+}
+
+fn parse_entries<'a>(section: &'a [u8], terminator: u8, select: &dyn Fn(&[u8]) -> bool) -> Env<'a> {
+    let mut env_map = HashMap::new();
+    let mut start = 0;
+    let line_ends = memchr_iter(terminator, section).chain([section.len()]);
+    for end in line_ends {
+        let line = &section[start..end];
+        start = end + 1;
+        if let Some(eq) = memchr(b'=', line).filter(|&eq| select(&line[..eq])) {
+            env_map.insert(decode(&line[..eq]), decode(&line[eq + 1..]));
+        }
+    }
+    env_map
+}
+
+// Bytes that are not valid UTF-8, as some legacy locales and binary values
+// have, are decoded to the chars U+10FF80 to U+10FFFF at the end of a private
+// use plane instead of being replaced, and written back as the same bytes
+const RAW_BYTES: u32 = 0x10FF00;
+
+// Borrowed when the bytes are valid UTF-8
+pub fn decode(bytes: &[u8]) -> Cow<'_, str> {
+    if let Ok(text) = std::str::from_utf8(bytes) {
+        return Cow::Borrowed(text);
+    }
+    let mut decoded = String::with_capacity(bytes.len() + 16);
+    for chunk in bytes.utf8_chunks() {
+        decoded.push_str(chunk.valid());
+        for &byte in chunk.invalid() {
+            decoded.extend(char::from_u32(RAW_BYTES + u32::from(byte)));
+        }
+    }
+    Cow::Owned(decoded)
+}
+
+// The byte a char of `decode` stands for
+pub fn raw_byte(c: char) -> Option<u8> {
+    (c as u32)
+        .checked_sub(RAW_BYTES)
+        .and_then(|byte| u8::try_from(byte).ok())
+        .filter(|byte| *byte >= 0x80)
+}
+
+pub fn has_raw_bytes(value: &str) -> bool {
+    value.chars().any(|c| raw_byte(c).is_some())
+}
+
+// The bytes `decode` read
+pub fn encode(value: &str) -> Cow<'_, [u8]> {
+    if !has_raw_bytes(value) {
+        return Cow::Borrowed(value.as_bytes());
+    }
+    let mut bytes = Vec::with_capacity(value.len());
+    for c in value.chars() {
+        match raw_byte(c) {
+            Some(byte) => bytes.push(byte),
+            None => bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+    Cow::Owned(bytes)
+}
+
+// A variable name or value as the bytes `decode` read, to hand back to a
+// process. Other platforms have no raw bytes to begin with.
+fn os_string(value: &str) -> OsString {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStringExt;
+        OsString::from_vec(encode(value).into_owned())
+    }
+    #[cfg(not(unix))]
+    {
+        OsString::from(value)
+    }
+}
+
+// The environment basrs runs in as an `env -0` dump, which is also what the
+// shell would start with
+pub fn process_env_dump() -> Vec<u8> {
+    let mut dump = Vec::new();
+    for (k, v) in env::vars_os() {
+        dump.extend_from_slice(k.as_encoded_bytes());
+        dump.push(b'=');
+        dump.extend_from_slice(v.as_encoded_bytes());
+        dump.push(0);
+    }
+    dump
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{baseline_end, parse_dump, Capture, Runner, StderrMode};
+    use crate::shell::{find_program, Shell};
+
+    fn record(content: &str) -> String {
+        format!("{}:{}", content.len(), content)
+    }
+
+    fn records(env: &str) -> String {
+        ["", "", "0", "", "0", "", "umask=0022", env]
+            .map(record)
+            .concat()
+    }
+
+    fn runner(keep_going: bool) -> Runner<'static> {
+        Runner {
+            shell: Shell::Bash,
+            base_env: None,
+            extra_env: &[],
+            limit: 1 << 20,
+            settle: Duration::ZERO,
+            trace: false,
+            all_vars: false,
+            pty: false,
+            login: false,
+            interactive: false,
+            shell_args: &[],
+            no_startup_files: true,
+            stderr: StderrMode::Comment,
+            timeout: None,
+            wsl: None,
+            ssh: None,
+            keep_going,
+        }
+    }
+
+    // The helper's records and env dump, as the capture stage reads them
+    #[test]
+    fn capture_parses_records() {
+        let output = [
+            "alias ll='ls -l'\n",
+            "",
+            "3",
+            "2",
+            "1",
+            "A /etc/profile:3\n",
+            "umask=0022\nOLDPWD=/",
+            "A=1\0B=x\ny\0",
+        ]
+        .map(record)
+        .concat();
+        let capture = Capture::parse(output.into_bytes()).unwrap();
+        let snapshot = capture.snapshot(&|_| true);
+        assert_eq!(
+            (snapshot.status, snapshot.step, snapshot.jobs),
+            (3, Some(2), 1)
+        );
+        assert_eq!(snapshot.env["B"], "x\ny");
+        assert_eq!(snapshot.state["umask"], "0022");
+        assert_eq!(snapshot.origins["A"], "/etc/profile:3");
+        assert_eq!(snapshot.aliases, "alias ll='ls -l'");
+        let selected = capture.snapshot(&|name| name == b"A");
+        assert_eq!(selected.env.len(), 1);
+    }
+
+    // The state before the command comes first in the same output
+    #[test]
+    fn baseline_comes_first() {
+        let output = records("A=1\0") + &records("A=2\0");
+        let end = baseline_end(output.as_bytes()).unwrap();
+        let before = Capture::parse(output.as_bytes()[..end].to_vec()).unwrap();
+        let after = Capture::parse(output.as_bytes()[end..].to_vec()).unwrap();
+        assert_eq!(before.snapshot(&|_| true).env["A"], "1");
+        assert_eq!(after.snapshot(&|_| true).env["A"], "2");
+    }
+
+    #[test]
+    fn dumps_split_on_nul_or_newline() {
+        let null = parse_dump(b"A=1\0B=a\nb=c\0", true, &|_| true);
+        assert_eq!((null["A"].as_ref(), null["B"].as_ref()), ("1", "a\nb=c"));
+        let lines = parse_dump(b"A=1\nB=2\nnot a variable\n", false, &|_| true);
+        assert_eq!(lines.len(), 2);
+    }
+
+    // An env dump shorter than its length was cut off, it would read as
+    // variables removed
+    #[test]
+    fn short_dump_is_rejected() {
+        let whole = records("A=1\0B=2\0");
+        assert!(Capture::parse(whole.clone().into_bytes()).is_ok());
+        let cut = &whole[..whole.len() - 3];
+        assert!(Capture::parse(cut.as_bytes().to_vec()).is_err());
+    }
+
+    // Background jobs don't get the descriptor the capture is written to,
+    // with or without --keep-going, so the capture doesn't wait for them
+    #[test]
+    fn background_jobs_do_not_hold_the_capture() {
+        if find_program("bash").is_none() {
+            return;
+        }
+        for keep_going in [false, true] {
+            let started = Instant::now();
+            let capture = Capture::run("sleep 3 & export A=1", &runner(keep_going), &[]).unwrap();
+            assert!(started.elapsed() < Duration::from_secs(2));
+            assert_eq!(capture.snapshot(&|_| true).env["A"], "1");
+        }
+        let capture = Capture::run("export A=1; exit 3", &runner(true), &[]).unwrap();
+        let snapshot = capture.snapshot(&|_| true);
+        assert_eq!((snapshot.status, snapshot.env["A"].as_ref()), (3, "1"));
+    }
+}
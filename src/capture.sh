@@ -0,0 +1,56 @@
+# basrs capture helper for POSIX sh, evaluates "$1" and prints the aliases, an
+# empty function section, the exit status, the step a chain of commands stopped
+# at, whether a background job is still running, an empty list of assignment
+# locations, the umask and the previous directory, and an `env -0` dump as
+# `<byte length>:<bytes>` records.
+# sh has no portable way to list functions. Its alias output differs between
+# implementations, dash and ash leave out the `alias ` bash puts in front.
+# Regenerated by basrs whenever it differs from the version basrs ships.
+
+# The records go to fd 9, a copy of stdout the command doesn't get. Everything
+# else on stdout, the command's output and whatever traps it sets print, goes
+# to stderr.
+exec 9>&1 >&2
+eval "$1" 9>&-
+__basrs_status=$?
+
+# sh cannot list jobs from a command substitution, only the last background
+# job is waited for, up to $2 tenths of a second
+__basrs_ticks=${2:-0}
+__basrs_jobs=0
+if [ -n "$!" ]; then
+    while [ "$__basrs_ticks" -gt 0 ] && kill -0 "$!" 2>/dev/null; do
+        sleep 0.1
+        __basrs_ticks=$((__basrs_ticks - 1))
+    done
+    kill -0 "$!" 2>/dev/null && __basrs_jobs=1
+fi
+
+# In the format of bash, one `alias <name>=<value>` per line
+__basrs_aliases=
+while IFS= read -r __basrs_line; do
+    case $__basrs_line in
+    '') ;;
+    alias\ *) __basrs_aliases="$__basrs_aliases$__basrs_line
+" ;;
+    *) __basrs_aliases="${__basrs_aliases}alias $__basrs_line
+" ;;
+    esac
+done <<EOF
+$(alias)
+EOF
+
+# LC_ALL=C makes ${#...} count bytes, set in a subshell as it may be exported
+(
+    LC_ALL=C
+    for __basrs_record in "$__basrs_aliases" "" "$__basrs_status" "${__basrs_step-}" "$__basrs_jobs" "" \
+        "umask=$(umask)
+OLDPWD=${OLDPWD-}"; do
+        printf '%s:%s' "${#__basrs_record}" "$__basrs_record"
+    done
+) >&9
+# Printed directly as variables cannot hold the NULs, which keep values with
+# newlines intact. An env without -0 falls back to lines.
+__basrs_size=$({ env -0 2>/dev/null || env; } | LC_ALL=C wc -c)
+printf '%s:' $((__basrs_size)) >&9
+{ env -0 2>/dev/null || env; } >&9
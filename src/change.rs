@@ -0,0 +1,303 @@
+use std::path::{Path, PathBuf};
+
+use crate::emitter::ShellEmitter;
+use crate::escape_comment;
+use crate::scope::Scope;
+
+// One entry of the generated script: what a single variable, alias, function,
+// shell state or completion change does, with an optional describing comment.
+// The diff fills in the actions, the lines are emitted from them for the
+// target shell.
+pub struct Change {
+    pub category: Category,
+    pub name: String,
+    pub comment: Option<String>,
+    pub actions: Vec<Action>,
+    pub lines: Vec<String>,
+    // New value of a variable, for output formats other than fish
+    pub value: Option<String>,
+    // The change erases the variable rather than setting it, or turns the
+    // shell option off
+    pub erases: bool,
+}
+
+// A change that was left out of the script, reported in verbose mode
+pub struct Skipped {
+    pub category: Category,
+    pub name: String,
+    pub reason: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Category {
+    Variable,
+    Alias,
+    Function,
+    // Shell state beyond variables: options, the umask, resource limits and
+    // the directory history
+    Setting,
+    // Programmable completions registered with `complete`
+    Completion,
+}
+
+impl Category {
+    pub fn name(self) -> &'static str {
+        match self {
+            Category::Variable => "variable",
+            Category::Alias => "alias",
+            Category::Function => "function",
+            Category::Setting => "setting",
+            Category::Completion => "completion",
+        }
+    }
+}
+
+// A step of a change, independent of the shell the script is for
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    SetVar {
+        name: String,
+        value: String,
+        scope: Scope,
+    },
+    // Not exported, the shell had it unexported
+    SetShellVar {
+        name: String,
+        value: String,
+        scope: Scope,
+    },
+    SetList {
+        name: String,
+        values: Vec<String>,
+        scope: Scope,
+    },
+    SetPathVar {
+        name: String,
+        entries: Vec<String>,
+        scope: Scope,
+    },
+    // Set to the output of a fish command run when the script is sourced
+    SetVarFromCommand {
+        name: String,
+        command: String,
+        scope: Scope,
+    },
+    // Without a scope erased from every scope
+    UnsetVar {
+        name: String,
+        scope: Option<Scope>,
+    },
+    // Whether the directory still exists where the command ran, None when
+    // the capture didn't say and it is looked for here
+    ChangeDir {
+        dir: String,
+        exists: Option<bool>,
+    },
+    PreviousDir(String),
+    // The stack below the current directory
+    DirStack(Vec<String>),
+    // Entries added to or removed from the user paths instead of setting PATH
+    AddUserPaths {
+        entries: Vec<String>,
+        append: bool,
+    },
+    RemoveUserPath(String),
+    DefineAlias {
+        name: String,
+        value: String,
+    },
+    DefineAbbr {
+        name: String,
+        value: String,
+    },
+    // An alias written out as a function passing its arguments on
+    DefineAliasFunction {
+        name: String,
+        value: String,
+    },
+    RemoveAlias(String),
+    RemoveAbbr(String),
+    // A function running `call` through basrs with `basrs_args`
+    DefineFunction {
+        name: String,
+        description: String,
+        basrs_args: String,
+        call: String,
+    },
+    SetOption {
+        name: String,
+        enabled: bool,
+    },
+    Umask(String),
+    Ulimit {
+        flag: String,
+        limit: String,
+    },
+    // Completions of `command` from the bash completion function `function`,
+    // defined in the file `definitions`
+    Completion {
+        command: String,
+        function: String,
+        definitions: PathBuf,
+    },
+    RemoveCompletions(String),
+    // Nothing the target can do, noted as a comment
+    Note(String),
+}
+
+fn strs(items: &[String]) -> Vec<&str> {
+    items.iter().map(String::as_str).collect()
+}
+
+impl Action {
+    pub fn emit(&self, emitter: &dyn ShellEmitter) -> Vec<String> {
+        let no_equivalent = || vec!["# fish has no equivalent".to_string()];
+        match self {
+            Action::SetVar { name, value, scope } => vec![emitter.set_var(name, value, *scope)],
+            Action::SetShellVar { name, value, scope } => {
+                vec![emitter.set_shell_var(name, value, *scope)]
+            }
+            Action::SetList {
+                name,
+                values,
+                scope,
+            } => vec![emitter.set_list(name, &strs(values), *scope)],
+            Action::SetPathVar {
+                name,
+                entries,
+                scope,
+            } => vec![emitter.set_path_var(name, &strs(entries), *scope)],
+            Action::SetVarFromCommand {
+                name,
+                command,
+                scope,
+            } => vec![emitter.set_var_from_command(name, command, *scope)],
+            Action::UnsetVar { name, scope } => vec![emitter.unset_var(name, *scope)],
+            Action::ChangeDir { dir, exists } => {
+                emitter.cd(dir, exists.unwrap_or_else(|| Path::new(dir).is_dir()))
+            }
+            Action::PreviousDir(dir) => vec![emitter.previous_dir(dir)],
+            Action::DirStack(dirs) => vec![emitter.dir_stack(&strs(dirs))],
+            Action::AddUserPaths { entries, append } => {
+                vec![emitter.add_user_paths(&strs(entries), *append)]
+            }
+            Action::RemoveUserPath(entry) => emitter.remove_user_path(entry),
+            Action::DefineAlias { name, value } => vec![emitter.alias(name, value)],
+            Action::DefineAbbr { name, value } => vec![emitter.abbr(name, value)],
+            Action::DefineAliasFunction { name, value } => emitter.alias_function(name, value),
+            Action::RemoveAlias(name) => vec![emitter.unalias(name)],
+            Action::RemoveAbbr(name) => vec![emitter.unabbr(name)],
+            Action::DefineFunction {
+                name,
+                description,
+                basrs_args,
+                call,
+            } => emitter.function(name, description, basrs_args, call),
+            Action::SetOption { name, enabled } => emitter
+                .shell_option(name, *enabled)
+                .map_or_else(no_equivalent, |line| vec![line]),
+            Action::Umask(mask) => vec![emitter.umask(mask)],
+            Action::Ulimit { flag, limit } => emitter
+                .ulimit(flag, limit)
+                .map_or_else(no_equivalent, |line| vec![line]),
+            Action::Completion {
+                command,
+                function,
+                definitions,
+            } => vec![emitter.bash_completion(command, function, definitions)],
+            Action::RemoveCompletions(command) => vec![emitter.remove_completions(command)],
+            Action::Note(note) => vec![format!("# {}", escape_comment(note))],
+        }
+    }
+}
+
+impl Change {
+    // Replaces the lines with the ones of the actions
+    pub fn emit(&mut self, emitter: &dyn ShellEmitter) {
+        self.lines = self.actions.iter().flat_map(|a| a.emit(emitter)).collect();
+    }
+
+    pub fn render(&self) -> Vec<String> {
+        let comment = self
+            .comment
+            .iter()
+            .map(|c| format!("# {}", escape_comment(c)));
+        comment.chain(self.lines.iter().cloned()).collect()
+    }
+}
+
+// Appends the lines of the changes in `category` to `script`, each ending in
+// a newline, or a blank line when there are none. Without `comments` the
+// changes come without their comments and no blank line. Written in place,
+// the script of a large environment is never held twice.
+pub fn render_into(script: &mut String, changes: &[Change], category: Category, comments: bool) {
+    let start = script.len();
+    for change in changes.iter().filter(|c| c.category == category) {
+        if let Some(comment) = change.comment.as_ref().filter(|_| comments) {
+            script.push_str("# ");
+            script.push_str(&escape_comment(comment));
+            script.push('\n');
+        }
+        for line in &change.lines {
+            script.push_str(line);
+            script.push('\n');
+        }
+    }
+    if script.len() == start && comments {
+        script.push('\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render_into, Action, Category, Change};
+    use crate::emitter::Fish;
+
+    fn change(category: Category, comment: Option<&str>, actions: Vec<Action>) -> Change {
+        Change {
+            category,
+            name: "A".to_string(),
+            comment: comment.map(str::to_string),
+            actions,
+            lines: Vec::new(),
+            value: None,
+            erases: false,
+        }
+    }
+
+    // The comment comes first, escaped to stay on one line
+    #[test]
+    fn changes_render_their_lines() {
+        let mut set = change(
+            Category::Variable,
+            Some("Adding A\nnext"),
+            vec![Action::Note("no\nbreak".to_string())],
+        );
+        set.emit(&Fish);
+        assert_eq!(set.render(), ["# Adding A\\x0anext", "# no\\x0abreak"]);
+        let mut script = String::new();
+        render_into(&mut script, &[set], Category::Variable, true);
+        assert_eq!(script, "# Adding A\\x0anext\n# no\\x0abreak\n");
+    }
+
+    // Only the category asked for, a blank line for none
+    #[test]
+    fn render_into_picks_the_category() {
+        let mut changes = vec![
+            change(
+                Category::Alias,
+                None,
+                vec![Action::RemoveAlias("ll".to_string())],
+            ),
+            change(Category::Variable, None, Vec::new()),
+        ];
+        changes.iter_mut().for_each(|c| c.emit(&Fish));
+        let mut script = String::new();
+        render_into(&mut script, &changes, Category::Alias, true);
+        render_into(&mut script, &changes, Category::Function, true);
+        assert_eq!(script, "functions -e ll\n\n");
+        script.clear();
+        render_into(&mut script, &changes, Category::Function, false);
+        assert_eq!(script, "");
+    }
+}
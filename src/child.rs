@@ -0,0 +1,144 @@
+use std::io::{self, Read};
+use std::mem;
+use std::process::{Child, Command, ExitStatus};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+#[cfg(unix)]
+use crate::signals;
+
+// How long stderr is still read once the child has exited. Background jobs
+// it started inherit the pipe and hold it open for as long as they run.
+const STDERR_DRAIN: Duration = Duration::from_millis(100);
+
+// Outcome of a child process whose pipes were drained while it ran
+pub struct Finished {
+    pub status: ExitStatus,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+// Runs a child with every piped stream drained on its own thread, so a child
+// filling one pipe never blocks on us while we wait on it or another pipe.
+// Stdout beyond `stdout_limit` bytes fails with ErrorKind::OutOfMemory.
+// Past the deadline the child's process group is killed, a child started in
+// a group of its own takes whatever it started along. Stderr is only read
+// for a moment past the child's exit.
+pub fn run(
+    command: &mut Command,
+    deadline: Option<Instant>,
+    stdout_limit: Option<usize>,
+) -> io::Result<Finished> {
+    let mut child = command.spawn()?;
+    #[cfg(unix)]
+    let _forwarding = signals::Forwarding::start(child.id());
+    let stdout = child.stdout.take().map(|pipe| drain(pipe, stdout_limit));
+    let stderr = child.stderr.take().map(drain_shared);
+    let status = wait(&mut child, deadline);
+    // Stdout closes once the child is gone, so its reader finishes either way
+    let stdout = collect(stdout)?;
+    let stderr = stderr.map_or_else(Vec::new, |(buffer, done)| {
+        let _ = done.recv_timeout(STDERR_DRAIN);
+        mem::take(&mut *buffer.lock().expect("stderr reader panicked"))
+    });
+    Ok(Finished {
+        status: status?,
+        stdout,
+        stderr,
+    })
+}
+
+fn drain<R: Read + Send + 'static>(
+    mut pipe: R,
+    limit: Option<usize>,
+) -> JoinHandle<io::Result<Vec<u8>>> {
+    thread::spawn(move || {
+        let mut buffer = Vec::new();
+        let Some(limit) = limit else {
+            pipe.read_to_end(&mut buffer)?;
+            return Ok(buffer);
+        };
+        // Reading one byte past the limit tells an oversized output apart.
+        // Returning drops the pipe, so a child still writing gets SIGPIPE.
+        pipe.by_ref()
+            .take(limit as u64 + 1)
+            .read_to_end(&mut buffer)?;
+        if buffer.len() > limit {
+            return Err(io::Error::new(
+                io::ErrorKind::OutOfMemory,
+                format!("Output exceeds {} bytes", limit),
+            ));
+        }
+        Ok(buffer)
+    })
+}
+
+// Reads the pipe into a buffer that can be taken while the reader still
+// waits for more, the receiver hears when the pipe is closed
+fn drain_shared<R: Read + Send + 'static>(mut pipe: R) -> (Arc<Mutex<Vec<u8>>>, Receiver<()>) {
+    let buffer = Arc::new(Mutex::new(Vec::new()));
+    let (done, closed) = mpsc::channel();
+    let shared = buffer.clone();
+    thread::spawn(move || {
+        let mut chunk = [0; 8192];
+        loop {
+            let n = match pipe.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            };
+            shared
+                .lock()
+                .expect("stderr reader panicked")
+                .extend_from_slice(&chunk[..n]);
+        }
+        let _ = done.send(());
+    });
+    (buffer, closed)
+}
+
+fn collect(reader: Option<JoinHandle<io::Result<Vec<u8>>>>) -> io::Result<Vec<u8>> {
+    match reader {
+        Some(reader) => reader
+            .join()
+            .map_err(|_| io::Error::other("Pipe reader panicked"))?,
+        None => Ok(Vec::new()),
+    }
+}
+
+#[cfg(unix)]
+fn kill_group(child: &Child) {
+    // SAFETY: kill has no memory effects, a child not leading a group of its
+    // own just makes it fail
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+}
+
+#[cfg(not(unix))]
+fn kill_group(_child: &Child) {}
+
+fn wait(child: &mut Child, deadline: Option<Instant>) -> io::Result<ExitStatus> {
+    let Some(deadline) = deadline else {
+        return child.wait();
+    };
+    // Poll often at first, most captures finish within milliseconds
+    let mut interval = Duration::from_millis(1);
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            kill_group(child);
+            child.kill()?;
+            child.wait()?;
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "Command timed out"));
+        }
+        thread::sleep(interval.min(deadline - now));
+        interval = (interval * 2).min(Duration::from_millis(20));
+    }
+}
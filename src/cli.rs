@@ -0,0 +1,1174 @@
+use std::env;
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+use clap::{ArgAction, ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand};
+
+use crate::capture::StderrMode;
+use crate::collision::CollisionMode;
+use crate::compare::{AliasMode, ExportedFunctionMode, LocaleMode, PathMode};
+use crate::cygpath::PathTranslate;
+#[cfg(all(unix, feature = "daemon"))]
+use crate::daemon;
+use crate::error::Error;
+use crate::filter::{Filter, Pattern, Selection};
+use crate::format::Format;
+use crate::hook;
+use crate::init;
+use crate::logging;
+use crate::pager::{self, PagerMode};
+use crate::pipeline::{self, emit, from_env, gen_script, Options};
+use crate::policy::Policy;
+use crate::preset::{self, bash_quote, Preset};
+use crate::scope::Scope;
+use crate::shell::Shell;
+use crate::ssh;
+use crate::summary::ColorMode;
+
+#[derive(Parser)]
+#[command(
+    name = "basrs",
+    version,
+    about = "Run a bash command and print a fish script applying the changes it made",
+    override_usage = "basrs [OPTIONS] <BASH_COMMAND>...\n       basrs [OPTIONS] <COMMAND>"
+)]
+struct Cli {
+    #[command(flatten)]
+    flags: Flags,
+    #[command(subcommand)]
+    subcommand: Option<Command>,
+    // Bare `basrs <bash-command>` as with bass, everything after the first
+    // word not taken as an option is part of it
+    #[arg(
+        value_name = "BASH_COMMAND",
+        help = "Bash command to run, the same as `exec`",
+        trailing_var_arg = true,
+        allow_hyphen_values = true
+    )]
+    command: Vec<String>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    #[command(about = "Run a bash command, for commands starting with a subcommand name")]
+    Exec {
+        #[arg(
+            value_name = "BASH_COMMAND",
+            required = true,
+            trailing_var_arg = true,
+            allow_hyphen_values = true
+        )]
+        command: Vec<String>,
+    },
+    #[command(about = "Source a bash script with arguments")]
+    Source {
+        file: String,
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    #[command(
+        about = "Source a bash script again whenever it changes, polled every half second, writing the script to -o"
+    )]
+    Watch {
+        file: String,
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    #[command(about = "Print the state after a bash command, or without one, as a snapshot file")]
+    Snapshot {
+        #[arg(
+            value_name = "BASH_COMMAND",
+            trailing_var_arg = true,
+            allow_hyphen_values = true
+        )]
+        command: Vec<String>,
+    },
+    #[command(
+        about = "Print the variables a bash command exports as a .env file, as --format dotenv"
+    )]
+    Env {
+        #[arg(
+            value_name = "BASH_COMMAND",
+            required = true,
+            trailing_var_arg = true,
+            allow_hyphen_values = true
+        )]
+        command: Vec<String>,
+    },
+    #[command(about = "Run a bash command on another machine over ssh and apply its changes here")]
+    Ssh {
+        #[arg(value_name = "[USER@]HOST")]
+        host: String,
+        #[arg(
+            value_name = "BASH_COMMAND",
+            required = true,
+            trailing_var_arg = true,
+            allow_hyphen_values = true
+        )]
+        command: Vec<String>,
+    },
+    #[command(about = "Compare two `env` dumps or snapshots, - reads one from stdin")]
+    Diff { before: String, after: String },
+    #[command(
+        visible_alias = "apply",
+        about = "Replay an `env` dump or snapshot against the current environment, - reads stdin"
+    )]
+    FromEnv { dump: String },
+    #[command(about = "Render a JSON diff on top of the current environment")]
+    Emit {
+        #[arg(long, value_name = "FILE", help = "The diff, - reads stdin")]
+        from_json: String,
+    },
+    #[command(about = "Shorthand for --preset module, the arguments go to `module`")]
+    Module {
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    #[cfg(all(unix, feature = "daemon"))]
+    #[command(about = "Keep a bash running to serve captures, saving the start of one per call")]
+    Daemon,
+    #[command(about = "Print the shell integration defining the `bass` function")]
+    ShellInit {
+        // Its own id, `shell` is taken by the global --shell
+        #[arg(id = "init_shell", value_name = "SHELL", default_value = "fish")]
+        shell: String,
+    },
+    #[command(about = "Print a hook applying the .basrsrc of the directory fish enters")]
+    Hook {
+        #[arg(id = "hook_shell", value_name = "SHELL", default_value = "fish")]
+        shell: String,
+    },
+    #[command(about = "Print completions of basrs itself for fish, bash or zsh")]
+    Completions {
+        #[arg(id = "completion_shell", value_name = "SHELL", value_parser = parse_completion_shell)]
+        shell: clap_complete::Shell,
+    },
+    #[command(about = "Write the `bass` function and its completions to the fish configuration")]
+    Install {
+        #[arg(long, help = "Print the function instead of writing it")]
+        print: bool,
+    },
+}
+
+// Global, so they can be given before or after the subcommand
+#[derive(clap::Args)]
+struct Flags {
+    #[arg(
+        long,
+        global = true,
+        value_name = "FILE",
+        help = "Configuration file to use"
+    )]
+    config: Option<PathBuf>,
+    #[arg(
+        long,
+        global = true,
+        value_name = "NAME",
+        help = "Apply the [profile.NAME] table of the config"
+    )]
+    profile: Option<String>,
+    #[arg(
+        long,
+        global = true,
+        value_name = "FILE",
+        help = "Output of `set --show` from the calling fish session"
+    )]
+    fish_dump: Option<PathBuf>,
+    #[arg(
+        long,
+        global = true,
+        value_name = "FILE",
+        help = "`env -0` dump of the calling fish session"
+    )]
+    inherit_fish_env: Option<String>,
+    #[arg(
+        long,
+        global = true,
+        help = "Compare variables to the --inherit-fish-env dump, not to the environment bash starts with"
+    )]
+    fish_baseline: bool,
+    #[arg(
+        long,
+        global = true,
+        help = "Run the command in an empty environment without startup files, like `env -i bash --noprofile --norc`"
+    )]
+    clean_env: bool,
+    #[arg(
+        long,
+        global = true,
+        value_name = "COMMAND",
+        help = "Run the capture through a wrapper, e.g. 'op run --'"
+    )]
+    wrap: Option<String>,
+    #[arg(long, global = true, hide = true)]
+    wrapper_protocol: Option<u32>,
+    #[arg(
+        long,
+        global = true,
+        value_name = "SECONDS",
+        value_parser = parse_duration,
+        help = "Give background jobs of the command that long to finish"
+    )]
+    wait_for_jobs: Option<Duration>,
+    #[arg(
+        long,
+        global = true,
+        value_name = "FILE",
+        help = "Also write a fish script reverting the output"
+    )]
+    undo_file: Option<PathBuf>,
+    #[arg(
+        short,
+        long,
+        global = true,
+        value_name = "FILE",
+        help = "Write the script to the file instead of stdout, replacing it whole"
+    )]
+    output: Option<PathBuf>,
+    #[arg(
+        long,
+        global = true,
+        value_name = "FILE",
+        help = "Variable policy to apply"
+    )]
+    policy: Option<PathBuf>,
+    #[arg(
+        long,
+        global = true,
+        value_name = "NAME",
+        value_parser = parse_preset,
+        help = "Canned handling for a well-known environment script"
+    )]
+    preset: Option<&'static Preset>,
+    #[arg(long, global = true, help = "Let the preset hook into the fish prompt")]
+    prompt: bool,
+    #[arg(
+        long,
+        global = true,
+        help = "Always run the command, bypassing the caches"
+    )]
+    no_cache: bool,
+    #[arg(
+        long,
+        global = true,
+        help = "With basrs source, reuse the script generated before while the sourced file's content is the same"
+    )]
+    cache_output: bool,
+    #[arg(
+        long,
+        global = true,
+        help = "Capture the state bash starts in anew, replacing the cached one"
+    )]
+    refresh_baseline: bool,
+    #[arg(long, global = true, help = "Always run the command in bash")]
+    no_fast_path: bool,
+    #[arg(
+        long,
+        global = true,
+        help = "Show the changes instead of printing the script"
+    )]
+    preview: bool,
+    #[arg(
+        long,
+        global = true,
+        help = "Print what would change as comments, without applying anything"
+    )]
+    dry_run: bool,
+    #[arg(
+        short,
+        long,
+        global = true,
+        help = "Ask about every change before including it"
+    )]
+    interactive: bool,
+    #[arg(short, long, global = true, help = "Explain why changes were left out")]
+    verbose: bool,
+    #[arg(
+        long,
+        global = true,
+        help = "Record the exit status in __basrs_last_status"
+    )]
+    status_var: bool,
+    #[arg(
+        long,
+        global = true,
+        help = "Rerun the command's directory hooks on every cd"
+    )]
+    on_cd: bool,
+    #[arg(
+        long,
+        global = true,
+        help = "Note the file and line assigning each variable"
+    )]
+    provenance: bool,
+    #[arg(
+        long,
+        global = true,
+        help = "Also apply variables the command sets without exporting them"
+    )]
+    all_vars: bool,
+    #[arg(
+        long,
+        global = true,
+        help = "Print the command run, the raw captures and timings on stderr, also with BASRS_DEBUG=1"
+    )]
+    debug: bool,
+    #[arg(
+        long,
+        global = true,
+        help = "Leave the comments describing the changes and blank lines out of the script"
+    )]
+    no_comments: bool,
+    #[arg(
+        long,
+        global = true,
+        help = "Set variables fish cannot name, such as foo.bar, as foo_bar instead of leaving them out"
+    )]
+    sanitize_names: bool,
+    #[arg(
+        long,
+        global = true,
+        help = "Also print a summary of the changes on stderr"
+    )]
+    summary: bool,
+    #[arg(
+        long,
+        global = true,
+        help = "Print how long the captures took and how many changes there are on stderr"
+    )]
+    stats: bool,
+    #[arg(
+        long,
+        global = true,
+        value_name = "WHEN",
+        value_parser = parse_color,
+        help = "When to color the summary: auto, always or never"
+    )]
+    color: Option<ColorMode>,
+    #[arg(
+        long,
+        global = true,
+        help = "Back up fish functions and aliases before redefining them"
+    )]
+    backup_functions: bool,
+    #[arg(
+        long,
+        global = true,
+        value_name = "MODE",
+        value_parser = parse_collision_mode,
+        help = "Aliases and functions named like a fish function or builtin: skip, warn or force"
+    )]
+    on_collision: Option<CollisionMode>,
+    #[arg(
+        long,
+        global = true,
+        value_name = "FILE",
+        help = "Output of `functions --all; builtin --names` to check collisions against, instead of asking fish"
+    )]
+    fish_commands: Option<String>,
+    #[arg(
+        short = 'c',
+        long = "command",
+        global = true,
+        value_name = "BASH_COMMAND",
+        action = ArgAction::Append,
+        help = "A command to run, commands run one after the other until one fails"
+    )]
+    commands: Vec<String>,
+    #[arg(
+        short = 'f',
+        long = "file",
+        global = true,
+        value_name = "FILE",
+        action = ArgAction::Append,
+        help = "A bash script to source, in turn with the -c commands"
+    )]
+    files: Vec<String>,
+    #[arg(
+        long,
+        global = true,
+        help = "Print the rules leaving variables out and exit"
+    )]
+    list_ignored: bool,
+    #[arg(
+        long = "filter",
+        global = true,
+        value_name = "GLOB",
+        action = ArgAction::Append,
+        help = "Only capture variables matching the glob"
+    )]
+    filters: Vec<String>,
+    #[arg(
+        long = "ignore",
+        global = true,
+        value_name = "PATTERN",
+        value_parser = Pattern::parse_with_regex,
+        action = ArgAction::Append,
+        help = "Leave matching variables out, a glob or a /regex/"
+    )]
+    ignored: Vec<Pattern>,
+    #[arg(
+        long,
+        global = true,
+        help = "Drop the built-in lists of ignored and read-only variables, like PS1 and SHLVL"
+    )]
+    no_default_ignores: bool,
+    #[arg(
+        long = "allow",
+        global = true,
+        value_name = "PATTERN",
+        value_parser = Pattern::parse_with_regex,
+        action = ArgAction::Append,
+        help = "Apply matching variables even if a built-in list leaves them out, a glob or a /regex/"
+    )]
+    allowed: Vec<Pattern>,
+    #[arg(
+        long = "path-var",
+        global = true,
+        value_name = "PATTERN",
+        value_parser = Pattern::parse_with_regex,
+        action = ArgAction::Append,
+        help = "Set matching variables as fish path lists split on colons, a glob or a /regex/"
+    )]
+    path_vars: Vec<Pattern>,
+    #[arg(
+        long,
+        global = true,
+        help = "Keep only the first occurrence of entries repeated in PATH and other path lists"
+    )]
+    dedupe_path: bool,
+    #[arg(
+        long,
+        global = true,
+        help = "Guard every line so sourcing the script again changes nothing, e.g. from conf.d"
+    )]
+    idempotent: bool,
+    #[arg(
+        long,
+        global = true,
+        value_name = "MODE",
+        value_parser = parse_path_translate,
+        help = "Convert Windows paths in PWD and path lists: none, auto under MSYS2 or Cygwin, or cygpath"
+    )]
+    path_translate: Option<PathTranslate>,
+    #[arg(
+        long,
+        global = true,
+        value_name = "DISTRO",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "",
+        help = "Run bash in a WSL distribution, the default one without DISTRO, with /mnt/<drive> paths written the Windows way"
+    )]
+    wsl: Option<String>,
+    #[arg(
+        long,
+        global = true,
+        help = "Leave out variables describing the machine, like HOSTNAME, DISPLAY and SSH_*, for basrs ssh"
+    )]
+    skip_host_vars: bool,
+    #[arg(
+        long,
+        global = true,
+        value_name = "PATTERN",
+        value_parser = |arg: &str| Ok::<_, String>(Selection::parse(arg)),
+        action = ArgAction::Append,
+        help = "Only apply matching variables, alias:<glob>, function:<glob>, setting:<glob> and completion:<glob> for others"
+    )]
+    only: Vec<Selection>,
+    #[arg(
+        long,
+        global = true,
+        overrides_with = "succeed_if_unchanged",
+        help = "Exit with 1 when nothing changed"
+    )]
+    fail_if_unchanged: bool,
+    #[arg(
+        long,
+        global = true,
+        overrides_with = "fail_if_unchanged",
+        help = "Exit with 0 when nothing changed, the default"
+    )]
+    succeed_if_unchanged: bool,
+    #[arg(
+        long,
+        global = true,
+        help = "Exit with 0 when the command fails, instead of with its status"
+    )]
+    exit_zero: bool,
+    #[arg(
+        long,
+        global = true,
+        help = "Apply what the command changed even when it ends bash with `exit`, warning instead of failing"
+    )]
+    keep_going: bool,
+    #[arg(
+        short = '0',
+        long = "null",
+        global = true,
+        help = "Dump entries end with NUL, as from `env -0`"
+    )]
+    null_data: bool,
+    #[arg(
+        long,
+        global = true,
+        value_name = "MODE",
+        value_parser = parse_pager,
+        help = "When to page the preview: auto, always or never"
+    )]
+    pager: Option<PagerMode>,
+    #[arg(
+        long,
+        visible_alias = "target",
+        global = true,
+        value_name = "FORMAT",
+        value_parser = parse_format,
+        help = format_help()
+    )]
+    format: Option<Format>,
+    #[arg(
+        long,
+        global = true,
+        value_name = "SHELL",
+        value_parser = parse_shell,
+        help = "Interpreter evaluating the command: bash, zsh or sh"
+    )]
+    shell: Option<Shell>,
+    #[arg(
+        long,
+        global = true,
+        value_name = "MODE",
+        value_parser = parse_path_mode,
+        help = "How a changed PATH is applied: replace, or user-paths to update fish_user_paths"
+    )]
+    path_mode: Option<PathMode>,
+    #[arg(
+        long,
+        global = true,
+        help = "Run the command on a pseudo-terminal, for commands prompting for input"
+    )]
+    pty: bool,
+    #[arg(
+        short,
+        long,
+        global = true,
+        help = "Start the shell as a login shell, reading ~/.bash_profile"
+    )]
+    login: bool,
+    #[arg(
+        long,
+        global = true,
+        help = "Start the shell as an interactive shell, reading ~/.bashrc"
+    )]
+    interactive_shell: bool,
+    #[arg(
+        long = "shell-arg",
+        visible_alias = "bash-arg",
+        global = true,
+        value_name = "ARG",
+        allow_hyphen_values = true,
+        action = ArgAction::Append,
+        help = "Start the shell with this option, e.g. --norc or --posix, can be repeated"
+    )]
+    shell_args: Vec<String>,
+    #[arg(
+        long,
+        global = true,
+        value_name = "MODE",
+        value_parser = parse_stderr,
+        help = "Where the command's output goes: inherit, comment or report"
+    )]
+    stderr: Option<StderrMode>,
+    #[arg(
+        long,
+        global = true,
+        value_name = "DURATION",
+        value_parser = parse_duration,
+        help = "Kill the command after that long, e.g. 30s or 2m"
+    )]
+    timeout: Option<Duration>,
+    #[arg(
+        long,
+        global = true,
+        value_name = "MODE",
+        value_parser = parse_alias_mode,
+        help = "What bash aliases become: alias, function, or abbr for abbreviations where possible"
+    )]
+    alias_mode: Option<AliasMode>,
+    #[arg(
+        long,
+        global = true,
+        value_name = "SCOPE",
+        value_parser = parse_scope,
+        help = "Scope of the variables set: local, global, or universal to keep them across sessions"
+    )]
+    scope: Option<Scope>,
+    #[arg(
+        long,
+        global = true,
+        value_name = "MODE",
+        value_parser = parse_exported_functions,
+        help = "What exported bash functions in the environment become: ignore, or function"
+    )]
+    exported_functions: Option<ExportedFunctionMode>,
+    #[arg(
+        long,
+        global = true,
+        value_name = "POLICY",
+        value_parser = parse_locale_policy,
+        help = "What happens to changed LANG, LANGUAGE and LC_* variables: export, ignore, or warn"
+    )]
+    locale_policy: Option<LocaleMode>,
+    #[arg(
+        long,
+        global = true,
+        value_name = "PREFIX",
+        value_parser = parse_prefix,
+        help = "Put PREFIX in front of the names of the aliases and functions defined in fish, e.g. corp_"
+    )]
+    prefix: Option<String>,
+}
+
+impl Flags {
+    // The --fish-dump file is read here, it may be a pipe that can be read
+    // only once
+    fn into_options(self) -> io::Result<Options> {
+        let fish_dump = match &self.fish_dump {
+            Some(path) => Some(fs::read_to_string(path).map_err(|e| {
+                io::Error::new(e.kind(), format!("Cannot read {}: {}", path.display(), e))
+            })?),
+            None => None,
+        };
+        Ok(Options {
+            config: self.config,
+            profile: self.profile,
+            fish_dump,
+            preset: self.preset,
+            prompt: self.prompt,
+            no_cache: self.no_cache,
+            cache_output: self.cache_output,
+            sourced: None,
+            refresh_baseline: self.refresh_baseline,
+            no_fast_path: self.no_fast_path,
+            shell: self.shell.unwrap_or_default(),
+            format: self.format.unwrap_or_default(),
+            preview: self.preview,
+            pager: self.pager.unwrap_or_default(),
+            interactive: self.interactive,
+            verbose: self.verbose,
+            status_var: self.status_var,
+            list_ignored: self.list_ignored,
+            filters: self.filters,
+            ignored: self.ignored,
+            no_default_ignores: self.no_default_ignores,
+            allowed: self.allowed,
+            path_vars: self.path_vars,
+            dedupe_path: self.dedupe_path,
+            idempotent: self.idempotent,
+            path_translate: self.path_translate.unwrap_or_default(),
+            wsl: self.wsl,
+            ssh: None,
+            skip_host_vars: self.skip_host_vars,
+            only: self.only,
+            fail_if_unchanged: self.fail_if_unchanged,
+            exit_zero: self.exit_zero,
+            keep_going: self.keep_going,
+            null_data: self.null_data,
+            policy: self.policy,
+            wrap: self
+                .wrap
+                .map(|wrapper| wrapper.split_whitespace().map(str::to_string).collect()),
+            inherit_env: self.inherit_fish_env,
+            fish_baseline: self.fish_baseline,
+            clean_env: self.clean_env,
+            on_cd: self.on_cd,
+            wrapper_protocol: self.wrapper_protocol,
+            commands: self.commands,
+            wait_for_jobs: self.wait_for_jobs.unwrap_or_default(),
+            backup_functions: self.backup_functions,
+            on_collision: self.on_collision.unwrap_or_default(),
+            fish_commands: self.fish_commands,
+            undo_file: self.undo_file,
+            output: self.output,
+            provenance: self.provenance,
+            all_vars: self.all_vars,
+            path_mode: self.path_mode.unwrap_or_default(),
+            scope: self.scope,
+            pty: self.pty,
+            login: self.login,
+            interactive_shell: self.interactive_shell,
+            shell_args: self.shell_args,
+            stderr: self.stderr.unwrap_or_default(),
+            timeout: self.timeout,
+            alias_mode: self.alias_mode,
+            exported_functions: self.exported_functions.unwrap_or_default(),
+            locale_policy: self.locale_policy,
+            prefix: self.prefix,
+            no_comments: self.no_comments,
+            sanitize_names: self.sanitize_names,
+            summary: self.summary,
+            stats: self.stats,
+            dry_run: self.dry_run,
+            color: self.color.unwrap_or_default(),
+            debug: self.debug
+                || env::var_os("BASRS_DEBUG").is_some_and(|v| !v.is_empty() && v != "0"),
+        })
+    }
+}
+
+// Seconds, or a number with an s, m or h suffix
+fn parse_duration(duration: &str) -> Result<Duration, String> {
+    let (number, unit) = match duration.strip_suffix(['s', 'm', 'h']) {
+        Some(number) => (number, &duration[number.len()..]),
+        None => (duration, "s"),
+    };
+    let seconds = match unit {
+        "m" => 60.0,
+        "h" => 3600.0,
+        _ => 1.0,
+    };
+    number
+        .parse::<f64>()
+        .ok()
+        .and_then(|number| Duration::try_from_secs_f64(number * seconds).ok())
+        .ok_or_else(|| format!("Invalid duration {}", duration))
+}
+
+fn parse_preset(name: &str) -> Result<&'static Preset, String> {
+    preset::find(name).map_err(|e| e.to_string())
+}
+
+fn parse_pager(mode: &str) -> Result<PagerMode, String> {
+    PagerMode::from_name(mode).ok_or_else(|| {
+        format!(
+            "Unknown pager mode {} (available: auto, always, never)",
+            mode
+        )
+    })
+}
+
+fn parse_color(mode: &str) -> Result<ColorMode, String> {
+    ColorMode::from_name(mode).ok_or_else(|| {
+        format!(
+            "Unknown color mode {} (available: auto, always, never)",
+            mode
+        )
+    })
+}
+
+// Lists every format, so none is left out as formats are added
+fn format_help() -> String {
+    let (last, others) = Format::NAMES.split_last().expect("formats are listed");
+    format!("What to generate: {} or {}", others.join(", "), last)
+}
+
+fn parse_format(name: &str) -> Result<Format, String> {
+    Format::from_name(name).ok_or_else(|| {
+        format!(
+            "Unknown format {} (available: {})",
+            name,
+            Format::NAMES.join(", ")
+        )
+    })
+}
+
+fn parse_shell(name: &str) -> Result<Shell, String> {
+    Shell::from_name(name)
+        .ok_or_else(|| format!("Unknown shell {} (available: bash, zsh, sh)", name))
+}
+
+fn parse_completion_shell(name: &str) -> Result<clap_complete::Shell, String> {
+    match name {
+        "fish" => Ok(clap_complete::Shell::Fish),
+        "bash" => Ok(clap_complete::Shell::Bash),
+        "zsh" => Ok(clap_complete::Shell::Zsh),
+        _ => Err(format!(
+            "Unknown shell {} (available: fish, bash, zsh)",
+            name
+        )),
+    }
+}
+
+fn parse_alias_mode(mode: &str) -> Result<AliasMode, String> {
+    AliasMode::from_name(mode).ok_or_else(|| {
+        format!(
+            "Unknown alias mode {} (available: alias, function, abbr)",
+            mode
+        )
+    })
+}
+
+fn parse_exported_functions(mode: &str) -> Result<ExportedFunctionMode, String> {
+    ExportedFunctionMode::from_name(mode).ok_or_else(|| {
+        format!(
+            "Unknown exported function mode {} (available: ignore, function)",
+            mode
+        )
+    })
+}
+
+fn parse_path_translate(mode: &str) -> Result<PathTranslate, String> {
+    PathTranslate::from_name(mode).ok_or_else(|| {
+        format!(
+            "Unknown path translation {} (available: none, auto, cygpath)",
+            mode
+        )
+    })
+}
+
+fn parse_locale_policy(policy: &str) -> Result<LocaleMode, String> {
+    LocaleMode::from_name(policy).ok_or_else(|| {
+        format!(
+            "Unknown locale policy {} (available: export, ignore, warn)",
+            policy
+        )
+    })
+}
+
+// Kept to characters needing no quotes in fish and bash
+fn parse_prefix(prefix: &str) -> Result<String, String> {
+    if prefix
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | ':'))
+    {
+        Ok(prefix.to_string())
+    } else {
+        Err(format!(
+            "Invalid prefix {}, only letters, digits, _, -, . and : are allowed",
+            prefix
+        ))
+    }
+}
+
+fn parse_scope(name: &str) -> Result<Scope, String> {
+    match name {
+        "local" => Ok(Scope::Local),
+        "global" => Ok(Scope::Global),
+        "universal" => Ok(Scope::Universal),
+        _ => Err(format!(
+            "Unknown scope {} (available: local, global, universal)",
+            name
+        )),
+    }
+}
+
+fn parse_path_mode(mode: &str) -> Result<PathMode, String> {
+    PathMode::from_name(mode).ok_or_else(|| {
+        format!(
+            "Unknown path mode {} (available: replace, user-paths)",
+            mode
+        )
+    })
+}
+
+fn parse_collision_mode(mode: &str) -> Result<CollisionMode, String> {
+    CollisionMode::from_name(mode).ok_or_else(|| {
+        format!(
+            "Unknown collision mode {} (available: skip, warn, force)",
+            mode
+        )
+    })
+}
+
+fn parse_stderr(mode: &str) -> Result<StderrMode, String> {
+    StderrMode::from_name(mode).ok_or_else(|| {
+        format!(
+            "Unknown stderr mode {} (available: inherit, comment, report)",
+            mode
+        )
+    })
+}
+
+// The basrs command line. On errors basrs has already said what went wrong,
+// the exit status comes from Error::exit_code.
+pub fn main() -> io::Result<()> {
+    logging::init();
+    let stdout = io::stdout();
+    let mut writer = io::BufWriter::new(stdout.lock());
+
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let steps = match steps(&matches) {
+        Ok(steps) => steps,
+        Err(e) => {
+            eprintln!("Basrs: {}", e);
+            return Err(e);
+        }
+    };
+    let mut options = cli
+        .flags
+        .into_options()
+        .inspect_err(|e| eprintln!("Basrs: {}", e))?;
+    options.commands = steps;
+    let mut command = cli.command;
+    if let Some(Command::ShellInit { shell }) = &cli.subcommand {
+        return match init::shell_init(shell) {
+            Ok(snippet) => writer.write_all(snippet.as_bytes()),
+            Err(e) => {
+                eprintln!("Basrs: {}", e);
+                Err(e)
+            }
+        };
+    }
+    if let Some(Command::Hook { shell }) = &cli.subcommand {
+        return match hook::dir_hook(shell) {
+            Ok(snippet) => writer.write_all(snippet.as_bytes()),
+            Err(e) => {
+                eprintln!("Basrs: {}", e);
+                Err(e)
+            }
+        };
+    }
+    if let Some(Command::Completions { shell }) = &cli.subcommand {
+        // Buffered, clap_complete panics on write errors such as a closed pipe
+        let mut completions = Vec::new();
+        clap_complete::generate(*shell, &mut Cli::command(), "basrs", &mut completions);
+        return writer.write_all(&completions);
+    }
+    if let Some(Command::Install { print }) = &cli.subcommand {
+        if *print {
+            return writer.write_all(init::fish_function_file().as_bytes());
+        }
+        return match init::install() {
+            Ok(written) => {
+                for path in written {
+                    eprintln!("Basrs: wrote {}", path.display());
+                }
+                Ok(())
+            }
+            Err(e) => {
+                eprintln!("Basrs: {}", e);
+                Err(e)
+            }
+        };
+    }
+    #[cfg(all(unix, feature = "daemon"))]
+    if let Some(Command::Daemon) = &cli.subcommand {
+        return daemon::serve().inspect_err(|e| eprintln!("Basrs: {}", e));
+    }
+    if let Some(version) = options.wrapper_protocol {
+        init::check_protocol(version);
+    }
+    if options.list_ignored {
+        let filter = pipeline::load_config(&options).and_then(|config| {
+            Policy::load(options.policy.as_deref())
+                .map(|policy| Filter::new(options.preset, &config, &policy, &options.ignored))
+        });
+        return match filter {
+            Ok(mut filter) => {
+                filter.allow(&options.allowed);
+                if options.skip_host_vars {
+                    filter.add_ignored(ssh::HOST_VARS, "--skip-host-vars");
+                }
+                writer.write_all(filter.describe().as_bytes())
+            }
+            Err(e) => {
+                eprintln!("Basrs: {}", e);
+                Err(e)
+            }
+        };
+    }
+    match &cli.subcommand {
+        Some(Command::Exec { command: words }) => command = words.clone(),
+        Some(Command::Ssh {
+            host,
+            command: words,
+        }) => {
+            options.ssh = Some(host.clone());
+            command = words.clone();
+        }
+        Some(Command::Env { command: words }) => {
+            options.format = Format::Dotenv;
+            command = words.clone();
+        }
+        Some(Command::Source { file, args } | Command::Watch { file, args }) => {
+            if options.preset.is_some() {
+                let e = io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "source cannot be combined with a preset",
+                );
+                eprintln!("Basrs: {}", e);
+                return Err(e);
+            }
+            // Said here, bash would only complain on stderr and fail with 1.
+            // A name without a slash bash looks up in PATH first, it reports
+            // that one. Watching needs the file here.
+            let watched = matches!(cli.subcommand, Some(Command::Watch { .. }));
+            let path = (watched || file.contains('/')).then(|| PathBuf::from(file));
+            if path.as_ref().is_some_and(|path| !path.is_file()) {
+                let e = io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("Cannot source {}: no such file", file),
+                );
+                eprintln!("Basrs: {}", e);
+                return Err(e);
+            }
+            if matches!(cli.subcommand, Some(Command::Source { .. })) {
+                options.sourced = path;
+            }
+            // The arguments go to the script, up to a separate `;` or the like
+            command = [format!("source {}", bash_quote(file))]
+                .into_iter()
+                .chain(args.iter().cloned())
+                .collect();
+        }
+        Some(Command::Module { args }) => {
+            let preset = preset::find("module").inspect_err(|e| eprintln!("Basrs: {}", e))?;
+            options.preset = Some(preset);
+            command = args.clone();
+        }
+        _ => {}
+    }
+    if command.is_empty()
+        && options.commands.is_empty()
+        && options.preset.is_none()
+        && cli.subcommand.is_none()
+    {
+        write!(writer, "{}", Cli::command().render_help())?;
+        return Ok(());
+    }
+
+    if let Some(Command::Watch { file, .. }) = &cli.subcommand {
+        return watch(&options, Path::new(file), &command)
+            .inspect_err(|e| eprintln!("Basrs: {}", e));
+    }
+
+    let generated = match &cli.subcommand {
+        Some(Command::FromEnv { dump }) => from_env(&options, dump),
+        Some(Command::Diff { before, after }) => pipeline::diff(&options, before, after),
+        Some(Command::Emit { from_json }) => emit(&options, from_json),
+        Some(Command::Snapshot { command }) => pipeline::snapshot(&options, command),
+        _ => gen_script(&options, &command),
+    };
+    let generated = match generated {
+        Ok(generated) => generated,
+        Err(e) => {
+            match e.kind() {
+                _ if Error::of(&e).is_some() => eprintln!("Basrs: {}", e),
+                io::ErrorKind::NotFound
+                | io::ErrorKind::InvalidInput
+                | io::ErrorKind::OutOfMemory
+                | io::ErrorKind::TimedOut => eprintln!("Basrs: {}", e),
+                _ => eprintln!("Basrs internal error: {}", e),
+            }
+            return Err(e);
+        }
+    };
+    if options.preview {
+        pager::show(&generated.script, options.pager)?;
+    } else if let Some(path) = &options.output {
+        if let Err(e) = write_output(path, &generated.script) {
+            eprintln!("Basrs: {}", e);
+            return Err(e);
+        }
+    } else {
+        // Someone ran basrs by hand and is about to look at raw fish code
+        let snapshot = matches!(cli.subcommand, Some(Command::Snapshot { .. }));
+        if io::stdout().is_terminal()
+            && options.format == Format::Fish
+            && !snapshot
+            && !options.dry_run
+        {
+            let args: Vec<String> = env::args().skip(1).collect();
+            eprintln!(
+                "Basrs hint: the output below is a fish script, it only takes effect \
+                 when sourced, e.g. `basrs {} | source`",
+                args.join(" ")
+            );
+        }
+        writer.write_all(generated.script.as_bytes())?;
+    }
+    // The script is out either way, it ends with `return <status>` too
+    if generated.status != 0 && !options.exit_zero {
+        writer.flush()?;
+        process::exit(generated.status);
+    }
+    if generated.unchanged && options.fail_if_unchanged {
+        writer.flush()?;
+        process::exit(1);
+    }
+    Ok(())
+}
+
+// The -c commands and the -f files, as `source <file>`, in the order they
+// were given
+fn steps(matches: &ArgMatches) -> io::Result<Vec<String>> {
+    let given = |id: &str| {
+        matches
+            .indices_of(id)
+            .into_iter()
+            .flatten()
+            .zip(matches.get_many::<String>(id).into_iter().flatten())
+    };
+    let mut steps: Vec<(usize, String)> = given("commands")
+        .map(|(index, command)| (index, command.clone()))
+        .collect();
+    for (index, file) in given("files") {
+        // Without a slash bash looks the name up in PATH, as for `source`
+        if file.contains('/') && !Path::new(file).is_file() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Cannot source {}: no such file", file),
+            ));
+        }
+        steps.push((index, format!("source {}", bash_quote(file))));
+    }
+    steps.sort_by_key(|(index, _)| *index);
+    Ok(steps.into_iter().map(|(_, step)| step).collect())
+}
+
+// How often `basrs watch` looks at the script
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+// Regenerates the output whenever the modification time or the size of `file`
+// changes, which also catches editors replacing the file. Failing runs are
+// reported and the previous output is kept.
+fn watch(options: &Options, file: &Path, command: &[String]) -> io::Result<()> {
+    let Some(output) = &options.output else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "watch needs -o with the file to write",
+        ));
+    };
+    let stamp = || {
+        fs::metadata(file)
+            .ok()
+            .map(|metadata| (metadata.modified().ok(), metadata.len()))
+    };
+    let mut last = None;
+    loop {
+        let current = stamp();
+        if current.is_some() && current != last {
+            last = current;
+            match gen_script(options, command).and_then(|g| write_output(output, &g.script)) {
+                Ok(()) => eprintln!("Basrs: wrote {}", output.display()),
+                Err(e) => eprintln!("Basrs: {}", e),
+            }
+        }
+        thread::sleep(WATCH_INTERVAL);
+    }
+}
+
+// Written aside and renamed, a failed run or a fish starting meanwhile never
+// sees half a script
+fn write_output(path: &Path, script: &str) -> io::Result<()> {
+    let write = || {
+        let name = path.file_name().ok_or(io::ErrorKind::InvalidInput)?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        if !dir.as_os_str().is_empty() {
+            fs::create_dir_all(dir)?;
+        }
+        let partial = dir.join(format!(".{}.{}", name.to_string_lossy(), process::id()));
+        fs::write(&partial, script)?;
+        fs::rename(&partial, path).inspect_err(|_| {
+            let _ = fs::remove_file(&partial);
+        })
+    };
+    write().map_err(|e: io::Error| {
+        io::Error::new(e.kind(), format!("Cannot write {}: {}", path.display(), e))
+    })
+}
@@ -0,0 +1,90 @@
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::io;
+#[cfg(unix)]
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use crate::change::{Category, Change};
+use crate::child;
+use crate::shell::find_program;
+
+// Set for the fish asked for its commands, so that a basrs run from its
+// config doesn't ask again
+const QUERYING: &str = "__BASRS_COLLISION_QUERY";
+
+// fish starting slower than this is not waited for
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+// What happens to an alias or function named like a fish function or builtin
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CollisionMode {
+    // Left out, the definition commented out in the script
+    Skip,
+    // Defined, with a warning on stderr
+    #[default]
+    Warn,
+    // Defined without asking fish at all
+    Force,
+}
+
+impl CollisionMode {
+    pub fn from_name(name: &str) -> Option<CollisionMode> {
+        match name {
+            "skip" => Some(CollisionMode::Skip),
+            "warn" => Some(CollisionMode::Warn),
+            "force" => Some(CollisionMode::Force),
+            _ => None,
+        }
+    }
+}
+
+// Whether the change defines a fish function, as aliases and bash functions do
+pub fn defines_function(change: &Change) -> bool {
+    matches!(change.category, Category::Alias | Category::Function) && !change.erases
+}
+
+// The fish functions and builtins by name, read from `file` as
+// `functions --all; builtin --names` prints them, or else asked of fish. The
+// names may be separated by newlines or, as `functions` prints them to a
+// terminal, by commas. None when fish can't be asked.
+pub fn fish_commands(file: Option<&str>) -> io::Result<Option<HashSet<String>>> {
+    let listed = match file {
+        Some(file) => fs::read(file)
+            .map_err(|e| io::Error::new(e.kind(), format!("Cannot read {}: {}", file, e)))?,
+        None => match query_fish() {
+            Some(listed) => listed,
+            None => return Ok(None),
+        },
+    };
+    Ok(Some(
+        String::from_utf8_lossy(&listed)
+            .split(['\n', ','])
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(str::to_string)
+            .collect(),
+    ))
+}
+
+// A fish started like the user's, config included
+fn query_fish() -> Option<Vec<u8>> {
+    // Already checked by the basrs that started this fish
+    if env::var_os(QUERYING).is_some() {
+        return Some(Vec::new());
+    }
+    let mut command = Command::new(find_program("fish")?);
+    command
+        .arg("-c")
+        .arg("functions --all; builtin --names")
+        .env(QUERYING, "1")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null());
+    #[cfg(unix)]
+    command.process_group(0);
+    let output = child::run(&mut command, Some(Instant::now() + QUERY_TIMEOUT), None).ok()?;
+    output.status.success().then_some(output.stdout)
+}
@@ -0,0 +1,1236 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::iter::Peekable;
+use std::str::CharIndices;
+use std::thread;
+
+use serde::Deserialize;
+
+use crate::capture::Env;
+use crate::change::{Action, Category, Change, Skipped};
+use crate::config::Config;
+use crate::escape_comment;
+use crate::filter::{is_fish_name, sanitize_name, Filter};
+use crate::policy::Policy;
+use crate::preset::Preset;
+use crate::proxy::Definitions;
+use crate::scope::ScopeTracker;
+use crate::shell::Shell;
+use crate::wsl;
+
+// Aliases in the output of bash's `alias`, the same as `alias -p`, or zsh's
+// `alias -rL`: `alias <name>=<value>` with both shell words. bash single
+// quotes every value, ending the quotes for a quote in it as `'\''`, zsh
+// quotes only where needed and may use `$'...'`. A quoted value can span
+// lines.
+pub fn parse_aliases(alias_output: &str) -> Vec<(Cow<'_, str>, Cow<'_, str>)> {
+    let mut aliases = Vec::new();
+    let mut rest = alias_output.trim_start();
+    while !rest.is_empty() {
+        let Some(definition) = rest.strip_prefix("alias ") else {
+            // Not a definition, skipped up to the next line
+            rest = rest
+                .split_once('\n')
+                .map_or("", |(_, next)| next)
+                .trim_start();
+            continue;
+        };
+        let definition = definition.trim_start();
+        let definition = definition.strip_prefix("-- ").unwrap_or(definition);
+        let Some((name, used)) = shell_word(definition, |c| c == '=') else {
+            break;
+        };
+        let Some(value_start) = definition[used..].strip_prefix('=') else {
+            break;
+        };
+        let Some((value, used)) = shell_word(value_start, char::is_whitespace) else {
+            break;
+        };
+        if !name.is_empty() {
+            aliases.push((name, value));
+        }
+        rest = value_start[used..].trim_start();
+    }
+    aliases
+}
+
+// The shell word at the start of `input`, ending at the first unquoted
+// character `ends` accepts, and how many bytes it took. None when a quote is
+// left open.
+fn shell_word(input: &str, ends: impl Fn(char) -> bool) -> Option<(Cow<'_, str>, usize)> {
+    let end = |rest: &str| rest.chars().next().is_none_or(&ends);
+    // What bash prints: one quoted span, or a word without quoting
+    if let Some(quoted) = input.strip_prefix('\'') {
+        let close = quoted.find('\'')?;
+        if end(&quoted[close + 1..]) {
+            return Some((Cow::Borrowed(&quoted[..close]), close + 2));
+        }
+    }
+    let plain = input.find(|c: char| ends(c)).unwrap_or(input.len());
+    if !input[..plain].contains(['\'', '"', '\\', '$']) {
+        return Some((Cow::Borrowed(&input[..plain]), plain));
+    }
+
+    let mut word = String::new();
+    let mut chars = input.char_indices().peekable();
+    while let Some(&(i, c)) = chars.peek() {
+        if ends(c) {
+            return Some((Cow::Owned(word), i));
+        }
+        chars.next();
+        match c {
+            '\'' => loop {
+                match chars.next()?.1 {
+                    '\'' => break,
+                    c => word.push(c),
+                }
+            },
+            '"' => loop {
+                match chars.next()?.1 {
+                    '"' => break,
+                    '\\' => match chars.next()?.1 {
+                        c @ ('$' | '`' | '"' | '\\') => word.push(c),
+                        '\n' => {}
+                        c => {
+                            word.push('\\');
+                            word.push(c);
+                        }
+                    },
+                    c => word.push(c),
+                }
+            },
+            '\\' => match chars.next() {
+                Some((_, '\n')) => {}
+                Some((_, c)) => word.push(c),
+                None => word.push('\\'),
+            },
+            '$' if chars.peek().is_some_and(|&(_, c)| c == '\'') => {
+                chars.next();
+                ansi_c_quoted(&mut chars, &mut word)?;
+            }
+            c => word.push(c),
+        }
+    }
+    Some((Cow::Owned(word), input.len()))
+}
+
+// The rest of a `$'...'` span, its escapes resolved
+fn ansi_c_quoted(chars: &mut Peekable<CharIndices>, word: &mut String) -> Option<()> {
+    let digit = |chars: &mut Peekable<CharIndices>, radix| {
+        let digit = chars.peek()?.1.to_digit(radix)?;
+        chars.next();
+        Some(digit)
+    };
+    loop {
+        let c = match chars.next()?.1 {
+            '\'' => return Some(()),
+            '\\' => match chars.next()?.1 {
+                'a' => '\x07',
+                'b' => '\x08',
+                'e' | 'E' => '\x1b',
+                'f' => '\x0c',
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                'v' => '\x0b',
+                'x' => {
+                    let high = digit(chars, 16)?;
+                    let code = digit(chars, 16).map_or(high, |low| high * 16 + low);
+                    char::from_u32(code)?
+                }
+                first @ '0'..='7' => {
+                    let mut code = first.to_digit(8)?;
+                    for _ in 0..2 {
+                        match digit(chars, 8) {
+                            Some(next) => code = code * 8 + next,
+                            None => break,
+                        }
+                    }
+                    char::from_u32(code)?
+                }
+                c => c,
+            },
+            c => c,
+        };
+        word.push(c);
+    }
+}
+
+// What bash aliases become in fish
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AliasMode {
+    // Fish aliases, defined with `alias`
+    #[default]
+    Alias,
+    // Fish functions passing their arguments on, written out
+    Function,
+    // Abbreviations expanding on the command line, for aliases that only
+    // substitute words. Others become functions.
+    Abbr,
+}
+
+impl AliasMode {
+    pub fn from_name(name: &str) -> Option<AliasMode> {
+        match name {
+            "alias" => Some(AliasMode::Alias),
+            "function" => Some(AliasMode::Function),
+            "abbr" => Some(AliasMode::Abbr),
+            _ => None,
+        }
+    }
+
+    // Whether the alias with this value becomes an abbreviation
+    fn abbreviates(self, value: &str) -> bool {
+        self == AliasMode::Abbr && is_plain_command(value)
+    }
+
+    fn define(self, name: &str, value: &str) -> Action {
+        let name = name.to_string();
+        match self {
+            AliasMode::Alias => Action::DefineAlias {
+                name,
+                value: value.to_string(),
+            },
+            _ if self.abbreviates(value) => Action::DefineAbbr {
+                name,
+                value: value.trim().to_string(),
+            },
+            AliasMode::Function | AliasMode::Abbr => Action::DefineAliasFunction {
+                name,
+                value: value.to_string(),
+            },
+        }
+    }
+
+    fn erase(self, name: &str, value: &str) -> Action {
+        match self.abbreviates(value) {
+            true => Action::RemoveAbbr(name.to_string()),
+            false => Action::RemoveAlias(name.to_string()),
+        }
+    }
+}
+
+// An alias body that is a command with plain words, no operators, expansions
+// or quoting, which reads the same in every shell
+pub fn is_plain_command(value: &str) -> bool {
+    !value.trim().is_empty()
+        && value.chars().all(|c| {
+            c.is_ascii_alphanumeric()
+                || matches!(
+                    c,
+                    ' ' | '-' | '_' | '.' | '/' | ':' | '=' | '@' | '%' | '+' | ','
+                )
+        })
+}
+
+// Only aliases the command added, redefined or removed, fish keeps its own
+// aliases otherwise. In fish their names start with `prefix`.
+pub fn process_alias_changes(
+    old_alias_str: &str,
+    new_alias_str: &str,
+    mode: AliasMode,
+    prefix: &str,
+) -> Vec<Change> {
+    let old_aliases = parse_aliases(old_alias_str);
+    let new_aliases = parse_aliases(new_alias_str);
+    let old_value = |name: &str| {
+        old_aliases
+            .iter()
+            .find(|(old, _)| old == name)
+            .map(|(_, value)| value.as_ref())
+    };
+    let mut changes = Vec::new();
+    for (name, value) in &new_aliases {
+        let (name, value) = (name.as_ref(), value.as_ref());
+        let fish_name = format!("{}{}", prefix, name);
+        let mut actions = Vec::new();
+        let comment = match old_value(name) {
+            Some(old) if old == value => continue,
+            Some(old) => {
+                // An abbreviation would still expand over a function
+                if mode.abbreviates(old) != mode.abbreviates(value) {
+                    actions.push(mode.erase(&fish_name, old));
+                }
+                format!("Updating alias {}", fish_name)
+            }
+            None => format!("Adding alias {}", fish_name),
+        };
+        actions.push(mode.define(&fish_name, value));
+        changes.push(Change {
+            category: Category::Alias,
+            name: fish_name,
+            comment: Some(comment),
+            actions,
+            lines: Vec::new(),
+            value: None,
+            erases: false,
+        });
+    }
+    for (name, value) in &old_aliases {
+        if !new_aliases.iter().any(|(new, _)| new == name) {
+            let fish_name = format!("{}{}", prefix, name);
+            changes.push(Change {
+                category: Category::Alias,
+                comment: Some(format!("Removing alias {}", fish_name)),
+                actions: vec![mode.erase(&fish_name, value)],
+                lines: Vec::new(),
+                name: fish_name,
+                value: None,
+                erases: true,
+            });
+        }
+    }
+    changes
+}
+
+// What exported bash functions in the environment, `BASH_FUNC_<name>%%`
+// variables, become in fish. fish cannot hold variables of that name, so they
+// are never passed on as they are.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExportedFunctionMode {
+    // Left out like other variables fish cannot set
+    #[default]
+    Ignore,
+    // Fish functions calling back into bash, like functions the command defines
+    Function,
+}
+
+impl ExportedFunctionMode {
+    pub fn from_name(name: &str) -> Option<ExportedFunctionMode> {
+        match name {
+            "ignore" => Some(ExportedFunctionMode::Ignore),
+            "function" => Some(ExportedFunctionMode::Function),
+            _ => None,
+        }
+    }
+}
+
+// How a changed PATH is applied
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PathMode {
+    // PATH is set to the new value as a whole
+    #[default]
+    Replace,
+    // Added and removed entries go into fish_user_paths, which fish keeps
+    // across sessions and puts in front of PATH
+    UserPaths,
+}
+
+impl PathMode {
+    pub fn from_name(name: &str) -> Option<PathMode> {
+        match name {
+            "replace" => Some(PathMode::Replace),
+            "user-paths" => Some(PathMode::UserPaths),
+            _ => None,
+        }
+    }
+}
+
+// What happens to locale variables, LANG, LANGUAGE and LC_*, the command
+// changes
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LocaleMode {
+    // Passed on like other variables
+    #[default]
+    Export,
+    // Left as they are in fish
+    Ignore,
+    // Passed on with a warning on stderr
+    Warn,
+}
+
+impl LocaleMode {
+    pub fn from_name(name: &str) -> Option<LocaleMode> {
+        match name {
+            "export" => Some(LocaleMode::Export),
+            "ignore" => Some(LocaleMode::Ignore),
+            "warn" => Some(LocaleMode::Warn),
+            _ => None,
+        }
+    }
+}
+
+pub fn is_locale_var(name: &str) -> bool {
+    name == "LANG" || name == "LANGUAGE" || name.starts_with("LC_")
+}
+
+// Entries of a colon-separated list added in front of the kept ones, added
+// after them, and removed
+pub fn path_edits<'a>(old: &'a str, new: &'a str) -> (Vec<&'a str>, Vec<&'a str>, Vec<&'a str>) {
+    let old: Vec<&str> = old.split(':').filter(|e| !e.is_empty()).collect();
+    let new: Vec<&str> = new.split(':').filter(|e| !e.is_empty()).collect();
+    let last_kept = new.iter().rposition(|entry| old.contains(entry));
+    let (mut prepended, mut appended) = (Vec::new(), Vec::new());
+    for (i, &entry) in new.iter().enumerate() {
+        if old.contains(&entry) || prepended.contains(&entry) || appended.contains(&entry) {
+            continue;
+        }
+        match last_kept {
+            Some(last) if i < last => prepended.push(entry),
+            _ => appended.push(entry),
+        }
+    }
+    let mut removed = Vec::new();
+    for &entry in &old {
+        if !new.contains(&entry) && !removed.contains(&entry) {
+            removed.push(entry);
+        }
+    }
+    (prepended, appended, removed)
+}
+
+// Environments with more variables than this are diffed on several threads
+const PARALLEL_DIFF_THRESHOLD: usize = 2048;
+
+pub fn process_env_changes(
+    old_env: &Env,
+    new_env: &Env,
+    filter: &Filter,
+    config: &Config,
+    policy: &Policy,
+    scopes: &ScopeTracker,
+) -> (Vec<Change>, Vec<Skipped>) {
+    // Only the changed ones, most of a large environment usually is not.
+    // Sorted by name for output that is the same on every run, with the
+    // directory change first.
+    let mut entries: Vec<_> = new_env
+        .iter()
+        .filter(|(name, value)| old_env.get(*name) != Some(*value))
+        .collect();
+    entries.sort_unstable_by_key(|(name, _)| (*name != "PWD", *name));
+    let threads = if entries.len() > PARALLEL_DIFF_THRESHOLD {
+        thread::available_parallelism().map_or(1, |n| n.get())
+    } else {
+        1
+    };
+    let chunk_size = entries.len().div_ceil(threads).max(1);
+    let (results, (mut removed, removed_skipped)) = if threads == 1 {
+        let results = vec![diff_variables(&entries, old_env, filter, config, policy)];
+        (results, removed_variables(old_env, new_env, filter, scopes))
+    } else {
+        thread::scope(|s| {
+            let removed = s.spawn(|| removed_variables(old_env, new_env, filter, scopes));
+            let workers: Vec<_> = entries
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    s.spawn(move || diff_variables(chunk, old_env, filter, config, policy))
+                })
+                .collect();
+            let results = workers
+                .into_iter()
+                .map(|worker| worker.join().expect("diff worker panicked"))
+                .collect();
+            (results, removed.join().expect("diff worker panicked"))
+        })
+    };
+    let mut changes = Vec::new();
+    let mut skipped = removed_skipped;
+    for (chunk_changes, chunk_skipped) in results {
+        changes.extend(chunk_changes);
+        skipped.extend(chunk_skipped);
+    }
+    removed.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+    skipped.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+    changes.extend(removed);
+    (changes, skipped)
+}
+
+// Finds removed environment variables, the ones the filter leaves out are
+// skipped as they would be when set
+pub fn removed_variables(
+    old_env: &Env,
+    new_env: &Env,
+    filter: &Filter,
+    scopes: &ScopeTracker,
+) -> (Vec<Change>, Vec<Skipped>) {
+    let mut changes = Vec::new();
+    let mut skipped = Vec::new();
+    for k in old_env.keys().filter(|k| !new_env.contains_key(*k)) {
+        if let Some(reason) = filter.skip_reason(k) {
+            skipped.push(Skipped {
+                category: Category::Variable,
+                name: k.to_string(),
+                reason,
+            });
+            continue;
+        }
+        // Names fish rejects only get here with sanitize_names set
+        let (name, comment) = if is_fish_name(k) {
+            (k.to_string(), format!("Removing {}", k))
+        } else {
+            let sanitized = sanitize_name(k);
+            let comment = format!("Removing {} as {}", escape_comment(k), sanitized);
+            (sanitized, comment)
+        };
+        changes.push(Change {
+            category: Category::Variable,
+            comment: Some(comment),
+            actions: scopes
+                .erase_scopes(&name)
+                .into_iter()
+                .map(|scope| Action::UnsetVar {
+                    name: name.clone(),
+                    scope,
+                })
+                .collect(),
+            name,
+            lines: Vec::new(),
+            value: None,
+            erases: true,
+        });
+    }
+    (changes, skipped)
+}
+
+// Finds added or modified environment variables among `entries` of the new environment
+pub fn diff_variables(
+    entries: &[(&Cow<str>, &Cow<str>)],
+    old_env: &Env,
+    filter: &Filter,
+    config: &Config,
+    policy: &Policy,
+) -> (Vec<Change>, Vec<Skipped>) {
+    let mut changes = Vec::new();
+    let mut skipped = Vec::new();
+    for &(k, v) in entries {
+        let comment = match old_env.get(k) {
+            None => format!("Adding {}", escape_comment(k)),
+            Some(old_value) if old_value != v => format!(
+                "Updating {}: '{}' -> '{}'",
+                escape_comment(k),
+                escape_comment(old_value),
+                escape_comment(v)
+            ),
+            _ => continue,
+        };
+        if let Some(reason) = filter.skip_reason(k) {
+            skipped.push(Skipped {
+                category: Category::Variable,
+                name: k.to_string(),
+                reason,
+            });
+            continue;
+        }
+        let treatment = policy.treatment(k);
+        let v = match treatment.and_then(|t| t.transform.as_ref()) {
+            Some(transform) => Cow::Owned(v.replace(&transform.replace, &transform.with)),
+            None => Cow::Borrowed(v.as_ref()),
+        };
+        let deferred = treatment
+            .and_then(|t| t.deferred.as_ref())
+            .or_else(|| config.deferred.get(k.as_ref()));
+        let scope = policy.scope(k);
+        // Names fish rejects only get here with sanitize_names set
+        let (k, comment) = if is_fish_name(k) {
+            (Cow::Borrowed(k.as_ref()), comment)
+        } else {
+            let sanitized = sanitize_name(k);
+            let comment = format!("{} as {}", comment, sanitized);
+            (Cow::Owned(sanitized), comment)
+        };
+        let k = k.as_ref();
+        let v = match policy.windows_paths && !policy.path_var(k) {
+            true => Cow::Owned(wsl::windows_path(&v).into_owned()),
+            false => v,
+        };
+        let action = if k == "PWD" {
+            Action::ChangeDir {
+                dir: v.to_string(),
+                exists: policy.dir_exists,
+            }
+        } else if deferred.is_some() && policy.skip_deferred {
+            Action::Note(format!(
+                "Skipping {}, its value comes from a fish command",
+                k
+            ))
+        } else if let Some(fish_command) = deferred {
+            // Evaluated by fish when the script is sourced
+            Action::SetVarFromCommand {
+                name: k.to_string(),
+                command: fish_command.clone(),
+                scope,
+            }
+        } else if policy.path_var(k) {
+            let mut entries: Vec<Cow<str>> = v.split(':').map(Cow::Borrowed).collect();
+            if policy.windows_paths {
+                entries = entries
+                    .iter()
+                    .map(|e| wsl::windows_path(e).into_owned().into())
+                    .collect();
+            }
+            if policy.dedupe_paths {
+                let mut seen = HashSet::new();
+                entries.retain(|entry| seen.insert(entry.clone()));
+            }
+            Action::SetPathVar {
+                name: k.to_string(),
+                entries: entries.into_iter().map(Cow::into_owned).collect(),
+                scope,
+            }
+        } else {
+            Action::SetVar {
+                name: k.to_string(),
+                value: v.to_string(),
+                scope,
+            }
+        };
+        changes.push(Change {
+            category: Category::Variable,
+            name: k.to_string(),
+            comment: Some(comment),
+            actions: vec![action],
+            lines: Vec::new(),
+            value: Some(v.to_string()),
+            erases: false,
+        });
+    }
+    (changes, skipped)
+}
+
+// Function names with their `declare -f` definitions. A listing of just the
+// names as `declare -f <name>` lines, as `declare -F` prints it, gives no
+// definitions.
+pub fn parse_funcs(func_str: &str) -> Vec<(&str, Option<&str>)> {
+    let lines: Vec<&str> = func_str.split_inclusive('\n').collect();
+    // A definition starts with `<name> () ` followed by `{ `, lines inside the
+    // body are indented
+    let header = |i: usize| {
+        let name = lines[i].trim_end_matches('\n').strip_suffix(" () ")?;
+        lines
+            .get(i + 1)
+            .filter(|next| next.trim_end_matches('\n') == "{ ")
+            .map(|_| name)
+    };
+    let starts: Vec<(usize, &str)> = (0..lines.len())
+        .filter_map(|i| header(i).map(|name| (i, name)))
+        .collect();
+    if starts.is_empty() {
+        return func_str
+            .lines()
+            .filter_map(|line| line.split_whitespace().last())
+            .map(|name| (name, None))
+            .collect();
+    }
+    let offsets: Vec<usize> = lines
+        .iter()
+        .scan(0, |offset, line| {
+            let start = *offset;
+            *offset += line.len();
+            Some(start)
+        })
+        .collect();
+    starts
+        .iter()
+        .enumerate()
+        .map(|(n, &(i, name))| {
+            let end = starts
+                .get(n + 1)
+                .map_or(func_str.len(), |&(next, _)| offsets[next]);
+            (name, Some(&func_str[offsets[i]..end]))
+        })
+        .collect()
+}
+
+// Added and redefined functions become fish functions calling back into bash,
+// with every function the command defined loaded from `definitions`. Removed
+// ones are only reported, a fish function of the same name may predate the
+// bash one. Redefinitions are only seen when both listings have definitions.
+pub fn process_func_changes(
+    old_func_str: &str,
+    new_func_str: &str,
+    preset: Option<&Preset>,
+    shell: Shell,
+    prefix: &str,
+    skipped: &mut Vec<Skipped>,
+) -> (Vec<Change>, Option<Definitions>) {
+    let old_funcs = parse_funcs(old_func_str);
+    let new_funcs = parse_funcs(new_func_str);
+    let old_names: Vec<&str> = old_funcs.iter().map(|(name, _)| *name).collect();
+    let new_names: Vec<&str> = new_funcs.iter().map(|(name, _)| *name).collect();
+    let added: Vec<_> = new_funcs
+        .iter()
+        .filter(|(name, _)| !old_names.contains(name))
+        .collect();
+    // E.g. a lazy-loading stub replaced by the real function on first use
+    let changed: Vec<_> = new_funcs
+        .iter()
+        .filter(|(name, definition)| {
+            definition.is_some()
+                && old_funcs
+                    .iter()
+                    .any(|(old, old_definition)| old == name && old_definition != definition)
+        })
+        .collect();
+    // Hidden functions are still loaded, the visible ones call them
+    let definitions = added
+        .iter()
+        .chain(&changed)
+        .map(|(_, definition)| *definition)
+        .collect::<Option<String>>()
+        .filter(|definitions| !definitions.is_empty())
+        .and_then(|definitions| Definitions::new(definitions, shell));
+    let hidden = |func: &str| preset.is_some_and(|p| p.hides_function(func));
+    let mut changes = Vec::new();
+    let function_change = |func: &str, comment: String, actions: Vec<Action>| Change {
+        category: Category::Function,
+        name: format!("{}{}", prefix, func),
+        comment: Some(comment),
+        actions,
+        lines: Vec::new(),
+        value: None,
+        erases: false,
+    };
+
+    // Find added and redefined functions
+    let updates = added
+        .into_iter()
+        .map(|(func, _)| (func, "Adding"))
+        .chain(changed.into_iter().map(|(func, _)| (func, "Updating")));
+    for (func, verb) in updates {
+        if hidden(func) {
+            skipped.push(Skipped {
+                category: Category::Function,
+                name: func.to_string(),
+                reason: format!("internal function of the {} preset", preset.unwrap().name),
+            });
+            continue;
+        }
+        let actions = definitions
+            .iter()
+            .map(|definitions| definitions.proxy(func, prefix))
+            .collect();
+        changes.push(function_change(
+            func,
+            format!("{} function {}{}", verb, prefix, func),
+            actions,
+        ));
+    }
+
+    // Find removed functions
+    for func in old_names.iter() {
+        if !hidden(func) && !new_names.contains(func) {
+            changes.push(function_change(
+                func,
+                format!("Removing function {}{}", prefix, func),
+                Vec::new(),
+            ));
+        }
+    }
+
+    (changes, definitions)
+}
+
+// Name of the function an exported function variable holds, for bash's
+// `BASH_FUNC_<name>%%` and the older `BASH_FUNC_<name>()`
+fn exported_function_name(variable: &str) -> Option<&str> {
+    let name = variable.strip_prefix("BASH_FUNC_")?;
+    name.strip_suffix("%%")
+        .or_else(|| name.strip_suffix("()"))
+        .filter(|name| !name.is_empty())
+}
+
+// Exported functions the command added or redefined, other than the ones in
+// `defined`, as fish functions calling back into bash. Mostly useful for
+// environment dumps, which hold functions only this way.
+pub fn process_exported_functions(
+    old_env: &Env,
+    new_env: &Env,
+    defined: &[&str],
+    prefix: &str,
+) -> (Vec<Change>, Option<Definitions>) {
+    let mut exported: Vec<_> = new_env
+        .iter()
+        .filter(|(variable, value)| old_env.get(*variable) != Some(*value))
+        .filter_map(|(variable, value)| Some((exported_function_name(variable)?, value)))
+        .filter(|(name, _)| !defined.contains(name))
+        .collect();
+    exported.sort_unstable_by_key(|(name, _)| *name);
+    // The value is the definition without the name, `() {  ...}`
+    let definitions = exported
+        .iter()
+        .map(|(name, value)| format!("{} {}\n", name, value))
+        .collect::<String>();
+    let definitions = Some(definitions)
+        .filter(|definitions| !definitions.is_empty())
+        .and_then(|definitions| Definitions::new(definitions, Shell::Bash));
+    let changes = exported
+        .iter()
+        .map(|(name, _)| Change {
+            category: Category::Function,
+            name: format!("{}{}", prefix, name),
+            comment: Some(format!("Adding exported function {}{}", prefix, name)),
+            actions: definitions
+                .iter()
+                .map(|definitions| definitions.proxy(name, prefix))
+                .collect(),
+            lines: Vec::new(),
+            value: None,
+            erases: false,
+        })
+        .collect();
+    (changes, definitions)
+}
+
+// Shell options turned on or off, from the colon separated `shellopts`
+// (`set -o`) and `bashopts` (`shopt`) lists of the shell state. Nothing is
+// compared when either side has no state, as for shells other than bash.
+pub fn process_setting_changes(
+    old_state: &HashMap<String, String>,
+    new_state: &HashMap<String, String>,
+) -> Vec<Change> {
+    let mut changes = Vec::new();
+    for key in ["shellopts", "bashopts"] {
+        let (Some(old), Some(new)) = (old_state.get(key), new_state.get(key)) else {
+            continue;
+        };
+        let options = |list: &'_ str| -> BTreeSet<String> {
+            list.split(':')
+                .filter(|name| !name.is_empty())
+                .map(str::to_string)
+                .collect()
+        };
+        let (old, new) = (options(old), options(new));
+        let enabled = new.difference(&old).map(|name| (name, true));
+        let disabled = old.difference(&new).map(|name| (name, false));
+        for (name, enabled_now) in enabled.chain(disabled) {
+            // `set -o vi` turns emacs off and the other way around, the
+            // enabled mode alone decides the key bindings
+            let other_mode = match name.as_str() {
+                "vi" => Some("emacs"),
+                "emacs" => Some("vi"),
+                _ => None,
+            };
+            if !enabled_now && other_mode.is_some_and(|mode| new.contains(mode)) {
+                continue;
+            }
+            let verb = if enabled_now { "Enabling" } else { "Disabling" };
+            changes.push(Change {
+                category: Category::Setting,
+                name: name.clone(),
+                comment: Some(format!("{} shell option {}", verb, name)),
+                actions: vec![Action::SetOption {
+                    name: name.clone(),
+                    enabled: enabled_now,
+                }],
+                lines: Vec::new(),
+                value: None,
+                erases: !enabled_now,
+            });
+        }
+    }
+    changes
+}
+
+// Left out with a comment in the script
+pub const ASSOCIATIVE_ARRAY: &str = "associative array, fish lists have no keys";
+
+// The elements of an `array <name>` entry of the shell state, None when the
+// quoting is broken
+pub fn array_elements(entry: &str) -> Option<Vec<Cow<'_, str>>> {
+    let mut elements = Vec::new();
+    let mut rest = entry.trim_start_matches(' ');
+    while !rest.is_empty() {
+        let (element, len) = shell_word(rest, |c| c == ' ')?;
+        elements.push(element);
+        rest = rest[len..].trim_start_matches(' ');
+    }
+    Some(elements)
+}
+
+// Arrays the command set, changed or unset, from the `array <name>` and
+// `assoc <name>` entries of the shell state. Indexed arrays become fish lists,
+// associative ones are left out.
+pub fn process_array_changes(
+    old_state: &HashMap<String, String>,
+    new_state: &HashMap<String, String>,
+    filter: &Filter,
+    policy: &Policy,
+) -> (Vec<Change>, Vec<Skipped>) {
+    let (mut changes, mut skipped) = (Vec::new(), Vec::new());
+    let mut names: Vec<_> = new_state
+        .keys()
+        .filter(|key| key.starts_with("array ") || key.starts_with("assoc "))
+        .collect();
+    names.sort();
+    for key in names {
+        let (kind, name) = key.split_at(6);
+        let value = &new_state[key];
+        let verb = match old_state.get(key) {
+            Some(old) if old == value => continue,
+            Some(_) => "Updating",
+            None => "Adding",
+        };
+        let reason = match filter.skip_reason(name) {
+            _ if kind == "assoc " => Some(ASSOCIATIVE_ARRAY.to_string()),
+            reason => reason,
+        };
+        let elements = array_elements(value);
+        let (Some(elements), None) = (elements, reason.clone()) else {
+            skipped.push(Skipped {
+                category: Category::Variable,
+                name: name.to_string(),
+                reason: reason.unwrap_or_else(|| "unreadable array elements".to_string()),
+            });
+            continue;
+        };
+        changes.push(Change {
+            category: Category::Variable,
+            name: name.to_string(),
+            comment: Some(format!("{} array {}", verb, name)),
+            actions: vec![Action::SetList {
+                name: name.to_string(),
+                values: elements.into_iter().map(Cow::into_owned).collect(),
+                scope: policy.scope(name),
+            }],
+            lines: Vec::new(),
+            value: None,
+            erases: false,
+        });
+    }
+    let mut removed: Vec<_> = old_state
+        .keys()
+        .filter(|key| key.starts_with("array ") && !new_state.contains_key(*key))
+        .collect();
+    removed.sort();
+    for key in removed {
+        let name = &key[6..];
+        if filter.skip_reason(name).is_none() {
+            changes.push(Change {
+                category: Category::Variable,
+                name: name.to_string(),
+                comment: Some(format!("Removing array {}", name)),
+                actions: vec![Action::UnsetVar {
+                    name: name.to_string(),
+                    scope: None,
+                }],
+                lines: Vec::new(),
+                value: None,
+                erases: true,
+            });
+        }
+    }
+    (changes, skipped)
+}
+
+// A changed umask, from the `umask` entry of the shell state
+pub fn process_umask_change(
+    old_state: &HashMap<String, String>,
+    new_state: &HashMap<String, String>,
+) -> Option<Change> {
+    let (old, new) = (old_state.get("umask")?, new_state.get("umask")?);
+    (old != new).then(|| Change {
+        category: Category::Setting,
+        name: "umask".to_string(),
+        comment: Some(format!("Changing umask from {} to {}", old, new)),
+        actions: vec![Action::Umask(new.clone())],
+        lines: Vec::new(),
+        value: Some(new.clone()),
+        erases: false,
+    })
+}
+
+// Changed soft resource limits, from the `ulimit -<flag>` entries of the shell
+// state
+pub fn process_limit_changes(
+    old_state: &HashMap<String, String>,
+    new_state: &HashMap<String, String>,
+) -> Vec<Change> {
+    let mut names: Vec<_> = new_state
+        .keys()
+        .filter(|name| name.starts_with("ulimit -"))
+        .collect();
+    names.sort();
+    names
+        .into_iter()
+        .filter_map(|name| {
+            let (old, new) = (old_state.get(name)?, &new_state[name]);
+            if old == new {
+                return None;
+            }
+            Some(Change {
+                category: Category::Setting,
+                name: name.clone(),
+                comment: Some(format!("Changing {} from {} to {}", name, old, new)),
+                actions: vec![Action::Ulimit {
+                    flag: name["ulimit -".len()..].to_string(),
+                    limit: new.clone(),
+                }],
+                lines: Vec::new(),
+                value: Some(new.clone()),
+                erases: false,
+            })
+        })
+        .collect()
+}
+
+// The directory stack below the current directory, from the `DIRSTACK[<n>]`
+// entries of the shell state
+pub fn dir_stack(state: &HashMap<String, String>) -> Vec<&str> {
+    (1..)
+        .map_while(|n| state.get(&format!("DIRSTACK[{}]", n)))
+        .map(String::as_str)
+        .collect()
+}
+
+// A new previous directory, for `cd -`, and a changed directory stack, as left
+// by `pushd` and `popd`
+pub fn process_directory_changes(
+    old_state: &HashMap<String, String>,
+    new_state: &HashMap<String, String>,
+) -> Vec<Change> {
+    let mut changes = Vec::new();
+    if let (Some(old), Some(new)) = (old_state.get("OLDPWD"), new_state.get("OLDPWD")) {
+        if old != new && !new.is_empty() {
+            changes.push(Change {
+                category: Category::Setting,
+                name: "OLDPWD".to_string(),
+                comment: Some(format!("Setting the previous directory to {}", new)),
+                actions: vec![Action::PreviousDir(new.clone())],
+                lines: Vec::new(),
+                value: Some(new.clone()),
+                erases: false,
+            });
+        }
+    }
+    let (old, new) = (dir_stack(old_state), dir_stack(new_state));
+    if old != new {
+        changes.push(Change {
+            category: Category::Setting,
+            name: "DIRSTACK".to_string(),
+            comment: Some(format!("Setting the directory stack to {}", new.join(" "))),
+            actions: vec![Action::DirStack(
+                new.iter().map(|dir| dir.to_string()).collect(),
+            )],
+            lines: Vec::new(),
+            value: None,
+            erases: new.is_empty(),
+        });
+    }
+    changes
+}
+
+// Completions registered or removed with `complete`, from the
+// `complete <command>` entries of the shell state. Completion functions the
+// command defined, `complete -F <function>`, are called through bash from
+// fish; other kinds of completions are only noted.
+pub fn process_completion_changes(
+    old_state: &HashMap<String, String>,
+    new_state: &HashMap<String, String>,
+    definitions: Option<&Definitions>,
+) -> Vec<Change> {
+    let completions = |state: &HashMap<String, String>| -> BTreeMap<String, String> {
+        state
+            .iter()
+            .filter_map(|(key, spec)| {
+                Some((key.strip_prefix("complete ")?.to_string(), spec.clone()))
+            })
+            .collect()
+    };
+    let (old, new) = (completions(old_state), completions(new_state));
+    let mut changes = Vec::new();
+    for (command, spec) in &new {
+        let verb = match old.get(command) {
+            Some(old_spec) if old_spec == spec => continue,
+            Some(_) => "Updating",
+            None => "Adding",
+        };
+        let mut words = spec.split_whitespace();
+        let function = words.find(|&word| word == "-F").and_then(|_| words.next());
+        let definitions = definitions.filter(|d| function.is_some_and(|f| d.defines(f)));
+        let actions = match (function, definitions) {
+            (Some(function), Some(definitions)) => vec![
+                Action::RemoveCompletions(command.clone()),
+                Action::Completion {
+                    command: command.clone(),
+                    function: function.to_string(),
+                    definitions: definitions.path().to_path_buf(),
+                },
+            ],
+            _ => vec![Action::Note(format!("fish has no equivalent of {}", spec))],
+        };
+        changes.push(Change {
+            category: Category::Completion,
+            name: command.clone(),
+            comment: Some(format!("{} completions of {}", verb, command)),
+            actions,
+            lines: Vec::new(),
+            value: None,
+            erases: false,
+        });
+    }
+    for command in old.keys().filter(|command| !new.contains_key(*command)) {
+        changes.push(Change {
+            category: Category::Completion,
+            name: command.clone(),
+            comment: Some(format!("Removing completions of {}", command)),
+            actions: vec![Action::RemoveCompletions(command.clone())],
+            lines: Vec::new(),
+            value: None,
+            erases: true,
+        });
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{
+        process_alias_changes, process_array_changes, process_env_changes, AliasMode,
+        ASSOCIATIVE_ARRAY,
+    };
+    use crate::capture::Env;
+    use crate::change::Action;
+    use crate::config::Config;
+    use crate::filter::{Filter, Pattern};
+    use crate::policy::Policy;
+    use crate::scope::{Scope, ScopeTracker};
+
+    // The diff stage produces actions, no shell code
+    #[test]
+    fn diff_produces_actions() {
+        let env = |pairs: &[(&'static str, &'static str)]| -> Env<'static> {
+            pairs
+                .iter()
+                .map(|(k, v)| ((*k).into(), (*v).into()))
+                .collect()
+        };
+        let old = env(&[("PWD", "/"), ("GONE", "1"), ("PATH", "/bin")]);
+        let new = env(&[("PWD", "/tmp"), ("NEW", "a b"), ("PATH", "/opt:/bin")]);
+        let (config, policy) = (Config::default(), Policy::default());
+        let filter = Filter::new(None, &config, &policy, &[]);
+        let scopes = ScopeTracker::default();
+        let (changes, _) = process_env_changes(&old, &new, &filter, &config, &policy, &scopes);
+        let actions: Vec<&Action> = changes.iter().flat_map(|c| &c.actions).collect();
+        assert_eq!(
+            actions,
+            [
+                &Action::ChangeDir {
+                    dir: "/tmp".to_string(),
+                    exists: None,
+                },
+                &Action::SetVar {
+                    name: "NEW".to_string(),
+                    value: "a b".to_string(),
+                    scope: Scope::Global,
+                },
+                &Action::SetPathVar {
+                    name: "PATH".to_string(),
+                    entries: vec!["/opt".to_string(), "/bin".to_string()],
+                    scope: Scope::Global,
+                },
+                &Action::UnsetVar {
+                    name: "GONE".to_string(),
+                    scope: None,
+                },
+            ]
+        );
+        assert!(changes.iter().all(|c| c.lines.is_empty()));
+    }
+
+    // Removals are filtered like assignments, fish would fail on erasing a
+    // read-only variable, built-in or flagged in the --fish-dump
+    #[test]
+    fn removals_respect_the_filter() {
+        let (config, policy) = (Config::default(), Policy::default());
+        let mut filter = Filter::new(None, &config, &policy, &[Pattern::parse("AWS_*")]);
+        filter.add_read_only(&["FROZEN".to_string()], "--fish-dump");
+        let old: Env = ["SHLVL", "AWS_X", "FROZEN", "GONE"]
+            .into_iter()
+            .map(|name| (name.into(), "1".into()))
+            .collect();
+        let (changes, skipped) = process_env_changes(
+            &old,
+            &Env::default(),
+            &filter,
+            &config,
+            &policy,
+            &ScopeTracker::default(),
+        );
+        let removed: Vec<&str> = changes.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(removed, ["GONE"]);
+        let skipped: Vec<(&str, bool)> = skipped
+            .iter()
+            .map(|s| (s.name.as_str(), filter.read_only(&s.name)))
+            .collect();
+        assert_eq!(
+            skipped,
+            [("AWS_X", false), ("FROZEN", true), ("SHLVL", true)]
+        );
+    }
+
+    // Each alias mode defines and removes aliases its own way, an alias that
+    // stops substituting only words is no abbreviation anymore
+    #[test]
+    fn aliases_follow_the_mode() {
+        let old = "alias gone='ls'\nalias g='git'\n";
+        let new = "alias g='git; true'\nalias ll='ls -l'\n";
+        let actions = |mode| -> Vec<Action> {
+            process_alias_changes(old, new, mode, "")
+                .into_iter()
+                .flat_map(|c| c.actions)
+                .collect()
+        };
+        let function = |name: &str, value: &str| Action::DefineAliasFunction {
+            name: name.to_string(),
+            value: value.to_string(),
+        };
+        assert_eq!(
+            actions(AliasMode::Function),
+            [
+                function("g", "git; true"),
+                function("ll", "ls -l"),
+                Action::RemoveAlias("gone".to_string()),
+            ]
+        );
+        assert_eq!(
+            actions(AliasMode::Abbr),
+            [
+                Action::RemoveAbbr("g".to_string()),
+                function("g", "git; true"),
+                Action::DefineAbbr {
+                    name: "ll".to_string(),
+                    value: "ls -l".to_string(),
+                },
+                Action::RemoveAbbr("gone".to_string()),
+            ]
+        );
+        let changes = process_alias_changes("", "alias ll='ls'", AliasMode::Alias, "b_");
+        assert_eq!(changes[0].comment.as_deref(), Some("Adding alias b_ll"));
+    }
+
+    // Indexed arrays become lists, associative ones are skipped, removed
+    // ones come out sorted
+    #[test]
+    fn arrays_become_lists() {
+        let state = |entries: &[(&str, &str)]| -> HashMap<String, String> {
+            entries
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+        let old = state(&[("array Z", "'1'"), ("array B", "'1'"), ("array A", "'1'")]);
+        let new = state(&[("array A", "'x' 'y z'"), ("assoc M", "[k]='v'")]);
+        let (config, policy) = (Config::default(), Policy::default());
+        let filter = Filter::new(None, &config, &policy, &[]);
+        let (changes, skipped) = process_array_changes(&old, &new, &filter, &policy);
+        assert_eq!(
+            changes[0].actions,
+            [Action::SetList {
+                name: "A".to_string(),
+                values: vec!["x".to_string(), "y z".to_string()],
+                scope: Scope::Global,
+            }]
+        );
+        let removed: Vec<&str> = changes[1..].iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(removed, ["B", "Z"]);
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(
+            (skipped[0].name.as_str(), skipped[0].reason.as_str()),
+            ("M", ASSOCIATIVE_ARRAY)
+        );
+    }
+}
@@ -0,0 +1,128 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::compare::{AliasMode, LocaleMode};
+use crate::dirs;
+use crate::scope::Scope;
+
+// Contents of ~/.config/basrs/config.toml
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    // Variables recomputed each time the generated script is sourced,
+    // name -> fish command producing the value, e.g. GPG_TTY = "tty"
+    pub deferred: BTreeMap<String, String>,
+    // Largest capture output accepted, 256 MiB by default
+    pub max_capture_mib: Option<usize>,
+    // Command the capture of the bash command runs through, e.g.
+    // ["aws-vault", "exec", "work", "--"]
+    pub wrap: Vec<String>,
+    // Variables left out of every script on top of the built-in ones, as
+    // `NAME`, `PREFIX*` or globs
+    pub ignored: Vec<String>,
+    // Variables fish does not let scripts set, on top of the built-in ones
+    pub read_only: Vec<String>,
+    // Drop the built-in ignored and read-only lists, keeping only the above
+    pub replace_builtin_lists: bool,
+    // Only commands in the generated script, as with --no-comments
+    pub no_comments: bool,
+    // Set variables fish cannot name under a name with an underscore for
+    // every character it rejects, as with --sanitize-names
+    pub sanitize_names: bool,
+    // Colon-separated variables set as fish path lists on top of the names
+    // ending in PATH, as `NAME`, `PREFIX*` or globs
+    pub path_vars: Vec<String>,
+    // What happens to changed locale variables, as with --locale-policy
+    pub locale_policy: Option<LocaleMode>,
+    // Settings for one kind of environment, picked with --profile, e.g.
+    // `[profile.work]`
+    pub profile: BTreeMap<String, Profile>,
+    // From the selected profile, --alias-mode and --scope win over them
+    #[serde(skip)]
+    pub alias_mode: Option<AliasMode>,
+    #[serde(skip)]
+    pub scope: Option<Scope>,
+    // The file the config was loaded from, shown as the source of its rules
+    #[serde(skip)]
+    pub source: String,
+}
+
+// A `[profile.<name>]` table, its lists adding to the ones of the config
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    pub ignored: Vec<String>,
+    pub path_vars: Vec<String>,
+    pub alias_mode: Option<AliasMode>,
+    pub scope: Option<Scope>,
+}
+
+impl Config {
+    // An explicitly given config file must exist, the default one is optional
+    pub fn load(path: Option<&Path>) -> io::Result<Config> {
+        let (path, required) = match path {
+            Some(path) => (path.to_path_buf(), true),
+            None => match default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Config::default()),
+            },
+        };
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if !required && e.kind() == io::ErrorKind::NotFound => {
+                return Ok(Config::default())
+            }
+            Err(e) => return Err(e),
+        };
+        let mut config: Config = toml::from_str(&content).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid config {}: {}", path.display(), e),
+            )
+        })?;
+        config.source = format!("config {}", path.display());
+        Ok(config)
+    }
+}
+
+impl Config {
+    // The config with the profile `name` applied, which has to exist
+    pub fn with_profile(mut self, name: Option<&str>) -> io::Result<Config> {
+        let Some(name) = name else {
+            return Ok(self);
+        };
+        let Some(profile) = self.profile.remove(name) else {
+            let names: Vec<&str> = self.profile.keys().map(|k| k.as_str()).collect();
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Unknown profile {} (available: {})",
+                    name,
+                    if names.is_empty() {
+                        "none, add [profile.<name>] tables to the config".to_string()
+                    } else {
+                        names.join(", ")
+                    }
+                ),
+            ));
+        };
+        self.ignored.extend(profile.ignored);
+        self.path_vars.extend(profile.path_vars);
+        self.alias_mode = profile.alias_mode;
+        self.scope = profile.scope;
+        self.source = format!("profile {} of {}", name, self.source);
+        Ok(self)
+    }
+
+    pub fn capture_limit(&self) -> usize {
+        self.max_capture_mib.unwrap_or(256).saturating_mul(1 << 20)
+    }
+}
+
+pub fn default_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("config.toml"))
+}
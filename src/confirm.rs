@@ -0,0 +1,96 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+
+use crate::change::{Category, Change, Skipped};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Answer {
+    Yes,
+    No,
+    // Accept the rest of this category without asking
+    All,
+    // Drop everything not yet accepted
+    Quit,
+}
+
+// Asks about every change on the terminal, stdout is most likely piped into
+// `source` and stdin may be too
+pub fn confirm(changes: Vec<Change>, skipped: &mut Vec<Skipped>) -> io::Result<Vec<Change>> {
+    let mut tty = Tty::open()?;
+    let mut accepted_categories: Vec<Category> = Vec::new();
+    let mut quit = false;
+    let mut accepted = Vec::new();
+    for change in changes {
+        if accepted_categories.contains(&change.category) {
+            accepted.push(change);
+            continue;
+        }
+        let answer = if quit {
+            Answer::Quit
+        } else {
+            tty.ask(&change)?
+        };
+        match answer {
+            Answer::Yes => accepted.push(change),
+            Answer::All => {
+                accepted_categories.push(change.category);
+                accepted.push(change);
+            }
+            Answer::No | Answer::Quit => {
+                quit = answer == Answer::Quit;
+                skipped.push(Skipped {
+                    category: change.category,
+                    name: change.name,
+                    reason: "declined interactively".to_string(),
+                });
+            }
+        }
+    }
+    Ok(accepted)
+}
+
+struct Tty {
+    input: BufReader<File>,
+    output: File,
+}
+
+impl Tty {
+    fn open() -> io::Result<Tty> {
+        let output = OpenOptions::new().write(true).open("/dev/tty")?;
+        let input = BufReader::new(File::open("/dev/tty")?);
+        Ok(Tty { input, output })
+    }
+
+    fn ask(&mut self, change: &Change) -> io::Result<Answer> {
+        writeln!(self.output)?;
+        for line in change.render() {
+            writeln!(self.output, "  {}", line)?;
+        }
+        loop {
+            write!(
+                self.output,
+                "Apply this {} change [y,n,a,q,?]? ",
+                change.category.name()
+            )?;
+            self.output.flush()?;
+            let mut reply = String::new();
+            if self.input.read_line(&mut reply)? == 0 {
+                return Ok(Answer::Quit);
+            }
+            match reply.trim() {
+                "y" => return Ok(Answer::Yes),
+                "n" => return Ok(Answer::No),
+                "a" => return Ok(Answer::All),
+                "q" => return Ok(Answer::Quit),
+                _ => writeln!(
+                    self.output,
+                    "y - apply this change\n\
+                     n - skip this change\n\
+                     a - apply this and all remaining {0} changes\n\
+                     q - skip this and all remaining changes",
+                    change.category.name()
+                )?,
+            }
+        }
+    }
+}
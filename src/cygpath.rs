@@ -0,0 +1,101 @@
+use std::borrow::Cow;
+use std::env;
+use std::io;
+use std::process::{Command, Stdio};
+
+use crate::capture::Env;
+use crate::shell::find_program;
+
+// How paths in the values bash leaves behind are converted for fish, for
+// Git Bash scripts imported into a fish running under MSYS2 or Cygwin
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PathTranslate {
+    // Values are set as bash has them
+    #[default]
+    None,
+    // As Cygpath when running under MSYS2 or Cygwin, otherwise as None
+    Auto,
+    // Windows paths in the directory and the path lists become POSIX ones
+    // with `cygpath -u`, e.g. `C:\Users` becomes `/c/Users`
+    Cygpath,
+}
+
+impl PathTranslate {
+    pub fn from_name(name: &str) -> Option<PathTranslate> {
+        match name {
+            "none" => Some(PathTranslate::None),
+            "auto" => Some(PathTranslate::Auto),
+            "cygpath" => Some(PathTranslate::Cygpath),
+            _ => None,
+        }
+    }
+
+    fn active(self) -> bool {
+        match self {
+            PathTranslate::None => false,
+            PathTranslate::Cygpath => true,
+            // MSYS2 and Git Bash set MSYSTEM, Cygwin has its own target
+            PathTranslate::Auto => {
+                cfg!(target_os = "cygwin") || env::var_os("MSYSTEM").is_some_and(|v| !v.is_empty())
+            }
+        }
+    }
+}
+
+// `new` with the values of PWD and of the path variables the command changed
+// translated, all in one run of cygpath. None when nothing is translated.
+pub fn translate<'a>(
+    mode: PathTranslate,
+    old: &Env,
+    new: &Env<'a>,
+    path_var: &dyn Fn(&str) -> bool,
+) -> io::Result<Option<Env<'a>>> {
+    if !mode.active() {
+        return Ok(None);
+    }
+    let cygpath = find_program("cygpath").ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            "--path-translate needs cygpath in PATH, as MSYS2, Git Bash and Cygwin have it",
+        )
+    })?;
+    // cygpath prints one line per argument
+    let mut names: Vec<&Cow<str>> = new
+        .iter()
+        .filter(|(name, value)| {
+            (*name == "PWD" || path_var(name))
+                && !value.is_empty()
+                && !value.contains('\n')
+                && old.get(*name) != Some(*value)
+        })
+        .map(|(name, _)| name)
+        .collect();
+    if names.is_empty() {
+        return Ok(None);
+    }
+    names.sort_unstable();
+    let output = Command::new(cygpath)
+        .arg("-u")
+        .arg("-p")
+        .args(names.iter().map(|name| new[*name].as_ref()))
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    if !output.status.success() || lines.len() != names.len() {
+        return Err(io::Error::other(format!(
+            "cygpath failed to translate {}",
+            names
+                .iter()
+                .map(|name| name.as_ref())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )));
+    }
+    let mut translated = new.clone();
+    for (name, line) in names.into_iter().zip(lines) {
+        translated.insert(name.clone(), Cow::Owned(line.to_string()));
+    }
+    Ok(Some(translated))
+}
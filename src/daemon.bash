@@ -0,0 +1,43 @@
+# basrs daemon worker. Reads request directories from stdin, NUL-terminated,
+# and runs the capture helper ($1) for each in a fresh subshell, which is far
+# cheaper than starting bash. The request file holds the working directory,
+# the command, the settle time, the trace flag, whether to capture unexported
+# variables, whether to capture the state before the command too and the
+# client's terminal if its stdin is one, then the `env -0` dump of the
+# environment to run in, all NUL-terminated. The capture goes to `out`,
+# the command's output to `err`, and the subshell's status back on stdout.
+__basrs_helper=$1
+# Listed once, subshells only see these exported before the reset
+__basrs_exported=($(compgen -e))
+while IFS= read -r -d '' __basrs_dir; do
+    (
+        {
+            IFS= read -r -d '' __basrs_cwd
+            IFS= read -r -d '' __basrs_command
+            IFS= read -r -d '' __basrs_settle
+            IFS= read -r -d '' __basrs_trace
+            IFS= read -r -d '' __basrs_all_vars
+            IFS= read -r -d '' __basrs_baseline
+            IFS= read -r -d '' __basrs_tty
+            # Before the reset, cd updates PWD and OLDPWD
+            cd -- "$__basrs_cwd" || exit
+            # Only the client's environment is exported
+            unset -v "${__basrs_exported[@]}"
+            while IFS= read -r -d '' __basrs_var; do
+                export "$__basrs_var"
+            done
+        } < "$__basrs_dir/request"
+        unset -v __basrs_var
+        # Opened by name, the command reads and prompts on the client's
+        # terminal as it would in a bash of its own
+        [ -z "$__basrs_tty" ] || exec < "$__basrs_tty" || exit
+        # What a starting bash does: PWD is always exported, corrected if it
+        # names another directory, and OLDPWD exported even without a value
+        [ -n "${PWD-}" ] && [ "$PWD" -ef . ] || PWD=$__basrs_cwd
+        export PWD
+        [ -n "${OLDPWD+set}" ] || export OLDPWD
+        . "$__basrs_helper" "$__basrs_command" "$__basrs_settle" "$__basrs_trace" \
+            "$__basrs_all_vars" "$__basrs_baseline"
+    ) < /dev/null > "$__basrs_dir/out" 2> "$__basrs_dir/err"
+    printf '%s\0' "$?"
+done
@@ -0,0 +1,383 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, DirBuilder, File};
+use std::io::{self, BufRead, BufReader, IsTerminal, Read, Write};
+use std::os::fd::AsFd;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process::{self, Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use crate::capture::{self, Runner};
+use crate::dirs;
+use crate::error::Error;
+use crate::helper;
+use crate::shell::Shell;
+
+// A daemon of another version may capture differently, its clients spawn
+// bash themselves instead
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// Sent back instead of running a request of another version
+const MISMATCH: &str = "version mismatch";
+
+const WORKER: &str = include_str!("daemon.bash");
+
+// How often the output of a running command is checked for more
+const RELAY_INTERVAL: Duration = Duration::from_millis(20);
+
+fn socket_path() -> Option<PathBuf> {
+    Some(dirs::runtime_dir()?.join("daemon.sock"))
+}
+
+fn write_record(writer: &mut impl Write, record: &[u8]) -> io::Result<()> {
+    write!(writer, "{}:", record.len())?;
+    writer.write_all(record)
+}
+
+fn read_records(message: &[u8], count: usize) -> io::Result<Vec<&[u8]>> {
+    let mut records = Vec::with_capacity(count);
+    let mut start = 0;
+    while records.len() < count {
+        let record = capture::next_record(message, start).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "Daemon message is incomplete")
+        })?;
+        start = record.end;
+        records.push(&message[record]);
+    }
+    Ok(records)
+}
+
+// Runs the capture in the daemon's warm bash. None when no daemon is
+// listening or the request needs a bash of its own, the command has not run
+// in either case.
+pub fn capture(command: &str, runner: &Runner, baseline: bool) -> Option<io::Result<Vec<u8>>> {
+    if runner.shell != Shell::Bash {
+        return None;
+    }
+    let mut env: BTreeMap<Vec<u8>, Vec<u8>> = match runner.base_env {
+        Some(base_env) => base_env
+            .iter()
+            .map(|(k, v)| {
+                (
+                    capture::encode(k).into_owned(),
+                    capture::encode(v).into_owned(),
+                )
+            })
+            .collect(),
+        None => env::vars_os()
+            .map(|(k, v)| (k.into_encoded_bytes(), v.into_encoded_bytes()))
+            .collect(),
+    };
+    env.extend(
+        runner
+            .extra_env
+            .iter()
+            .map(|(k, v)| (k.as_bytes().to_vec(), v.as_bytes().to_vec())),
+    );
+    // A starting bash imports exported functions, sources BASH_ENV and passes
+    // on variables it cannot name, the worker's subshell does none of that
+    let fresh_bash_only =
+        |name: &[u8]| name == b"BASH_ENV" || name.starts_with(b"BASH_FUNC_") || !is_name(name);
+    if env.keys().any(|name| fresh_bash_only(name)) {
+        return None;
+    }
+    let tty = stdin_terminal()?;
+    let cwd = env::current_dir().ok()?;
+    let mut stream = UnixStream::connect(socket_path()?).ok()?;
+
+    let mut request = Vec::new();
+    for field in [
+        cwd.as_os_str().as_encoded_bytes(),
+        command.as_bytes(),
+        (runner.settle.as_millis() / 100).to_string().as_bytes(),
+        if runner.trace { b"1" } else { b"0" },
+        if runner.all_vars { b"1" } else { b"0" },
+        if baseline { b"1" } else { b"0" },
+        &tty,
+    ] {
+        request.extend_from_slice(field);
+        request.push(0);
+    }
+    for (k, v) in &env {
+        request.extend_from_slice(k);
+        request.push(b'=');
+        request.extend_from_slice(v);
+        request.push(0);
+    }
+    let mut message = Vec::new();
+    write_record(&mut message, VERSION.as_bytes()).ok()?;
+    write_record(&mut message, &request).ok()?;
+    stream.write_all(&message).ok()?;
+    stream.shutdown(std::net::Shutdown::Write).ok()?;
+
+    // The command's output comes as it is written, until the outcome
+    let mut response = BufReader::new(stream);
+    loop {
+        let record = match read_record(&mut response, usize::MAX) {
+            Ok(record) => record,
+            Err(e) => return Some(Err(e)),
+        };
+        if record == MISMATCH.as_bytes() {
+            return None;
+        }
+        match record.split_first() {
+            Some((b'o', output)) => {
+                let _ = io::stderr().write_all(output);
+            }
+            Some((b's', status)) => return Some(finish(status, &mut response, runner.limit)),
+            Some((b'e', error)) => {
+                return Some(Err(io::Error::other(
+                    String::from_utf8_lossy(error).into_owned(),
+                )))
+            }
+            _ => {
+                return Some(Err(Error::MalformedCapture(
+                    "unexpected message from the daemon".to_string(),
+                )
+                .into()))
+            }
+        }
+    }
+}
+
+// The capture following the status of the worker's subshell
+fn finish(status: &[u8], response: &mut impl BufRead, limit: usize) -> io::Result<Vec<u8>> {
+    if status != b"0" {
+        // Already relayed, the command's output is not repeated
+        return Err(Error::ShellFailed {
+            shell: Shell::Bash.program(),
+            status: std::str::from_utf8(status)
+                .ok()
+                .and_then(|s| s.parse().ok()),
+            signal: None,
+            stderr: String::new(),
+        }
+        .into());
+    }
+    read_record(response, limit)
+}
+
+// A `<length>:<bytes>` record from a stream, one longer than `limit` is an
+// error rather than read
+fn read_record(reader: &mut impl BufRead, limit: usize) -> io::Result<Vec<u8>> {
+    let incomplete = || {
+        io::Error::from(Error::MalformedCapture(
+            "the daemon's message is incomplete".into(),
+        ))
+    };
+    let mut len = Vec::new();
+    reader.read_until(b':', &mut len)?;
+    if len.pop() != Some(b':') {
+        return Err(incomplete());
+    }
+    let len: usize = std::str::from_utf8(&len)
+        .ok()
+        .and_then(|len| len.parse().ok())
+        .ok_or_else(incomplete)?;
+    if len > limit {
+        return Err(Error::CaptureTooLarge(limit >> 20).into());
+    }
+    let mut record = vec![0; len];
+    reader.read_exact(&mut record)?;
+    Ok(record)
+}
+
+// The terminal on stdin by name, for the worker to open, or nothing when
+// stdin is /dev/null or closed. None for a pipe, a file or anything else the
+// command has to read as the same open file, which only a starting bash gets.
+fn stdin_terminal() -> Option<Vec<u8>> {
+    let stdin = io::stdin();
+    if stdin.is_terminal() {
+        let mut name = vec![0u8; 256];
+        let found =
+            unsafe { libc::ttyname_r(libc::STDIN_FILENO, name.as_mut_ptr().cast(), name.len()) };
+        if found != 0 {
+            return None;
+        }
+        name.truncate(name.iter().position(|&b| b == 0)?);
+        // Opened as /dev/tty, which would be the daemon's own terminal
+        return (name != b"/dev/tty").then_some(name);
+    }
+    let Ok(stdin) = stdin.as_fd().try_clone_to_owned() else {
+        return Some(Vec::new());
+    };
+    let (stdin, null) = (
+        File::from(stdin).metadata().ok()?,
+        fs::metadata("/dev/null").ok()?,
+    );
+    (stdin.dev() == null.dev() && stdin.ino() == null.ino()).then(Vec::new)
+}
+
+fn is_name(name: &[u8]) -> bool {
+    name.first()
+        .is_some_and(|c| c.is_ascii_alphabetic() || *c == b'_')
+        && name.iter().all(|c| c.is_ascii_alphanumeric() || *c == b'_')
+}
+
+// The warm bash, forking a subshell for every request
+struct Worker {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl Worker {
+    fn spawn() -> io::Result<Worker> {
+        let helper = helper::install(Shell::Bash)?;
+        let mut child = Command::new(Shell::Bash.program())
+            .arg("-c")
+            .arg(WORKER)
+            .arg("basrs-daemon")
+            .arg(helper)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take().expect("piped stdin");
+        let stdout = BufReader::new(child.stdout.take().expect("piped stdout"));
+        Ok(Worker {
+            child,
+            stdin,
+            stdout,
+        })
+    }
+
+    fn run(&mut self, dir: &Path) -> io::Result<Vec<u8>> {
+        self.stdin.write_all(dir.as_os_str().as_encoded_bytes())?;
+        self.stdin.write_all(b"\0")?;
+        let mut status = Vec::new();
+        self.stdout.read_until(0, &mut status)?;
+        if status.pop() != Some(0) {
+            return Err(io::Error::other("Worker bash exited"));
+        }
+        Ok(status)
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// `basrs daemon`, serving captures until killed
+pub fn serve() -> io::Result<()> {
+    let path = socket_path()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No runtime directory"))?;
+    let dir = path.parent().expect("socket in a directory");
+    DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+    if UnixStream::connect(&path).is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("A daemon is already listening on {}", path.display()),
+        ));
+    }
+    // Left behind by a daemon that did not exit cleanly
+    let _ = fs::remove_file(&path);
+    let listener = UnixListener::bind(&path)?;
+    eprintln!("Basrs: daemon listening on {}", path.display());
+
+    let mut worker = None;
+    for (id, stream) in listener.incoming().enumerate() {
+        let result = stream.and_then(|stream| {
+            let request_dir = dir.join(format!("request.{}.{}", process::id(), id));
+            let result = handle(stream, &request_dir, &mut worker);
+            let _ = fs::remove_dir_all(&request_dir);
+            result
+        });
+        if let Err(e) = result {
+            eprintln!("Basrs warning: daemon request failed: {}", e);
+        }
+    }
+    Ok(())
+}
+
+// Responses to this version start every record with a tag: `o` for output of
+// the command, then `s` with the status of the worker's subshell followed by
+// the capture, or `e` with an error
+fn handle(mut stream: UnixStream, dir: &Path, worker: &mut Option<Worker>) -> io::Result<()> {
+    let mut message = Vec::new();
+    stream.read_to_end(&mut message)?;
+    // Someone checking whether the daemon is up
+    if message.is_empty() {
+        return Ok(());
+    }
+    let result = match read_records(&message, 2) {
+        Ok(records) if records[0] != VERSION.as_bytes() => {
+            return write_record(&mut stream, MISMATCH.as_bytes());
+        }
+        Ok(records) => run(records[1], dir, worker, &stream),
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok((status, capture)) => {
+            write_record(&mut stream, &[b"s", &status[..]].concat())?;
+            write_record(&mut stream, &capture)
+        }
+        Err(e) => write_record(&mut stream, format!("e{}", e).as_bytes()),
+    }
+}
+
+// The status of the worker's subshell and the capture, the command's output
+// relayed to `stream` meanwhile
+fn run(
+    request: &[u8],
+    dir: &Path,
+    worker: &mut Option<Worker>,
+    stream: &UnixStream,
+) -> io::Result<(Vec<u8>, Vec<u8>)> {
+    DirBuilder::new().mode(0o700).create(dir)?;
+    fs::write(dir.join("request"), request)?;
+    // Opened before the worker writes to it, it only ever grows
+    File::create(dir.join("err"))?;
+    let output = File::open(dir.join("err"))?;
+    // A worker killed in between is replaced
+    if let Some(Ok(Some(_))) = worker.as_mut().map(|w| w.child.try_wait()) {
+        *worker = None;
+    }
+    let current = match worker {
+        Some(current) => current,
+        None => worker.insert(Worker::spawn()?),
+    };
+    let done = AtomicBool::new(false);
+    let status = thread::scope(|s| {
+        let relay = s.spawn(|| relay(output, stream, &done));
+        let status = current.run(dir);
+        done.store(true, Ordering::Release);
+        // A client gone away still gets its command run to the end
+        let _ = relay.join();
+        status
+    });
+    let status = match status {
+        Ok(status) => status,
+        Err(e) => {
+            *worker = None;
+            return Err(e);
+        }
+    };
+    Ok((status, fs::read(dir.join("out"))?))
+}
+
+// Sends what the command writes to `output` as it arrives, until `done` and
+// everything written is sent
+fn relay(mut output: File, mut stream: &UnixStream, done: &AtomicBool) -> io::Result<()> {
+    let mut chunk = vec![0; 8192];
+    loop {
+        let finished = done.load(Ordering::Acquire);
+        loop {
+            let read = output.read(&mut chunk)?;
+            if read == 0 {
+                break;
+            }
+            write_record(&mut stream, &[b"o", &chunk[..read]].concat())?;
+        }
+        if finished {
+            return Ok(());
+        }
+        thread::sleep(RELAY_INTERVAL);
+    }
+}
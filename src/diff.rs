@@ -0,0 +1,215 @@
+use std::borrow::Cow;
+
+use serde::{Deserialize, Serialize};
+
+use crate::capture::{self, Env, Snapshot};
+use crate::change::{Category, Change};
+use crate::compare;
+
+// A computed diff in the JSON form exchanged between capturing and emitting,
+// e.g. to review or edit the diff before it is rendered
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EnvDiff {
+    pub variables: Vec<VariableChange>,
+    pub aliases: Vec<AliasDiff>,
+    pub functions: FunctionChanges,
+}
+
+// The value before the change doesn't matter for emitting, other fields are
+// ignored too
+#[derive(Debug, Deserialize, Serialize)]
+pub struct VariableChange {
+    pub name: String,
+    // Null when the variable is added
+    #[serde(default)]
+    pub old: Option<String>,
+    // Null removes the variable
+    pub new: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AliasDiff {
+    pub name: String,
+    // Null or missing removes the alias
+    pub value: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FunctionChanges {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    // Redefined, only reported as the definitions are not part of the diff
+    pub changed: Vec<String>,
+}
+
+impl EnvDiff {
+    // What changed from `old` to `new`, before any filtering
+    pub fn between(old: &Snapshot, new: &Snapshot) -> EnvDiff {
+        let mut variables: Vec<VariableChange> = new
+            .env
+            .iter()
+            .filter(|(name, value)| old.env.get(*name) != Some(value))
+            .map(|(name, value)| VariableChange {
+                name: name.to_string(),
+                old: old.env.get(name).map(|old| old.to_string()),
+                new: Some(value.to_string()),
+            })
+            .collect();
+        variables.extend(
+            old.env
+                .iter()
+                .filter(|(name, _)| !new.env.contains_key(*name))
+                .map(|(name, value)| VariableChange {
+                    name: name.to_string(),
+                    old: Some(value.to_string()),
+                    new: None,
+                }),
+        );
+        let (old_aliases, new_aliases) = (
+            compare::parse_aliases(&old.aliases),
+            compare::parse_aliases(&new.aliases),
+        );
+        let mut aliases: Vec<AliasDiff> = new_aliases
+            .iter()
+            .filter(|alias| !old_aliases.contains(alias))
+            .map(|(name, value)| AliasDiff {
+                name: name.to_string(),
+                value: Some(value.to_string()),
+            })
+            .collect();
+        aliases.extend(
+            old_aliases
+                .iter()
+                .filter(|(name, _)| !new_aliases.iter().any(|(new, _)| new == name))
+                .map(|(name, _)| AliasDiff {
+                    name: name.to_string(),
+                    value: None,
+                }),
+        );
+        let (old_definitions, new_definitions) = (
+            compare::parse_funcs(&old.functions),
+            compare::parse_funcs(&new.functions),
+        );
+        let names = |funcs: &[(&str, Option<&str>)]| -> Vec<String> {
+            funcs.iter().map(|(name, _)| name.to_string()).collect()
+        };
+        let (old_funcs, new_funcs) = (names(&old_definitions), names(&new_definitions));
+        let functions = FunctionChanges {
+            added: new_funcs
+                .iter()
+                .filter(|func| !old_funcs.contains(func))
+                .cloned()
+                .collect(),
+            removed: old_funcs
+                .iter()
+                .filter(|func| !new_funcs.contains(func))
+                .cloned()
+                .collect(),
+            changed: new_definitions
+                .iter()
+                .filter(|(name, definition)| {
+                    definition.is_some()
+                        && old_definitions.iter().any(|(old, old_definition)| {
+                            old == name && old_definition != definition
+                        })
+                })
+                .map(|(name, _)| name.to_string())
+                .collect(),
+        };
+        variables.sort_by(|a, b| (a.new.is_none(), &a.name).cmp(&(b.new.is_none(), &b.name)));
+        EnvDiff {
+            variables,
+            aliases,
+            functions,
+        }
+    }
+
+    pub fn from_json(json: &[u8]) -> serde_json::Result<EnvDiff> {
+        serde_json::from_slice(json)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("diff serializes")
+    }
+
+    // Keeps only what is also in `changes`, i.e. what made it through the
+    // filters into the script
+    pub fn retain(&mut self, changes: &[Change]) {
+        let kept = |category: Category, name: &str| {
+            changes
+                .iter()
+                .any(|change| change.category == category && change.name == name)
+        };
+        self.variables
+            .retain(|variable| kept(Category::Variable, &variable.name));
+        self.aliases
+            .retain(|alias| kept(Category::Alias, &alias.name));
+        self.functions
+            .added
+            .retain(|func| kept(Category::Function, func));
+        self.functions
+            .removed
+            .retain(|func| kept(Category::Function, func));
+        self.functions
+            .changed
+            .retain(|func| kept(Category::Function, func));
+    }
+
+    // The states before and after applying the diff on top of `env`, which
+    // the regular diffing then turns back into changes for the target format
+    pub fn apply<'a>(&'a self, env: Env<'a>) -> (Snapshot<'a>, Snapshot<'a>) {
+        let mut new_env = env.clone();
+        for variable in &self.variables {
+            match &variable.new {
+                Some(value) => new_env.insert(
+                    Cow::Borrowed(variable.name.as_str()),
+                    Cow::Borrowed(value.as_str()),
+                ),
+                None => new_env.remove(variable.name.as_str()),
+            };
+        }
+        // Back in the bash listing formats the snapshots normally hold, a removed
+        // alias only exists before
+        let listing = |removed: bool| -> String {
+            self.aliases
+                .iter()
+                .filter(|alias| alias.value.is_none() == removed)
+                .map(|alias| {
+                    let value = alias.value.as_deref().unwrap_or_default();
+                    format!("alias {}='{}'\n", alias.name, value.replace('\'', "'\\''"))
+                })
+                .collect()
+        };
+        let (old_aliases, aliases) = (listing(true), listing(false));
+        let declarations = |names: &[String]| -> String {
+            names
+                .iter()
+                .map(|name| format!("declare -f {}\n", name))
+                .collect()
+        };
+        let (old_functions, new_functions) = (
+            declarations(&self.functions.removed),
+            declarations(&self.functions.added),
+        );
+        let old = Snapshot {
+            env,
+            aliases_hash: capture::hash(old_aliases.as_bytes()),
+            aliases: Cow::Owned(old_aliases),
+            functions_hash: capture::hash(old_functions.as_bytes()),
+            functions: Cow::Owned(old_functions),
+            ..Default::default()
+        };
+        let new = Snapshot {
+            env: new_env,
+            aliases_hash: capture::hash(aliases.as_bytes()),
+            aliases: Cow::Owned(aliases),
+            functions_hash: capture::hash(new_functions.as_bytes()),
+            functions: Cow::Owned(new_functions),
+            ..Default::default()
+        };
+        (old, new)
+    }
+}
@@ -0,0 +1,42 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+// XDG base directories, scoped to basrs
+
+fn xdg_base(var: &str, home_fallback: &str) -> Option<PathBuf> {
+    env::var_os(var)
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(home_fallback)))
+}
+
+fn xdg_dir(var: &str, home_fallback: &str) -> Option<PathBuf> {
+    Some(xdg_base(var, home_fallback)?.join("basrs"))
+}
+
+pub fn config_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_CONFIG_HOME", ".config")
+}
+
+// Where fish looks for its configuration, functions and completions
+pub fn fish_config_dir() -> Option<PathBuf> {
+    Some(xdg_base("XDG_CONFIG_HOME", ".config")?.join("fish"))
+}
+
+pub fn state_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_STATE_HOME", ".local/state")
+}
+
+pub fn cache_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_CACHE_HOME", ".cache")
+}
+
+pub fn data_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_DATA_HOME", ".local/share")
+}
+
+// XDG has no fallback for the runtime directory, the cache one stands in
+#[cfg(all(unix, feature = "daemon"))]
+pub fn runtime_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_RUNTIME_DIR", ".cache")
+}
@@ -0,0 +1,610 @@
+use std::path::Path;
+
+use crate::scope::Scope;
+use crate::{escape, escape_comment};
+
+// Syntax of the shell the generated script is sourced in. The diff decides
+// what changes, the emitter how each change is written, so another target
+// shell only needs another implementation.
+pub trait ShellEmitter: Sync {
+    // The shell as the comments of the script name it
+    fn name(&self) -> &'static str;
+
+    fn set_var(&self, name: &str, value: &str, scope: Scope) -> String;
+
+    // Like `set_var`, without exporting the variable
+    fn set_shell_var(&self, name: &str, value: &str, scope: Scope) -> String;
+
+    // A list of `values`, not exported as bash does not export arrays
+    fn set_list(&self, name: &str, values: &[&str], scope: Scope) -> String;
+
+    // A colon-separated list like PATH, already split
+    fn set_path_var(&self, name: &str, entries: &[&str], scope: Scope) -> String;
+
+    // The value is the output of `command`, run when the script is sourced
+    fn set_var_from_command(&self, name: &str, command: &str, scope: Scope) -> String;
+
+    // Without a scope, whichever one the shell finds first
+    fn unset_var(&self, name: &str, scope: Option<Scope>) -> String;
+
+    fn alias(&self, name: &str, value: &str) -> String;
+
+    fn unalias(&self, name: &str) -> String;
+
+    // An abbreviation expanding to `value` as it is typed
+    fn abbr(&self, name: &str, value: &str) -> String;
+
+    fn unabbr(&self, name: &str) -> String;
+
+    // A function standing in for the alias, `value` followed by the arguments
+    fn alias_function(&self, name: &str, value: &str) -> Vec<String>;
+
+    // Without `exists` the command left a directory that is gone
+    fn cd(&self, dir: &str, exists: bool) -> Vec<String>;
+
+    // Makes `dir` the one `cd -` goes back to
+    fn previous_dir(&self, dir: &str) -> String;
+
+    // Replaces the directory stack of `pushd` and `popd`, top first
+    fn dir_stack(&self, dirs: &[&str]) -> String;
+
+    // Adds entries to the search path kept across sessions, in front of the
+    // existing ones unless `append`
+    fn add_user_paths(&self, entries: &[&str], append: bool) -> String;
+
+    // Takes an entry out of the kept search path and the current one
+    fn remove_user_path(&self, entry: &str) -> Vec<String>;
+
+    // What turning a `set -o` or `shopt` option on or off does in fish, None
+    // for the options fish has no equivalent of
+    fn shell_option(&self, name: &str, enabled: bool) -> Option<String>;
+
+    // Sets the file creation mask, given in octal
+    fn umask(&self, mask: &str) -> String;
+
+    // Sets the soft resource limit of a bash `ulimit` flag, None for the
+    // limits the shell cannot set
+    fn ulimit(&self, flag: &str, limit: &str) -> Option<String>;
+
+    // Completes `command` with the bash completion function `function`,
+    // loaded from `definitions`
+    fn bash_completion(&self, command: &str, function: &str, definitions: &Path) -> String;
+
+    fn remove_completions(&self, command: &str) -> String;
+
+    // A function `name` running `call` with its arguments appended, each
+    // quoted for bash, through `basrs <basrs_args>` and sourcing the output
+    fn function(&self, name: &str, description: &str, basrs_args: &str, call: &str) -> Vec<String>;
+
+    // `lines` run only when the variable does not hold `value` yet
+    fn unless_value(&self, name: &str, value: &str, lines: Vec<String>) -> Vec<String>;
+
+    // `lines` run only when the variable is set
+    fn if_set(&self, name: &str, lines: Vec<String>) -> Vec<String>;
+
+    // `lines` run only when there is no function `name` yet
+    fn unless_function(&self, name: &str, lines: Vec<String>) -> Vec<String>;
+}
+
+// `lines` as the body of a fish block opened by `header`
+fn block(header: String, lines: Vec<String>) -> Vec<String> {
+    let mut block = vec![header];
+    block.extend(lines.into_iter().map(|line| format!("    {}", line)));
+    block.push("end".to_string());
+    block
+}
+
+pub struct Fish;
+
+impl ShellEmitter for Fish {
+    fn name(&self) -> &'static str {
+        "fish"
+    }
+
+    fn set_var(&self, name: &str, value: &str, scope: Scope) -> String {
+        format!("set -{} -x {} {}", scope.code(), name, escape(value))
+    }
+
+    fn set_shell_var(&self, name: &str, value: &str, scope: Scope) -> String {
+        format!("set -{} {} {}", scope.code(), name, escape(value))
+    }
+
+    fn set_list(&self, name: &str, values: &[&str], scope: Scope) -> String {
+        let mut line = format!("set -{} {}", scope.code(), name);
+        for value in values {
+            line.push(' ');
+            line.push_str(&escape(value));
+        }
+        line
+    }
+
+    fn set_path_var(&self, name: &str, entries: &[&str], scope: Scope) -> String {
+        let entries: Vec<_> = entries.iter().map(|entry| escape(entry)).collect();
+        format!(
+            "set -{} -x --path {} {}",
+            scope.code(),
+            name,
+            entries.join(" ")
+        )
+    }
+
+    fn set_var_from_command(&self, name: &str, command: &str, scope: Scope) -> String {
+        format!("set -{} -x {} ({})", scope.code(), name, command)
+    }
+
+    fn unset_var(&self, name: &str, scope: Option<Scope>) -> String {
+        match scope {
+            Some(scope) => format!("set -e{} {}", scope.code(), name),
+            None => format!("set -e {}", name),
+        }
+    }
+
+    fn alias(&self, name: &str, value: &str) -> String {
+        format!("alias {} {}", name, escape(value))
+    }
+
+    // Fish aliases are functions
+    fn unalias(&self, name: &str) -> String {
+        format!("functions -e {}", name)
+    }
+
+    fn abbr(&self, name: &str, value: &str) -> String {
+        format!("abbr -a {} {}", escape(name), escape(value))
+    }
+
+    fn unabbr(&self, name: &str) -> String {
+        format!("abbr -e {}", escape(name))
+    }
+
+    // Arguments come last as with a bash alias, also after a chain like
+    // `make && make install`. A trailing space has bash expand aliases in the
+    // next word, fish has no such thing.
+    fn alias_function(&self, name: &str, value: &str) -> Vec<String> {
+        let body = value.trim_end();
+        let mut header = format!(
+            "function {} --description {}",
+            escape(name),
+            escape(&format!("alias {}={}", name, value))
+        );
+        // Completions of the wrapped command, unless the alias wraps itself
+        let first = body.split_whitespace().next().unwrap_or_default();
+        if !first.is_empty() && first != name {
+            header.push_str(&format!(" --wraps {}", escape(first)));
+        }
+        let call = match body {
+            "" => "    $argv".to_string(),
+            body => format!("    {} $argv", body),
+        };
+        vec![header, call, "end".to_string()]
+    }
+
+    // The builtin, as a `cd` function or alias of the user may do anything.
+    // It takes the path as given, a logical $PWD through a symlink stays one.
+    // The script may have left a directory it removed again, e.g. a temporary
+    // one, so a missing directory is skipped when the script is sourced.
+    fn cd(&self, dir: &str, exists: bool) -> Vec<String> {
+        if exists {
+            return vec![format!("builtin cd -- {}", escape(dir))];
+        }
+        vec![
+            format!(
+                "# Warning: {} does not exist anymore, the directory stays as it is",
+                escape(dir)
+            ),
+            format!("test -d {0}; and builtin cd -- {0}", escape(dir)),
+        ]
+    }
+
+    // `cd -` goes to the last entry of the directory history
+    fn previous_dir(&self, dir: &str) -> String {
+        format!("set -g -a dirprev {}", escape(dir))
+    }
+
+    fn dir_stack(&self, dirs: &[&str]) -> String {
+        let dirs: Vec<_> = dirs.iter().map(|dir| format!(" {}", escape(dir))).collect();
+        format!("set -g dirstack{}", dirs.concat())
+    }
+
+    // fish_add_path skips directories that are missing or already there
+    fn add_user_paths(&self, entries: &[&str], append: bool) -> String {
+        let entries: Vec<_> = entries.iter().map(|entry| escape(entry)).collect();
+        let append = if append { "--append " } else { "" };
+        format!("fish_add_path {}{}", append, entries.join(" "))
+    }
+
+    fn remove_user_path(&self, entry: &str) -> Vec<String> {
+        vec![
+            format!(
+                "set -l index (contains -i -- {} $fish_user_paths); and set -eU fish_user_paths[$index]",
+                escape(entry)
+            ),
+            format!(
+                "set -l index (contains -i -- {} $PATH); and set -e PATH[$index]",
+                escape(entry)
+            ),
+        ]
+    }
+
+    // Only the line editing modes carry over, fish has none of the others
+    fn shell_option(&self, name: &str, enabled: bool) -> Option<String> {
+        match (name, enabled) {
+            ("vi", true) => Some("fish_vi_key_bindings".to_string()),
+            ("vi", false) | ("emacs", true) => Some("fish_default_key_bindings".to_string()),
+            _ => None,
+        }
+    }
+
+    fn umask(&self, mask: &str) -> String {
+        format!("umask {}", mask)
+    }
+
+    // fish's flags match bash's for the limits both know
+    fn ulimit(&self, flag: &str, limit: &str) -> Option<String> {
+        matches!(
+            flag,
+            "c" | "d" | "e" | "f" | "i" | "l" | "m" | "n" | "q" | "r" | "s" | "t" | "u" | "v"
+        )
+        .then(|| format!("ulimit -S -{} {}", flag, limit))
+    }
+
+    // The command line up to the cursor is split into COMP_WORDS the way
+    // bash would for simple words, a trailing space starts an empty one
+    fn bash_completion(&self, command: &str, function: &str, definitions: &Path) -> String {
+        let script = concat!(
+            r#"source "$1" || exit; COMP_LINE=$3; COMP_POINT=${#3}; "#,
+            r#"read -ra COMP_WORDS <<< "$3"; "#,
+            r#"[[ $3 == *[[:space:]] || ${#COMP_WORDS[@]} -eq 0 ]] && COMP_WORDS+=(""); "#,
+            r#"COMP_CWORD=$((${#COMP_WORDS[@]} - 1)); "#,
+            r#""$2" "${COMP_WORDS[0]}" "${COMP_WORDS[COMP_CWORD]}" "${COMP_WORDS[COMP_CWORD-1]}"; "#,
+            r#"printf '%s\n' "${COMPREPLY[@]}""#,
+        );
+        let call = format!(
+            "(bash -c {} bash {} {} (commandline -cp))",
+            escape(script),
+            escape(&definitions.to_string_lossy()),
+            escape(function)
+        );
+        format!("complete -c {} -f -a {}", escape(command), escape(&call))
+    }
+
+    fn remove_completions(&self, command: &str) -> String {
+        format!("complete -c {} -e", escape(command))
+    }
+
+    fn function(&self, name: &str, description: &str, basrs_args: &str, call: &str) -> Vec<String> {
+        // Inside double quotes the arguments are joined with spaces
+        let call = format!("{}\"$args\"", escape(&format!("{} ", call)));
+        let basrs_args = match basrs_args {
+            "" => String::new(),
+            args => format!("{} ", args),
+        };
+        vec![
+            format!(
+                "function {} --description {}",
+                escape(name),
+                escape(description)
+            ),
+            "    set -l args".to_string(),
+            "    for arg in $argv".to_string(),
+            "        set -a args \"'\"(string replace -a \"'\" \"'\\\\''\" -- $arg)\"'\"".to_string(),
+            "    end".to_string(),
+            format!(
+                "    command basrs {}--fish-dump (set --show | psub) --inherit-fish-env (env -0 | psub) -- {} | source",
+                basrs_args, call
+            ),
+            "end".to_string(),
+        ]
+    }
+
+    // A quoted path list joins its entries with colons, so bash's value
+    // compares as it is
+    fn unless_value(&self, name: &str, value: &str, lines: Vec<String>) -> Vec<String> {
+        block(
+            format!(
+                "if not set -q {0}; or test \"${0}\" != {1}",
+                name,
+                escape(value)
+            ),
+            lines,
+        )
+    }
+
+    fn if_set(&self, name: &str, lines: Vec<String>) -> Vec<String> {
+        block(format!("if set -q {}", name), lines)
+    }
+
+    fn unless_function(&self, name: &str, lines: Vec<String>) -> Vec<String> {
+        block(format!("if not functions -q {}", escape(name)), lines)
+    }
+}
+
+// Nushell, variables go to `$env`. Bash functions, completions and shell
+// state other than the directory are left out before anything is emitted, so
+// those methods only say what is missing.
+pub struct Nu;
+
+// Single quotes take everything but quotes and control characters as it is,
+// double quotes escape those
+pub fn nu_quote(value: &str) -> String {
+    if !value.contains(|c: char| c == '\'' || c.is_control()) {
+        return format!("'{}'", value);
+    }
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '\r' => quoted.push_str("\\r"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// A plain alias body as a Nushell command, the arguments quoted. Bodies with
+// more than plain words never get here.
+fn nu_command(value: &str) -> String {
+    let mut words = value.split_whitespace();
+    let command = words.next().unwrap_or_default().to_string();
+    words.fold(command, |line, word| line + " " + &nu_quote(word))
+}
+
+fn nu_list(values: &[&str]) -> String {
+    let values: Vec<_> = values.iter().map(|value| nu_quote(value)).collect();
+    format!("[{}]", values.join(" "))
+}
+
+// `lines` in a block run if `condition` holds, changes of $env in it stay
+fn nu_block(condition: String, lines: Vec<String>) -> Vec<String> {
+    let mut block = vec![format!("if {} {{", condition)];
+    block.extend(lines.into_iter().map(|line| format!("    {}", line)));
+    block.push("}".to_string());
+    block
+}
+
+impl ShellEmitter for Nu {
+    fn name(&self) -> &'static str {
+        "Nushell"
+    }
+
+    // Scopes are fish's, the script changes the environment of the Nushell
+    // sourcing it
+    fn set_var(&self, name: &str, value: &str, _scope: Scope) -> String {
+        format!("$env.{} = {}", name, nu_quote(value))
+    }
+
+    fn set_shell_var(&self, name: &str, value: &str, scope: Scope) -> String {
+        self.set_var(name, value, scope)
+    }
+
+    fn set_list(&self, name: &str, values: &[&str], _scope: Scope) -> String {
+        format!("$env.{} = {}", name, nu_list(values))
+    }
+
+    fn set_path_var(&self, name: &str, entries: &[&str], scope: Scope) -> String {
+        self.set_list(name, entries, scope)
+    }
+
+    fn set_var_from_command(&self, name: &str, command: &str, _scope: Scope) -> String {
+        format!("$env.{} = ({} | str trim)", name, command)
+    }
+
+    fn unset_var(&self, name: &str, _scope: Option<Scope>) -> String {
+        format!("hide-env -i {}", name)
+    }
+
+    fn alias(&self, name: &str, value: &str) -> String {
+        format!("alias {} = {}", name, nu_command(value))
+    }
+
+    fn unalias(&self, name: &str) -> String {
+        format!("hide {}", name)
+    }
+
+    fn abbr(&self, name: &str, value: &str) -> String {
+        self.alias(name, value)
+    }
+
+    fn unabbr(&self, name: &str) -> String {
+        self.unalias(name)
+    }
+
+    fn alias_function(&self, name: &str, value: &str) -> Vec<String> {
+        vec![
+            format!("def --wrapped {} [...args] {{", name),
+            format!("    {} ...$args", nu_command(value)),
+            "}".to_string(),
+        ]
+    }
+
+    fn cd(&self, dir: &str, exists: bool) -> Vec<String> {
+        if exists {
+            return vec![format!("cd {}", nu_quote(dir))];
+        }
+        vec![
+            format!(
+                "# Warning: {} does not exist anymore, the directory stays as it is",
+                nu_quote(dir)
+            ),
+            format!("if ({0} | path exists) {{ cd {0} }}", nu_quote(dir)),
+        ]
+    }
+
+    fn previous_dir(&self, dir: &str) -> String {
+        format!("$env.OLDPWD = {}", nu_quote(dir))
+    }
+
+    fn dir_stack(&self, dirs: &[&str]) -> String {
+        format!(
+            "# Directory stack {} has no Nushell equivalent",
+            nu_list(dirs)
+        )
+    }
+
+    fn add_user_paths(&self, entries: &[&str], append: bool) -> String {
+        format!(
+            "$env.PATH = ($env.PATH | {} {} | uniq)",
+            if append { "append" } else { "prepend" },
+            nu_list(entries)
+        )
+    }
+
+    fn remove_user_path(&self, entry: &str) -> Vec<String> {
+        vec![format!(
+            "$env.PATH = ($env.PATH | where $it != {})",
+            nu_quote(entry)
+        )]
+    }
+
+    fn shell_option(&self, _name: &str, _enabled: bool) -> Option<String> {
+        None
+    }
+
+    fn umask(&self, mask: &str) -> String {
+        format!("# umask {} has no Nushell equivalent", escape_comment(mask))
+    }
+
+    fn ulimit(&self, _flag: &str, _limit: &str) -> Option<String> {
+        None
+    }
+
+    fn bash_completion(&self, command: &str, function: &str, _definitions: &Path) -> String {
+        format!(
+            "# Completion of {} with {} has no Nushell equivalent",
+            escape_comment(command),
+            escape_comment(function)
+        )
+    }
+
+    fn remove_completions(&self, command: &str) -> String {
+        format!(
+            "# Completions of {} have no Nushell equivalent",
+            escape_comment(command)
+        )
+    }
+
+    fn function(
+        &self,
+        name: &str,
+        _description: &str,
+        _basrs_args: &str,
+        _call: &str,
+    ) -> Vec<String> {
+        vec![format!(
+            "# Function {} has no Nushell equivalent",
+            escape_comment(name)
+        )]
+    }
+
+    fn unless_value(&self, name: &str, value: &str, lines: Vec<String>) -> Vec<String> {
+        nu_block(format!("$env.{}? != {}", name, nu_quote(value)), lines)
+    }
+
+    fn if_set(&self, name: &str, lines: Vec<String>) -> Vec<String> {
+        nu_block(format!("$env.{}? != null", name), lines)
+    }
+
+    // Definitions inside a block stay there, and defining them again is
+    // harmless
+    fn unless_function(&self, _name: &str, lines: Vec<String>) -> Vec<String> {
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{nu_quote, Fish, Nu, ShellEmitter};
+    use crate::change::Action;
+    use crate::scope::Scope;
+
+    // The same action in the syntax of each target
+    #[test]
+    fn actions_emit_per_shell() {
+        let action = Action::DefineAlias {
+            name: "ll".to_string(),
+            value: "ls -l".to_string(),
+        };
+        assert_eq!(action.emit(&Fish), ["alias ll 'ls -l'"]);
+        assert_eq!(action.emit(&Nu), ["alias ll = ls '-l'"]);
+        let unset = Action::UnsetVar {
+            name: "A".to_string(),
+            scope: None,
+        };
+        assert_eq!(unset.emit(&Nu), ["hide-env -i A"]);
+    }
+    #[test]
+    fn fish_syntax() {
+        assert_eq!(Fish.set_var("A", "a b", Scope::Global), "set -g -x A 'a b'");
+        assert_eq!(Fish.set_shell_var("A", "1", Scope::Local), "set -l A '1'");
+        assert_eq!(
+            Fish.set_list("A", &["x", "y"], Scope::Global),
+            "set -g A 'x' 'y'"
+        );
+        assert_eq!(
+            Fish.set_path_var("PATH", &["/a", "/b"], Scope::Universal),
+            "set -U -x --path PATH '/a' '/b'"
+        );
+        assert_eq!(Fish.unset_var("A", None), "set -e A");
+        assert_eq!(Fish.unset_var("A", Some(Scope::Global)), "set -eg A");
+        assert_eq!(Fish.abbr("g", "git"), "abbr -a 'g' 'git'");
+        assert_eq!(Fish.unalias("ll"), "functions -e ll");
+        assert_eq!(
+            Fish.alias_function("ll", "ls -l "),
+            [
+                "function 'll' --description 'alias ll=ls -l ' --wraps 'ls'",
+                "    ls -l $argv",
+                "end",
+            ]
+        );
+        assert_eq!(Fish.cd("/", true), ["builtin cd -- '/'"]);
+        assert_eq!(Fish.cd("/gone", false).len(), 2);
+        assert_eq!(
+            Fish.shell_option("vi", true).as_deref(),
+            Some("fish_vi_key_bindings")
+        );
+        assert_eq!(Fish.shell_option("errexit", true), None);
+        assert_eq!(Fish.ulimit("x", "1"), None);
+        assert_eq!(
+            Fish.if_set("A", vec!["echo".to_string()]),
+            ["if set -q A", "    echo", "end"]
+        );
+    }
+
+    #[test]
+    fn nu_syntax() {
+        assert_eq!(Nu.name(), "Nushell");
+        assert_eq!(Nu.set_var("A", "a b", Scope::Universal), "$env.A = 'a b'");
+        assert_eq!(
+            Nu.set_path_var("PATH", &["/a", "/b"], Scope::Global),
+            "$env.PATH = ['/a' '/b']"
+        );
+        assert_eq!(
+            Nu.set_var_from_command("A", "date", Scope::Global),
+            "$env.A = (date | str trim)"
+        );
+        assert_eq!(Nu.unalias("ll"), "hide ll");
+        assert_eq!(
+            Nu.alias_function("ll", "ls -l"),
+            ["def --wrapped ll [...args] {", "    ls '-l' ...$args", "}"]
+        );
+        assert_eq!(Nu.cd("/", true), ["cd '/'"]);
+        assert_eq!(
+            Nu.if_set("A", vec!["print".to_string()]),
+            ["if $env.A? != null {", "    print", "}"]
+        );
+    }
+
+    // Quotes and control characters go in double quotes, escaped
+    #[test]
+    fn nu_quotes() {
+        assert_eq!(nu_quote("a $b"), "'a $b'");
+        assert_eq!(nu_quote("it's"), "\"it's\"");
+        assert_eq!(nu_quote("\"\\\n\x1b'"), "\"\\\"\\\\\\n\\u{1b}'\"");
+    }
+}
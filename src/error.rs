@@ -0,0 +1,143 @@
+use std::fmt;
+use std::io;
+use std::process::ExitStatus;
+use std::time::Duration;
+
+// Failures of a capture, carried inside io::Error so the io::Result plumbing
+// stays as it is. The cli takes the message and the exit status from them.
+#[derive(Debug)]
+pub enum Error {
+    // The shell exited before printing the state, with what it printed on
+    // stderr
+    ShellFailed {
+        shell: &'static str,
+        status: Option<i32>,
+        signal: Option<i32>,
+        stderr: String,
+    },
+    // The helper's output ends before the named section
+    MissingSection(&'static str),
+    // The helper's or the daemon's output is not in the expected format
+    MalformedCapture(String),
+    TimedOut(Duration),
+    // Limit in MiB the capture output went past
+    CaptureTooLarge(usize),
+}
+
+// The sections of a capture in the order the helpers print them
+pub const SECTIONS: [&str; 8] = [
+    "aliases",
+    "functions",
+    "exit status",
+    "chain step",
+    "background jobs",
+    "assignment locations",
+    "shell state",
+    "environment",
+];
+
+impl Error {
+    pub fn shell_failed(shell: &'static str, status: ExitStatus, stderr: &[u8]) -> Error {
+        #[cfg(unix)]
+        let signal = std::os::unix::process::ExitStatusExt::signal(&status);
+        #[cfg(not(unix))]
+        let signal = None;
+        Error::ShellFailed {
+            shell,
+            status: status.code(),
+            signal,
+            stderr: String::from_utf8_lossy(stderr).trim().to_string(),
+        }
+    }
+
+    // The error inside `e`, if it is one of these
+    pub fn of(e: &io::Error) -> Option<&Error> {
+        e.get_ref()?.downcast_ref()
+    }
+
+    // Exit status of basrs: 2 for usage errors as with clap, 3 when the shell
+    // failed, 70 for capture output basrs cannot read, 124 on timeouts as with
+    // timeout(1), 127 for missing programs and 1 for anything else
+    pub fn exit_code(e: &io::Error) -> i32 {
+        match Error::of(e) {
+            Some(Error::ShellFailed { .. }) => 3,
+            Some(Error::MissingSection(_) | Error::MalformedCapture(_)) => 70,
+            Some(Error::TimedOut(_)) => 124,
+            Some(Error::CaptureTooLarge(_)) => 1,
+            None => match e.kind() {
+                io::ErrorKind::InvalidInput => 2,
+                io::ErrorKind::NotFound => 127,
+                io::ErrorKind::TimedOut => 124,
+                _ => 1,
+            },
+        }
+    }
+
+    fn kind(&self) -> io::ErrorKind {
+        match self {
+            Error::ShellFailed { .. } => io::ErrorKind::Other,
+            Error::MissingSection(_) | Error::MalformedCapture(_) => io::ErrorKind::InvalidData,
+            Error::TimedOut(_) => io::ErrorKind::TimedOut,
+            Error::CaptureTooLarge(_) => io::ErrorKind::OutOfMemory,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::ShellFailed {
+                shell,
+                status,
+                signal,
+                stderr,
+            } => {
+                match (status, signal) {
+                    (_, Some(signal)) => write!(
+                        f,
+                        "{} was killed by signal {} before the state could be captured",
+                        shell, signal
+                    )?,
+                    (Some(status), _) => write!(
+                        f,
+                        "{} exited with status {} before the state could be captured; if the \
+                         command calls `exit`, run it in a subshell, e.g. `(./script.sh)`, use \
+                         `return` in sourced scripts, or --keep-going to apply its changes anyway",
+                        shell, status
+                    )?,
+                    _ => write!(f, "{} failed before the state could be captured", shell)?,
+                }
+                if !stderr.is_empty() {
+                    write!(f, "\n{}", stderr)?;
+                }
+                Ok(())
+            }
+            Error::MissingSection(section) => write!(
+                f,
+                "The capture output ends before the {} section, the command may have ended \
+                 the shell with `exit 0` or redirected its stdout with `exec`",
+                section
+            ),
+            Error::MalformedCapture(what) => write!(f, "Malformed capture output: {}", what),
+            Error::TimedOut(timeout) => write!(
+                f,
+                "Command timed out after {}s, a longer --timeout gives it more time",
+                timeout.as_secs_f32()
+            ),
+            Error::CaptureTooLarge(limit_mib) => write!(
+                f,
+                "Capture output exceeds {} MiB, raise max_capture_mib in the config if this \
+                 is expected",
+                limit_mib
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<Error> for io::Error {
+    fn from(e: Error) -> io::Error {
+        io::Error::new(e.kind(), e)
+    }
+}
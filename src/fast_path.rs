@@ -0,0 +1,221 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::capture::Env;
+
+// Scripts whose effect on the environment is known without running bash.
+// Each fast path recognizes a command and, if the script it refers to is
+// exactly what it expects, applies the same changes bash would have made.
+struct FastPath {
+    apply: fn(&str, &mut Env) -> Option<()>,
+}
+
+const FAST_PATHS: &[FastPath] = &[
+    FastPath { apply: cargo_env },
+    FastPath {
+        apply: simple_exports,
+    },
+];
+
+// Returns the environment after the command, or None if bash has to run it
+pub fn apply<'a>(command: &str, env: &Env<'a>) -> Option<Env<'a>> {
+    FAST_PATHS.iter().find_map(|fast_path| {
+        let mut new_env = env.clone();
+        (fast_path.apply)(command, &mut new_env).map(|_| new_env)
+    })
+}
+
+// `source <file>` or `. <file>` with a single, possibly quoted, file argument
+fn sourced_file(command: &str) -> Option<PathBuf> {
+    let command = command.trim();
+    let file = command
+        .strip_prefix("source ")
+        .or_else(|| command.strip_prefix(". "))?
+        .trim();
+    let file = match file.as_bytes().first() {
+        Some(b'"') => file.strip_prefix('"')?.strip_suffix('"')?,
+        Some(b'\'') => {
+            // No expansions inside single quotes
+            let file = file.strip_prefix('\'')?.strip_suffix('\'')?;
+            return (!file.contains('\'')).then(|| PathBuf::from(file));
+        }
+        _ => file,
+    };
+    if file.contains(|c: char| c.is_whitespace() || "\"'`;&|<>()*?[".contains(c)) {
+        return None;
+    }
+    Some(PathBuf::from(expand_home(file)?))
+}
+
+// Expands a leading ~, $HOME or ${HOME}, refusing any other expansion
+fn expand_home(word: &str) -> Option<String> {
+    let home = env::var("HOME").ok();
+    let word = ["~/", "$HOME/", "${HOME}/"]
+        .iter()
+        .find_map(|prefix| word.strip_prefix(prefix))
+        .map(|rest| -> Option<String> { Some(format!("{}/{}", home.as_ref()?, rest)) })
+        .unwrap_or_else(|| Some(word.to_string()))?;
+    (!word.contains('$')).then_some(word)
+}
+
+// rustup's ~/.cargo/env, which prepends the cargo bin directory to PATH once
+fn cargo_env(command: &str, env: &mut Env) -> Option<()> {
+    let file = sourced_file(command)?;
+    if file.file_name()? != "env" {
+        return None;
+    }
+    let content = fs::read_to_string(&file).ok()?;
+    let lines: Vec<&str> = content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect();
+    let [case, pattern, ";;", "*)", export, ";;", "esac"] = lines[..] else {
+        return None;
+    };
+    if case != "case \":${PATH}:\" in" {
+        return None;
+    }
+    let dir = pattern.strip_prefix("*:\"")?.strip_suffix("\":*)")?;
+    if export != format!("export PATH=\"{}:$PATH\"", dir) {
+        return None;
+    }
+    let dir = expand_home(dir)?;
+
+    let path = env.get("PATH").map_or("", |path| path);
+    if !path.split(':').any(|entry| entry == dir) {
+        let new_path = format!("{}:{}", dir, path);
+        env.insert(Cow::Borrowed("PATH"), Cow::Owned(new_path));
+    }
+    Some(())
+}
+
+// Scripts made of nothing but comments and assignments of literals and
+// variable references, e.g. `export EDITOR=vim` or `PATH="$PATH:$HOME/bin"`.
+// Either the command itself or a file it sources.
+fn simple_exports(command: &str, env: &mut Env) -> Option<()> {
+    let script = match sourced_file(command) {
+        // Without a slash, bash looks the file up in PATH first
+        Some(file) if file.to_string_lossy().contains('/') => fs::read_to_string(&file).ok()?,
+        Some(_) => return None,
+        None => command.to_string(),
+    };
+    // Assigned but not exported, only visible to later lines of the script
+    let mut shell_vars = HashMap::new();
+    for line in script.lines() {
+        let mut rest = line.trim_start();
+        let export = match rest.strip_prefix("export") {
+            Some(args) if args.starts_with([' ', '\t']) => {
+                rest = args;
+                true
+            }
+            _ => false,
+        };
+        // The arguments of export are all expanded before any of them is
+        // assigned, bare assignments happen one after the other
+        let mut assignments = Vec::new();
+        let mut assigned = false;
+        loop {
+            rest = rest.trim_start();
+            if rest.is_empty() || rest.starts_with('#') {
+                break;
+            }
+            let (name, value) = rest.split_once('=')?;
+            if !is_name(name) {
+                return None;
+            }
+            let (value, after) = expand_word(value, env, &shell_vars)?;
+            assigned = true;
+            if export {
+                assignments.push((name.to_string(), value));
+            } else {
+                assign(name.to_string(), value, false, env, &mut shell_vars);
+            }
+            rest = after;
+        }
+        if export && !assigned {
+            return None;
+        }
+        for (name, value) in assignments {
+            assign(name, value, true, env, &mut shell_vars);
+        }
+    }
+    Some(())
+}
+
+// Exported variables and ones already in the environment go there, the rest
+// stays with the shell
+fn assign(
+    name: String,
+    value: String,
+    export: bool,
+    env: &mut Env,
+    shell_vars: &mut HashMap<String, String>,
+) {
+    if export || env.contains_key(name.as_str()) {
+        shell_vars.remove(&name);
+        env.insert(Cow::Owned(name), Cow::Owned(value));
+    } else {
+        shell_vars.insert(name, value);
+    }
+}
+
+fn is_name(word: &str) -> bool {
+    let mut chars = word.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// Expands one word made of literals, quotes and $NAME or ${NAME} references,
+// returning it with the rest of the line. Anything else, including references
+// to variables bash may know but we don't, means bash has to run the script.
+fn expand_word<'a>(
+    word: &'a str,
+    env: &Env,
+    shell_vars: &HashMap<String, String>,
+) -> Option<(String, &'a str)> {
+    let lookup = |name: &str| -> Option<String> {
+        shell_vars
+            .get(name)
+            .map(String::as_str)
+            .or_else(|| env.get(name).map(|value| value.as_ref()))
+            .map(str::to_string)
+    };
+    let mut value = String::new();
+    let mut chars = word.char_indices().peekable();
+    let mut quote = None;
+    while let Some((i, c)) = chars.next() {
+        match (quote, c) {
+            (None, ' ' | '\t') => return Some((value, &word[i..])),
+            (None, '\'' | '"') => quote = Some(c),
+            (Some(q), _) if c == q => quote = None,
+            (Some('\''), _) => value.push(c),
+            (_, '$') => {
+                let braced = chars.next_if(|&(_, c)| c == '{').is_some();
+                let mut name = String::new();
+                while let Some((_, c)) =
+                    chars.next_if(|&(_, c)| c.is_ascii_alphanumeric() || c == '_')
+                {
+                    name.push(c);
+                }
+                if braced && chars.next_if(|&(_, c)| c == '}').is_none() {
+                    return None;
+                }
+                if !is_name(&name) {
+                    return None;
+                }
+                value.push_str(&lookup(&name)?);
+            }
+            (_, '\\' | '`') => return None,
+            (None, ';' | '&' | '|' | '<' | '>' | '(' | ')' | '*' | '?' | '[' | ']' | '{')
+            | (None, '}' | '~' | '#' | '!') => return None,
+            _ => value.push(c),
+        }
+    }
+    quote.is_none().then_some((value, ""))
+}
@@ -0,0 +1,387 @@
+use std::fmt;
+
+use regex::Regex;
+
+use crate::change::Category;
+use crate::config::Config;
+use crate::policy::Policy;
+use crate::preset::Preset;
+
+// List of read-only and ignored environment variables. The read-only ones are
+// fish's electric variables that `set` refuses, those of newer fish versions
+// come from the --fish-dump of the running fish.
+const FISH_READONLY: &[&str] = &[
+    "PWD",
+    "SHLVL",
+    "history",
+    "pipestatus",
+    "status",
+    "status_generation",
+    "version",
+    "FISH_VERSION",
+    "fish_pid",
+    "fish_kill_signal",
+    "fish_killring",
+    "hostname",
+    "_",
+    "fish_private_mode",
+];
+
+// OLDPWD is applied as fish's directory history instead
+const IGNORED: &[&str] = &["OLDPWD", "PS1", "XPC_SERVICE_NAME"];
+
+#[derive(Clone)]
+pub enum Pattern {
+    Exact(String),
+    Prefix(String),
+    Glob(String),
+    Regex(Regex),
+}
+
+impl Pattern {
+    // `NAME`, `PREFIX*` or any other glob
+    pub fn parse(pattern: &str) -> Pattern {
+        match pattern.strip_suffix('*') {
+            _ if !pattern.contains(['*', '?']) => Pattern::Exact(pattern.to_string()),
+            Some(prefix) if !prefix.contains(['*', '?']) => Pattern::Prefix(prefix.to_string()),
+            _ => Pattern::Glob(pattern.to_string()),
+        }
+    }
+
+    // Like `parse`, or a regex between slashes, e.g. `/^AWS_(KEY|TOKEN)$/`
+    pub fn parse_with_regex(pattern: &str) -> Result<Pattern, String> {
+        match pattern
+            .strip_prefix('/')
+            .and_then(|rest| rest.strip_suffix('/'))
+        {
+            Some(regex) => Regex::new(regex)
+                .map(Pattern::Regex)
+                .map_err(|e| format!("Invalid regex {}: {}", pattern, e)),
+            None => Ok(Pattern::parse(pattern)),
+        }
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        match self {
+            Pattern::Exact(exact) => name == exact,
+            Pattern::Prefix(prefix) => name.starts_with(prefix.as_str()),
+            Pattern::Glob(glob) => glob_match(glob, name),
+            Pattern::Regex(regex) => regex.is_match(name),
+        }
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Pattern::Exact(exact) => write!(f, "{}", exact),
+            Pattern::Prefix(prefix) => write!(f, "{}*", prefix),
+            Pattern::Glob(glob) => write!(f, "{}", glob),
+            Pattern::Regex(regex) => write!(f, "/{}/", regex),
+        }
+    }
+}
+
+// What --only keeps: variables matching a pattern, or aliases and functions
+// with `alias:<pattern>` and `function:<pattern>`, shell options with
+// `setting:<pattern>` and completions with `completion:<pattern>`
+#[derive(Clone)]
+pub struct Selection {
+    category: Category,
+    pattern: Pattern,
+}
+
+impl Selection {
+    pub fn parse(arg: &str) -> Selection {
+        let (category, pattern) = match arg.split_once(':') {
+            Some(("alias", pattern)) => (Category::Alias, pattern),
+            Some(("function", pattern)) => (Category::Function, pattern),
+            Some(("setting", pattern)) => (Category::Setting, pattern),
+            Some(("completion", pattern)) => (Category::Completion, pattern),
+            _ => (Category::Variable, arg),
+        };
+        Selection {
+            category,
+            pattern: Pattern::parse(pattern),
+        }
+    }
+
+    pub fn selects(&self, category: Category, name: &str) -> bool {
+        self.category == category && self.pattern.matches(name)
+    }
+}
+
+// Shell-style glob with `*` and `?`
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Position of the last `*` and the name position it was tried at
+    let mut backtrack = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, tried)) => {
+                    p = star + 1;
+                    n = tried + 1;
+                    backtrack = Some((star, tried + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+// fish variable names are letters, digits and underscores
+pub fn is_fish_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+// The name with an underscore for every character fish rejects, e.g.
+// `foo_bar` for `foo.bar`
+pub fn sanitize_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum RuleKind {
+    ReadOnly,
+    Ignored,
+    ExportedFunction,
+    InvalidName,
+}
+
+impl RuleKind {
+    fn name(self) -> &'static str {
+        match self {
+            RuleKind::ReadOnly => "read-only",
+            RuleKind::Ignored => "ignored",
+            RuleKind::ExportedFunction => "exported-function",
+            RuleKind::InvalidName => "invalid-name",
+        }
+    }
+
+    fn reason(self) -> &'static str {
+        match self {
+            RuleKind::ReadOnly => "read-only in fish",
+            RuleKind::Ignored => "ignored",
+            RuleKind::ExportedFunction => "exported bash function",
+            RuleKind::InvalidName => "not a fish variable name, --sanitize-names renames it",
+        }
+    }
+}
+
+pub struct Rule {
+    pub pattern: Pattern,
+    pub kind: RuleKind,
+    // Where the rule comes from, e.g. "built-in" or "preset venv"
+    pub source: String,
+}
+
+// Decides which variables are left out of the script
+pub struct Filter {
+    rules: Vec<Rule>,
+    // Exempt from the built-in ignored and read-only lists, given with --allow
+    allowed: Vec<Pattern>,
+}
+
+impl Filter {
+    // `ignored` comes from --ignore
+    pub fn new(
+        preset: Option<&Preset>,
+        config: &Config,
+        policy: &Policy,
+        ignored: &[Pattern],
+    ) -> Filter {
+        let mut filter = Filter {
+            rules: Vec::new(),
+            allowed: Vec::new(),
+        };
+        let builtin = |pattern, kind| Rule {
+            pattern,
+            kind,
+            source: "built-in".to_string(),
+        };
+        let (builtin_read_only, builtin_ignored) = if config.replace_builtin_lists {
+            (&[][..], &[][..])
+        } else {
+            (FISH_READONLY, IGNORED)
+        };
+        for name in builtin_read_only.iter().filter(|&&name| name != "PWD") {
+            filter.rules.push(builtin(
+                Pattern::Exact(name.to_string()),
+                RuleKind::ReadOnly,
+            ));
+        }
+        for name in builtin_ignored {
+            filter
+                .rules
+                .push(builtin(Pattern::Exact(name.to_string()), RuleKind::Ignored));
+        }
+        let configured = config
+            .read_only
+            .iter()
+            .map(|pattern| (pattern, RuleKind::ReadOnly))
+            .chain(
+                config
+                    .ignored
+                    .iter()
+                    .map(|pattern| (pattern, RuleKind::Ignored)),
+            );
+        for (pattern, kind) in configured {
+            filter.rules.push(Rule {
+                pattern: Pattern::parse(pattern),
+                kind,
+                source: config.source.clone(),
+            });
+        }
+        filter.rules.push(builtin(
+            Pattern::Prefix("BASH_FUNC".to_string()),
+            RuleKind::ExportedFunction,
+        ));
+        // Set under another name instead when sanitizing
+        if !config.sanitize_names {
+            filter.rules.push(builtin(
+                Pattern::Regex(Regex::new(r"^$|[^\p{Alphabetic}\p{N}_]").expect("valid regex")),
+                RuleKind::InvalidName,
+            ));
+        }
+        if let Some(preset) = preset {
+            let source = format!("preset {}", preset.name);
+            let exact = preset.ignored.iter().map(|n| Pattern::Exact(n.to_string()));
+            let prefixes = preset
+                .ignored_prefixes
+                .iter()
+                .map(|p| Pattern::Prefix(p.to_string()));
+            for pattern in exact.chain(prefixes) {
+                filter.rules.push(Rule {
+                    pattern,
+                    kind: RuleKind::Ignored,
+                    source: source.clone(),
+                });
+            }
+        }
+        for treatment in policy.rules.iter().filter(|treatment| treatment.skip) {
+            filter.rules.push(Rule {
+                pattern: treatment.pattern.clone(),
+                kind: RuleKind::Ignored,
+                source: policy.source.clone(),
+            });
+        }
+        for pattern in ignored {
+            filter.rules.push(Rule {
+                pattern: pattern.clone(),
+                kind: RuleKind::Ignored,
+                source: "--ignore".to_string(),
+            });
+        }
+        filter
+    }
+
+    // Read-only variables the running fish reports, in addition to the
+    // built-in ones
+    pub fn add_read_only(&mut self, names: &[String], source: &str) {
+        for name in names.iter().filter(|&name| name != "PWD") {
+            self.rules.push(Rule {
+                pattern: Pattern::Exact(name.clone()),
+                kind: RuleKind::ReadOnly,
+                source: source.to_string(),
+            });
+        }
+    }
+
+    // Names or `PREFIX*` patterns left out for a reason of the caller's
+    pub fn add_ignored(&mut self, patterns: &[&str], source: &str) {
+        for pattern in patterns {
+            self.rules.push(Rule {
+                pattern: Pattern::parse(pattern),
+                kind: RuleKind::Ignored,
+                source: source.to_string(),
+            });
+        }
+    }
+
+    pub fn allow(&mut self, patterns: &[Pattern]) {
+        self.allowed.extend(patterns.iter().cloned());
+    }
+
+    // The rule leaving `name` out, None if none does
+    fn rule(&self, name: &str) -> Option<&Rule> {
+        if name == "PWD" {
+            return None; // PWD has special handling
+        }
+        let allowed = self.allowed.iter().any(|pattern| pattern.matches(name));
+        self.rules.iter().find(|rule| {
+            rule.pattern.matches(name)
+                && !(allowed
+                    && rule.source == "built-in"
+                    && matches!(rule.kind, RuleKind::ReadOnly | RuleKind::Ignored))
+        })
+    }
+
+    // Why a variable is left out of the script, None if it is not
+    pub fn skip_reason(&self, name: &str) -> Option<String> {
+        let rule = self.rule(name)?;
+        Some(format!("{} ({})", rule.kind.reason(), rule.source))
+    }
+
+    // The kind of the rule leaving `name` out, None if none does
+    fn kind(&self, name: &str) -> Option<RuleKind> {
+        self.rule(name).map(|rule| rule.kind)
+    }
+
+    // Whether `name` is left out for being no fish variable name
+    pub fn rejects_name(&self, name: &str) -> bool {
+        self.kind(name) == Some(RuleKind::InvalidName)
+    }
+
+    // Whether `name` is left out as fish would refuse to set it
+    pub fn read_only(&self, name: &str) -> bool {
+        self.kind(name) == Some(RuleKind::ReadOnly)
+    }
+
+    pub fn ignores(&self, name: &str) -> bool {
+        self.skip_reason(name).is_some()
+    }
+
+    // One rule per line: kind, pattern and source, then the --allow
+    // exemptions
+    pub fn describe(&self) -> String {
+        let lines: Vec<(&str, String, &str)> = self
+            .rules
+            .iter()
+            .map(|rule| {
+                (
+                    rule.kind.name(),
+                    rule.pattern.to_string(),
+                    rule.source.as_str(),
+                )
+            })
+            .chain(
+                self.allowed
+                    .iter()
+                    .map(|pattern| ("allowed", pattern.to_string(), "--allow")),
+            )
+            .collect();
+        let width = lines
+            .iter()
+            .map(|(_, pattern, _)| pattern.len())
+            .max()
+            .unwrap_or(0);
+        lines
+            .iter()
+            .map(|(kind, pattern, source)| format!("{:<17} {:<width$} {}\n", kind, pattern, source))
+            .collect()
+    }
+}
@@ -0,0 +1,82 @@
+use std::io;
+
+use crate::capture::{Env, Snapshot};
+use crate::change::{self, Category, Change};
+use crate::compare::{self, AliasMode};
+use crate::config::Config;
+use crate::diff::EnvDiff;
+use crate::emitter::Fish;
+use crate::filter::Filter;
+use crate::policy::Policy;
+use crate::proxy::Definitions;
+use crate::scope::ScopeTracker;
+use crate::shell::Shell;
+
+// Fish code turning one state into another, what the basrs command prints
+// without any options, config or policy
+pub struct FishScript {
+    changes: Vec<Change>,
+    definitions: Option<Definitions>,
+}
+
+impl FishScript {
+    pub fn between(old: &Snapshot, new: &Snapshot) -> FishScript {
+        let policy = Policy::default();
+        let config = Config::default();
+        let filter = Filter::new(None, &config, &policy, &[]);
+        let (mut changes, mut skipped) = compare::process_env_changes(
+            &old.env,
+            &new.env,
+            &filter,
+            &config,
+            &policy,
+            &ScopeTracker::default(),
+        );
+        changes.extend(compare::process_alias_changes(
+            &old.aliases,
+            &new.aliases,
+            AliasMode::Alias,
+            "",
+        ));
+        let (func_changes, definitions) = compare::process_func_changes(
+            &old.functions,
+            &new.functions,
+            None,
+            Shell::Bash,
+            "",
+            &mut skipped,
+        );
+        changes.extend(func_changes);
+        for change in &mut changes {
+            change.emit(&Fish);
+        }
+        FishScript {
+            changes,
+            definitions,
+        }
+    }
+
+    // `diff` applied on top of `env`
+    pub fn from_diff(diff: &EnvDiff, env: &Env) -> FishScript {
+        let (old, new) = diff.apply(env.clone());
+        FishScript::between(&old, &new)
+    }
+
+    pub fn changes(&self) -> &[Change] {
+        &self.changes
+    }
+
+    // Saves the bash definitions the fish functions of the script load, which
+    // has to happen before it is sourced
+    pub fn save_functions(&self) -> io::Result<()> {
+        self.definitions.as_ref().map_or(Ok(()), Definitions::write)
+    }
+
+    pub fn render(&self) -> String {
+        let mut script = String::new();
+        for category in [Category::Variable, Category::Alias, Category::Function] {
+            change::render_into(&mut script, &self.changes, category, true);
+        }
+        script
+    }
+}
@@ -0,0 +1,327 @@
+use crate::capture;
+use crate::change::{Action, Category, Change, Skipped};
+use crate::compare::is_plain_command;
+use crate::emitter::{Fish, Nu, ShellEmitter};
+
+// Why a change with bytes that are not valid UTF-8 is left out
+pub const NOT_UTF8: &str = "values that are not valid UTF-8 cannot be represented";
+
+// What the generated output is meant for
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    // A script to source in fish
+    #[default]
+    Fish,
+    // A script to source in Nushell
+    Nu,
+    // An `EnvironmentFile=` for systemd units
+    SystemdEnv,
+    // A `docker run --env-file` file
+    DockerEnv,
+    // Lines to append to $GITHUB_ENV in GitHub Actions
+    GithubEnv,
+    // A `.env` file as docker-compose and most dotenv loaders read it
+    Dotenv,
+    // The diff itself, as read by `basrs emit --from-json`
+    Json,
+}
+
+impl Format {
+    pub const NAMES: &'static [&'static str] = &[
+        "fish",
+        "nu",
+        "systemd-env",
+        "docker-env",
+        "github-env",
+        "dotenv",
+        "json",
+    ];
+
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name {
+            "fish" => Some(Format::Fish),
+            "nu" => Some(Format::Nu),
+            "systemd-env" => Some(Format::SystemdEnv),
+            "docker-env" => Some(Format::DockerEnv),
+            "github-env" => Some(Format::GithubEnv),
+            "dotenv" => Some(Format::Dotenv),
+            "json" => Some(Format::Json),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Format::Fish => "fish",
+            Format::Nu => "nu",
+            Format::SystemdEnv => "systemd-env",
+            Format::DockerEnv => "docker-env",
+            Format::GithubEnv => "github-env",
+            Format::Dotenv => "dotenv",
+            Format::Json => "json",
+        }
+    }
+
+    // Writes the script lines of the changes, the env file formats only use
+    // the values
+    pub fn emitter(self) -> &'static dyn ShellEmitter {
+        match self {
+            Format::Nu => &Nu,
+            _ => &Fish,
+        }
+    }
+
+    // Whether the output is a script to source rather than a list of values
+    pub fn is_script(self) -> bool {
+        matches!(self, Format::Fish | Format::Nu)
+    }
+
+    // Drops the changes the format cannot express, recording why. Environment
+    // files only assign variables: nothing can be unset and there is no
+    // working directory, aliases, functions or arrays. JSON has everything but shell
+    // options, the umask, resource limits, the directory history and
+    // completions. Nushell gets variables, aliases running a command with
+    // plain words and the directory, the functions fish gets call back into
+    // basrs through fish. Only fish can be
+    // given values that are not valid UTF-8, Nushell strings can't hold them
+    // in lists, directories and aliases either.
+    pub fn retain_supported(self, changes: &mut Vec<Change>, skipped: &mut Vec<Skipped>) {
+        if self == Format::Fish {
+            return;
+        }
+        changes.retain(|change| {
+            let raw_bytes = change.value.as_deref().is_some_and(capture::has_raw_bytes)
+                || self == Format::Nu
+                    && change
+                        .actions
+                        .iter()
+                        .flat_map(|action| action.emit(&Nu))
+                        .any(|line| capture::has_raw_bytes(&line));
+            let reason = if raw_bytes {
+                eprintln!(
+                    "Basrs warning: {} left out, its value is not valid UTF-8, which {} output cannot hold",
+                    change.name,
+                    self.name()
+                );
+                NOT_UTF8
+            } else if self == Format::Nu {
+                if change.category == Category::Alias && !change.actions.iter().all(plain_alias) {
+                    // Nushell reads the whole script first, one alias it can't
+                    // parse and nothing in it runs
+                    "only aliases running a command with plain words carry over"
+                } else if matches!(change.category, Category::Variable | Category::Alias) {
+                    return true;
+                } else {
+                    "only variables, aliases and the directory carry over"
+                }
+            } else if self == Format::Json {
+                if !matches!(change.category, Category::Setting | Category::Completion) {
+                    return true;
+                }
+                "shell state other than variables is not part of the diff"
+            } else if change.category != Category::Variable {
+                "only variables can be set"
+            } else if change.erases {
+                "cannot be unset"
+            } else if change.name == "PWD" {
+                "the working directory cannot be changed"
+            } else if change.value.is_none() {
+                "bash arrays cannot be represented"
+            } else if self == Format::DockerEnv
+                && change.value.as_ref().is_some_and(|v| v.contains('\n'))
+            {
+                // Docker takes values verbatim up to the end of the line, losing
+                // such a variable silently would be worse than the noise
+                eprintln!(
+                    "Basrs warning: {} left out, docker env files cannot hold values with newlines",
+                    change.name
+                );
+                "values with newlines cannot be represented"
+            } else {
+                return true;
+            };
+            skipped.push(Skipped {
+                category: change.category,
+                name: change.name.clone(),
+                reason: format!("{} in {} output", reason, self.name()),
+            });
+            false
+        });
+    }
+
+    // Output of the variable assignments for the env file formats
+    pub fn render(self, changes: &[Change]) -> String {
+        let mut output = String::new();
+        for change in changes {
+            let value = change.value.as_deref().unwrap_or_default();
+            let line = match self {
+                Format::Fish | Format::Nu | Format::Json => change.render().join("\n"),
+                Format::SystemdEnv => format!("{}={}", change.name, systemd_quote(value)),
+                // No quoting or escaping at all
+                Format::DockerEnv => format!("{}={}", change.name, value),
+                Format::GithubEnv => github_assignment(&change.name, value),
+                Format::Dotenv => format!("{}={}", change.name, dotenv_quote(value)),
+            };
+            output.push_str(&line);
+            output.push('\n');
+        }
+        output
+    }
+}
+
+// Whether the action is no alias definition or one Nushell can take
+fn plain_alias(action: &Action) -> bool {
+    match action {
+        Action::DefineAlias { value, .. }
+        | Action::DefineAbbr { value, .. }
+        | Action::DefineAliasFunction { value, .. } => is_plain_command(value),
+        _ => true,
+    }
+}
+
+// Double quotes keep whitespace and newlines, backslashes escape the rest
+fn systemd_quote(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | '`' | '$') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+// Bare where nothing needs quoting, in single quotes, which keep everything as
+// it is, unless the value has a quote or a newline, else in double quotes with
+// backslash escapes and `\n` for newlines
+fn dotenv_quote(value: &str) -> String {
+    let bare = |c: char| c.is_ascii_alphanumeric() || "_-./:@%+,".contains(c);
+    if value.chars().all(bare) {
+        return value.to_string();
+    }
+    if !value.contains(['\'', '\n']) {
+        return format!("'{}'", value);
+    }
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '\n' => quoted.push_str("\\n"),
+            '"' | '\\' | '`' | '$' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// Multiline values use the heredoc syntax, with a delimiter that doesn't occur
+// in the value so its content can't end the block early
+fn github_assignment(name: &str, value: &str) -> String {
+    if !value.contains(['\n', '\r']) {
+        return format!("{}={}", name, value);
+    }
+    let mut delimiter = String::from("BASRS_EOF");
+    while value.contains(&delimiter) {
+        delimiter.push('_');
+    }
+    format!("{}<<{}\n{}\n{}", name, delimiter, value, delimiter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Format;
+    use crate::change::{Action, Category, Change};
+
+    fn variable(name: &str, value: Option<&str>, erases: bool) -> Change {
+        Change {
+            category: Category::Variable,
+            name: name.to_string(),
+            comment: None,
+            actions: Vec::new(),
+            lines: Vec::new(),
+            value: value.map(str::to_string),
+            erases,
+        }
+    }
+
+    fn alias(value: &str) -> Change {
+        Change {
+            category: Category::Alias,
+            name: "ll".to_string(),
+            comment: None,
+            actions: vec![Action::DefineAlias {
+                name: "ll".to_string(),
+                value: value.to_string(),
+            }],
+            lines: Vec::new(),
+            value: None,
+            erases: false,
+        }
+    }
+
+    fn kept(format: Format, mut changes: Vec<Change>) -> (Vec<String>, Vec<String>) {
+        let mut skipped = Vec::new();
+        format.retain_supported(&mut changes, &mut skipped);
+        (
+            changes.into_iter().map(|c| c.name).collect(),
+            skipped.into_iter().map(|s| s.reason).collect(),
+        )
+    }
+
+    #[test]
+    fn env_files_only_assign() {
+        let changes = || {
+            vec![
+                variable("A", Some("1"), false),
+                variable("GONE", None, true),
+                variable("PWD", Some("/"), false),
+                variable("ARRAY", None, false),
+                alias("ls -l"),
+            ]
+        };
+        let (names, reasons) = kept(Format::Dotenv, changes());
+        assert_eq!(names, ["A"]);
+        assert_eq!(
+            reasons,
+            [
+                "cannot be unset in dotenv output",
+                "the working directory cannot be changed in dotenv output",
+                "bash arrays cannot be represented in dotenv output",
+                "only variables can be set in dotenv output",
+            ]
+        );
+        assert_eq!(kept(Format::Fish, changes()).0.len(), 5);
+    }
+
+    // Nushell could not parse the script at all with such an alias in it
+    #[test]
+    fn nu_keeps_plain_aliases() {
+        let (names, reasons) = kept(Format::Nu, vec![alias("ls -l"), alias("ls | less")]);
+        assert_eq!(names, ["ll"]);
+        assert_eq!(reasons.len(), 1);
+    }
+
+    #[test]
+    fn values_are_quoted_per_format() {
+        let changes = [
+            variable("A", Some("plain"), false),
+            variable("B", Some("a $b"), false),
+            variable("C", Some("it's\n"), false),
+        ];
+        let render = |format: Format| format.render(&changes);
+        assert_eq!(render(Format::Dotenv), "A=plain\nB='a $b'\nC=\"it's\\n\"\n");
+        assert_eq!(
+            render(Format::SystemdEnv),
+            "A=\"plain\"\nB=\"a \\$b\"\nC=\"it's\n\"\n"
+        );
+        assert_eq!(
+            render(Format::GithubEnv),
+            "A=plain\nB=a $b\nC<<BASRS_EOF\nit's\n\nBASRS_EOF\n"
+        );
+        assert_eq!(render(Format::DockerEnv), "A=plain\nB=a $b\nC=it's\n\n");
+    }
+}
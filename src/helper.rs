@@ -0,0 +1,27 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::process;
+
+use crate::dirs;
+use crate::shell::Shell;
+
+// Installs the capture script of `shell` under the data directory, rewriting
+// it when missing or different from the one this basrs ships
+pub fn install(shell: Shell) -> io::Result<PathBuf> {
+    let dir = dirs::data_dir()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No data directory"))?;
+    let path = dir.join(shell.helper_name());
+    let script = shell.capture_script();
+    if fs::read(&path).is_ok_and(|installed| installed == script.as_bytes()) {
+        return Ok(path);
+    }
+    fs::create_dir_all(&dir)?;
+    // Written aside and renamed so a concurrent basrs never runs half a script
+    let partial = dir.join(format!(".{}.{}", shell.helper_name(), process::id()));
+    fs::write(&partial, script)?;
+    fs::rename(&partial, &path).inspect_err(|_| {
+        let _ = fs::remove_file(&partial);
+    })?;
+    Ok(path)
+}
@@ -0,0 +1,75 @@
+use std::io;
+
+use crate::capture;
+use crate::escape;
+
+// Bash tools like rvm or nvm's auto-use switch versions from a `cd` wrapper or
+// PROMPT_COMMAND, neither of which fish ever runs. The handler reruns the
+// command on each directory change and then triggers both hooks by hand, so
+// the switching keeps working in fish. The extra cd leaves OLDPWD alone.
+pub fn pwd_handler(command: &str, label: &str) -> String {
+    let hook_command = format!(
+        "{}\n__basrs_oldpwd=$OLDPWD; cd \"$PWD\" && OLDPWD=$__basrs_oldpwd && \
+         for __basrs_hook in \"${{PROMPT_COMMAND[@]}}\"; do eval \"$__basrs_hook\"; done",
+        command
+    );
+    // Named after the command, so sourcing the output again replaces the
+    // handler instead of adding another one
+    format!(
+        "function __basrs_pwd_hook_{:x} --on-variable PWD --description {}
+    command basrs --fish-dump (set --show | psub) --inherit-fish-env (env -0 | psub) -- {} | source
+end",
+        capture::hash(command.as_bytes()),
+        escape(&format!("Directory hooks of {} (through basrs)", label)),
+        escape(&hook_command)
+    )
+}
+
+// Applies the `.basrsrc` of the directory entered or its nearest parent that
+// has one, and reverses it through an undo file once the shell leaves that
+// directory. Only directories in the universal `basrs_allowed_dirs` list are
+// trusted, a cloned repository must not run code just by being entered.
+const FISH_DIR_HOOK: &str = r#"function __basrs_dir_hook --on-variable PWD --description "Apply the .basrsrc of the current directory (through basrs)"
+    # Sourcing an undo file changing directory comes back here
+    set -q __basrs_dir_busy; and return
+    set -l rc
+    set -l dir $PWD
+    while true
+        if test -f $dir/.basrsrc; and contains -- $dir $basrs_allowed_dirs
+            set rc $dir/.basrsrc
+            break
+        end
+        test $dir = /; and break
+        set dir (path dirname $dir)
+    end
+    test "$rc" = "$__basrs_dir_rc"; and return
+    set -g __basrs_dir_busy
+    if set -q __basrs_dir_undo
+        source $__basrs_dir_undo
+        command rm -f $__basrs_dir_undo
+        set -e __basrs_dir_rc __basrs_dir_undo
+    end
+    if test -n "$rc"
+        set -g __basrs_dir_rc $rc
+        set -g __basrs_dir_undo (command mktemp)
+        # Back in $PWD afterwards, undoing a directory change would cd back in
+        command basrs --fish-dump (set --show | psub) --inherit-fish-env (env -0 | psub) --undo-file $__basrs_dir_undo source $rc ';' builtin cd -- $PWD | source
+    end
+    set -e __basrs_dir_busy
+end
+__basrs_dir_hook
+"#;
+
+// `basrs hook fish | source` in config.fish
+pub fn dir_hook(shell: &str) -> io::Result<String> {
+    match shell {
+        "fish" => Ok(format!(
+            "# basrs directory hook, generated by `basrs hook fish`\n{}",
+            FISH_DIR_HOOK
+        )),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Unsupported shell {} (available: fish)", shell),
+        )),
+    }
+}
@@ -0,0 +1,87 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::dirs;
+
+// Version of the interface between the binary and the fish function calling
+// it, bumped whenever older functions would call it wrong. The function
+// passes the version it was generated for with --wrapper-protocol.
+pub const PROTOCOL: u32 = 1;
+
+const FISH_FUNCTION: &str = r#"function bass --description "Run a bash command and apply its environment changes"
+    # The variable dump lets removals erase the scope fish actually has them in,
+    # the environment dump makes bash start from exactly what fish exports
+    command basrs --wrapper-protocol 1 --fish-dump (set --show | psub) --inherit-fish-env (env -0 | psub) $argv | source
+    # basrs failing itself wins over the status of the command
+    set -l statuses $pipestatus
+    test $statuses[1] -ne 0; and return $statuses[1]
+    return $statuses[2]
+end
+"#;
+
+const FISH_COMPLETION: &str = "complete -c bass -x -a \"(__fish_complete_subcommand)\"\n";
+
+// Warns when the fish function and the binary disagree, usually after an
+// upgrade of basrs without regenerating the function
+pub fn check_protocol(version: u32) {
+    if version < PROTOCOL {
+        eprintln!(
+            "Basrs warning: the bass function is older than this basrs, regenerate it \
+             with `basrs shell-init fish` or `basrs install`"
+        );
+    } else if version > PROTOCOL {
+        eprintln!("Basrs warning: the bass function is newer than this basrs, upgrade basrs");
+    }
+}
+
+// `basrs shell-init fish | source` in config.fish
+pub fn shell_init(shell: &str) -> io::Result<String> {
+    match shell {
+        "fish" => Ok(format!(
+            "# basrs shell integration, generated by `basrs shell-init fish`\n{}\n{}",
+            FISH_FUNCTION, FISH_COMPLETION
+        )),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Unsupported shell {} (available: fish)", shell),
+        )),
+    }
+}
+
+// The autoloaded `functions/bass.fish`, also what `basrs install --print` shows
+pub fn fish_function_file() -> String {
+    format!("# Generated by `basrs install`\n{}", FISH_FUNCTION)
+}
+
+// Writes the bass function and its completions where fish autoloads them,
+// returning the files written
+pub fn install() -> io::Result<Vec<PathBuf>> {
+    let dir = dirs::fish_config_dir().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            "Cannot find the fish configuration directory, HOME is not set",
+        )
+    })?;
+    let files = [
+        (
+            dir.join("functions").join("bass.fish"),
+            fish_function_file(),
+        ),
+        (
+            dir.join("completions").join("bass.fish"),
+            format!("# Generated by `basrs install`\n{}", FISH_COMPLETION),
+        ),
+    ];
+    let mut written = Vec::new();
+    for (path, content) in files {
+        let parent = path.parent().expect("fish file has a directory");
+        fs::create_dir_all(parent)
+            .and_then(|_| fs::write(&path, content))
+            .map_err(|e| {
+                io::Error::new(e.kind(), format!("Cannot write {}: {}", path.display(), e))
+            })?;
+        written.push(path);
+    }
+    Ok(written)
+}
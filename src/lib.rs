@@ -0,0 +1,340 @@
+// Runs a bash command, diffs the shell state before and after and turns the
+// difference into fish code. The basrs binary is a thin wrapper around `cli`,
+// other programs can use the capture, diff and emit steps on their own.
+
+mod backup;
+mod cache;
+pub mod capture;
+pub mod change;
+mod child;
+pub mod cli;
+mod collision;
+mod compare;
+mod config;
+mod confirm;
+mod cygpath;
+#[cfg(all(unix, feature = "daemon"))]
+mod daemon;
+pub mod diff;
+mod dirs;
+mod emitter;
+pub mod error;
+mod fast_path;
+mod filter;
+pub mod fish;
+mod format;
+mod helper;
+mod hook;
+mod init;
+mod logging;
+mod pager;
+mod pipeline;
+mod policy;
+mod preset;
+mod progress;
+mod proxy;
+#[cfg(all(unix, feature = "pty"))]
+mod pty;
+mod scope;
+mod shell;
+#[cfg(unix)]
+mod signals;
+mod snapshot_file;
+mod ssh;
+mod summary;
+mod undo;
+mod wsl;
+
+pub use capture::{Capture, Env, Runner, Snapshot as EnvSnapshot, StderrMode};
+pub use change::{Action, Category, Change};
+pub use diff::{AliasDiff, EnvDiff};
+pub use fish::FishScript;
+pub use shell::Shell;
+
+use std::borrow::Cow;
+
+// Quotes a string as a single fish word. Inside single quotes fish only
+// interprets \\ and \', control characters go between the quotes as \xHH
+// escapes so the script stays readable and line-based tools don't trip.
+pub fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    let mut quoted = false;
+    for c in value.chars() {
+        // Bytes that are not UTF-8 go out as they came in, fish reads \xHH
+        // above 7f as a raw byte
+        let byte = match c.is_ascii_control() {
+            true => Some(c as u8),
+            false => capture::raw_byte(c),
+        };
+        if let Some(byte) = byte {
+            if quoted {
+                escaped.push('\'');
+                quoted = false;
+            }
+            escaped.push_str(&format!("\\x{:02x}", byte));
+            continue;
+        }
+        if !quoted {
+            escaped.push('\'');
+            quoted = true;
+        }
+        if c == '\\' || c == '\'' {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    if quoted {
+        escaped.push('\'');
+    } else if escaped.is_empty() {
+        escaped.push_str("''");
+    }
+    escaped
+}
+
+// Captured text for a `#` comment of the script, control characters and raw
+// bytes as \xHH escapes, so that a value holding a line break cannot end the
+// comment and run as code
+pub fn escape_comment(text: &str) -> Cow<'_, str> {
+    let byte = |c: char| match c.is_ascii_control() {
+        true => Some(c as u8),
+        false => capture::raw_byte(c),
+    };
+    if !text.chars().any(|c| byte(c).is_some()) {
+        return Cow::Borrowed(text);
+    }
+    let mut escaped = String::with_capacity(text.len() + 8);
+    for c in text.chars() {
+        match byte(c) {
+            Some(byte) => escaped.push_str(&format!("\\x{:02x}", byte)),
+            None => escaped.push(c),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::escape;
+    use std::process::Command;
+
+    const NASTY: &[&str] = &[
+        "",
+        "plain",
+        "with space",
+        "single ' quote",
+        "double \" quote",
+        "back\\slash",
+        "trailing backslash\\",
+        "\\'",
+        "$HOME and $(echo no) and (echo no)",
+        "`backticks`",
+        "line\nbreak\n",
+        "\ttab\rreturn\x1b[0mescape\x7f",
+        "glob * ? [a] {b,c} ~",
+        "; and | & < > # comment",
+        "unicode é ✓ 🐟",
+    ];
+
+    // Reads a word back the way fish does for the subset escape produces
+    fn unescape(word: &str) -> String {
+        let mut value = String::new();
+        let mut chars = word.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\'' => loop {
+                    match chars.next().expect("unterminated quote") {
+                        '\'' => break,
+                        '\\' => value.push(chars.next().expect("dangling backslash")),
+                        c => value.push(c),
+                    }
+                },
+                '\\' => {
+                    assert_eq!(chars.next(), Some('x'));
+                    let hex: String = chars.by_ref().take(2).collect();
+                    value.push(u8::from_str_radix(&hex, 16).unwrap() as char);
+                }
+                c => panic!("unquoted {:?} in {}", c, word),
+            }
+        }
+        value
+    }
+
+    #[test]
+    fn escape_round_trips() {
+        for value in NASTY {
+            assert_eq!(unescape(&escape(value)), *value, "{}", escape(value));
+        }
+    }
+
+    #[test]
+    fn escape_is_one_line() {
+        for value in NASTY {
+            assert!(!escape(value).contains(|c: char| c.is_ascii_control()));
+        }
+    }
+
+    #[test]
+    fn escape_quotes_simply() {
+        assert_eq!(escape(""), "''");
+        assert_eq!(escape("a b"), "'a b'");
+        assert_eq!(escape("it's"), "'it\\'s'");
+        assert_eq!(escape("a\nb"), "'a'\\x0a'b'");
+        assert_eq!(escape("\n"), "\\x0a");
+    }
+
+    // Bytes that are not UTF-8 survive decoding and reach fish as the same
+    // bytes, fish reading them back is only checked where it is installed
+    #[test]
+    fn raw_bytes_round_trip() {
+        let bytes = b"caf\xe9 \xff\xfe\x80 \xc3\xa9";
+        let value = crate::capture::decode(bytes);
+        assert!(crate::capture::has_raw_bytes(&value));
+        assert_eq!(crate::capture::encode(&value), &bytes[..]);
+        assert_eq!(escape(&value), "'caf'\\xe9' '\\xff\\xfe\\x80' é'");
+        assert!(!crate::capture::has_raw_bytes(&crate::capture::decode(
+            "é ✓".as_bytes()
+        )));
+        let Some(fish) = crate::shell::find_program("fish") else {
+            return;
+        };
+        let output = Command::new(&fish)
+            .arg("--no-config")
+            .arg("-c")
+            .arg(format!("printf %s {}", escape(&value)))
+            .output()
+            .unwrap();
+        assert_eq!(output.stdout, bytes);
+    }
+
+    // Only runs where fish is installed
+    #[test]
+    fn escape_round_trips_through_fish() {
+        let Some(fish) = crate::shell::find_program("fish") else {
+            return;
+        };
+        for value in NASTY {
+            let output = Command::new(&fish)
+                .arg("--no-config")
+                .arg("-c")
+                .arg(format!("printf %s {}", escape(value)))
+                .output()
+                .unwrap();
+            assert_eq!(String::from_utf8_lossy(&output.stdout), *value);
+        }
+    }
+
+    // What bash prints for aliases with every one of the values reads back
+    // as the same values
+    #[test]
+    fn aliases_round_trip_through_bash() {
+        let Some(bash) = crate::shell::find_program("bash") else {
+            return;
+        };
+        let definitions: String = NASTY
+            .iter()
+            .enumerate()
+            .map(|(i, value)| format!("alias a{}={}\n", i, crate::preset::bash_quote(value)))
+            .collect();
+        let output = Command::new(&bash)
+            .arg("-c")
+            .arg(format!("{}alias", definitions))
+            .output()
+            .unwrap();
+        let output = String::from_utf8_lossy(&output.stdout);
+        let mut aliases = crate::compare::parse_aliases(&output);
+        aliases.sort_by_key(|(name, _)| name[1..].parse::<usize>().unwrap());
+        let values: Vec<&str> = aliases.iter().map(|(_, value)| value.as_ref()).collect();
+        assert_eq!(values, NASTY);
+    }
+
+    #[test]
+    fn aliases_parse_zsh_quoting() {
+        let output = "alias ll='ls -l'\nalias g=git\nalias q='it'\\''s'\n\
+                      alias e=$'a\\tb\\x41\\n'\nalias 'we=ird'=\"x\\\"y\"\n";
+        let aliases = crate::compare::parse_aliases(output);
+        let aliases: Vec<(&str, &str)> = aliases
+            .iter()
+            .map(|(name, value)| (name.as_ref(), value.as_ref()))
+            .collect();
+        assert_eq!(
+            aliases,
+            [
+                ("ll", "ls -l"),
+                ("g", "git"),
+                ("q", "it's"),
+                ("e", "a\tbA\n"),
+                ("we=ird", "x\"y"),
+            ]
+        );
+    }
+
+    // Captured values end up in the comments too, none of them may break out
+    // of one and run as code
+    #[test]
+    fn comments_hold_no_control_characters() {
+        let env = |pairs: &[(&'static str, &'static str)]| crate::EnvSnapshot {
+            env: pairs
+                .iter()
+                .map(|(k, v)| ((*k).into(), (*v).into()))
+                .collect(),
+            ..Default::default()
+        };
+        let mut old = vec![("FOO", "old"), ("GONE\n1", "1"), ("PWD", "/")];
+        let mut new = vec![("FOO", "x\necho PWNED #"), ("PWD", "/gone\nrm -rf ~ #")];
+        for (i, value) in NASTY.iter().enumerate() {
+            let name: &'static str = Box::leak(format!("V{}", i).into_boxed_str());
+            old.push((name, "before\n#"));
+            new.push((name, value));
+        }
+        let script = crate::FishScript::between(&env(&old), &env(&new)).render();
+        assert!(script.contains("# Updating FOO: 'old' -> 'x\\x0aecho PWNED #'"));
+        let dirs = crate::change::Action::DirStack(vec!["/a\nb".to_string()]);
+        let lines = [
+            dirs.emit(&crate::emitter::Nu),
+            script.lines().map(str::to_string).collect(),
+        ];
+        for line in lines.concat() {
+            assert!(!line.contains(|c: char| c.is_ascii_control()), "{:?}", line);
+            assert!(
+                !line.starts_with("echo") && !line.starts_with("rm"),
+                "{:?}",
+                line
+            );
+        }
+    }
+
+    // What the fast path computes is what bash, as --no-fast-path runs it,
+    // ends up with
+    #[test]
+    fn fast_path_agrees_with_bash() {
+        let Some(bash) = crate::shell::find_program("bash") else {
+            return;
+        };
+        let start = [("X", "old"), ("Y", "z"), ("PATH", "/bin")];
+        let env: crate::Env = start
+            .iter()
+            .map(|(k, v)| ((*k).into(), (*v).into()))
+            .collect();
+        for command in [
+            "X=new Y=$X",
+            "export X=new Y=$X",
+            "X=new\nexport Y=\"$X:$PATH\"",
+            "A=1 B=$A\nexport C=$B X=$A",
+            "export X='a b' Y=\"${X}c\" # comment",
+        ] {
+            let fast = crate::fast_path::apply(command, &env).expect(command);
+            let output = Command::new(&bash)
+                .env_clear()
+                .envs(start)
+                .arg("-c")
+                .arg(format!("{}\nenv -0", command))
+                .output()
+                .unwrap();
+            // What bash sets on its own start
+            let slow = crate::capture::parse_dump(&output.stdout, true, &|name| {
+                !matches!(name, b"PWD" | b"SHLVL" | b"_")
+            });
+            assert_eq!(fast, slow, "{}", command);
+        }
+    }
+}
@@ -0,0 +1,41 @@
+#[cfg(feature = "log")]
+use std::env;
+#[cfg(feature = "log")]
+use std::io::{self, IsTerminal};
+
+#[cfg(feature = "log")]
+use tracing_subscriber::fmt::format::FmtSpan;
+#[cfg(feature = "log")]
+use tracing_subscriber::EnvFilter;
+
+// Sends the tracing events and the capture, parse, diff and emit spans to
+// stderr when BASRS_LOG is set, to a filter like RUST_LOG takes: `debug`, or
+// `basrs::capture=trace` for one module. Closed spans report how long they
+// took. BASRS_LOG_FORMAT=json prints one JSON object per line instead, for
+// attaching to bug reports.
+#[cfg(feature = "log")]
+pub fn init() {
+    let Some(directives) = env::var("BASRS_LOG").ok().filter(|v| !v.is_empty()) else {
+        return;
+    };
+    let filter = EnvFilter::try_new(&directives).unwrap_or_else(|e| {
+        eprintln!(
+            "Basrs warning: BASRS_LOG={} is not a valid filter: {}",
+            directives, e
+        );
+        EnvFilter::new("debug")
+    });
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(io::stderr)
+        .with_span_events(FmtSpan::CLOSE);
+    if env::var("BASRS_LOG_FORMAT").is_ok_and(|f| f == "json") {
+        builder.json().init();
+    } else {
+        builder.with_ansi(io::stderr().is_terminal()).init();
+    }
+}
+
+// Leaving out the `log` feature leaves the events without a subscriber
+#[cfg(not(feature = "log"))]
+pub fn init() {}
@@ -0,0 +1,74 @@
+use std::env;
+use std::io::{self, IsTerminal, Write};
+use std::process::{Command, Stdio};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PagerMode {
+    // Page reports that don't fit the terminal
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl PagerMode {
+    pub fn from_name(name: &str) -> Option<PagerMode> {
+        match name {
+            "auto" => Some(PagerMode::Auto),
+            "always" => Some(PagerMode::Always),
+            "never" => Some(PagerMode::Never),
+            _ => None,
+        }
+    }
+}
+
+// Writes a report to stderr, through $PAGER when it is long. stdout is left
+// alone, it may well be piped into `source`.
+pub fn show(report: &str, mode: PagerMode) -> io::Result<()> {
+    let page = match mode {
+        PagerMode::Never => false,
+        PagerMode::Always => true,
+        PagerMode::Auto => io::stderr().is_terminal() && report.lines().count() > terminal_rows(),
+    };
+    if page {
+        match spawn_pager(report) {
+            Ok(()) => return Ok(()),
+            Err(e) => eprintln!("Basrs warning: could not run pager: {}", e),
+        }
+    }
+    io::stderr().write_all(report.as_bytes())
+}
+
+fn terminal_rows() -> usize {
+    env::var("LINES")
+        .ok()
+        .and_then(|rows| rows.parse().ok())
+        .unwrap_or(24)
+}
+
+fn spawn_pager(report: &str) -> io::Result<()> {
+    let pager = env::var("PAGER")
+        .ok()
+        .filter(|pager| !pager.is_empty())
+        .unwrap_or_else(|| "less".to_string());
+    let mut command = Command::new("sh");
+    command
+        .arg("-c")
+        .arg(&pager)
+        .stdin(Stdio::piped())
+        .stdout(io::stderr());
+    // Same defaults git uses: quit on short input, keep colors and the screen
+    if env::var_os("LESS").is_none() {
+        command.env("LESS", "FRX");
+    }
+    let mut child = command.spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // The user quitting the pager early closes the pipe, that is fine
+        match stdin.write_all(report.as_bytes()) {
+            Err(e) if e.kind() != io::ErrorKind::BrokenPipe => return Err(e),
+            _ => {}
+        }
+    }
+    child.wait()?;
+    Ok(())
+}
@@ -0,0 +1,1351 @@
+use std::env;
+use std::fs;
+use std::io::{self, Read};
+use std::mem;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::backup;
+use crate::cache::{BaselineCache, OutputCache};
+use crate::capture::{self, Capture, Env, Runner, Snapshot, StderrMode};
+use crate::change::{self, Action, Category, Change, Skipped};
+use crate::collision::{self, CollisionMode};
+use crate::compare::{
+    is_locale_var, path_edits, process_alias_changes, process_array_changes,
+    process_completion_changes, process_directory_changes, process_env_changes,
+    process_exported_functions, process_func_changes, process_limit_changes,
+    process_setting_changes, process_umask_change, AliasMode, ExportedFunctionMode, LocaleMode,
+    PathMode, ASSOCIATIVE_ARRAY,
+};
+use crate::config::{self, Config};
+use crate::confirm;
+use crate::cygpath::{self, PathTranslate};
+use crate::diff::EnvDiff;
+use crate::fast_path;
+use crate::filter::{self, sanitize_name, Filter, Pattern, Selection};
+use crate::format::{Format, NOT_UTF8};
+use crate::hook;
+use crate::pager::PagerMode;
+use crate::policy::Policy;
+use crate::preset::{self, Preset, PresetContext};
+use crate::progress::{self, Progress};
+use crate::scope::{Scope, ScopeTracker};
+use crate::shell::Shell;
+use crate::snapshot_file::SnapshotFile;
+use crate::ssh;
+use crate::summary::{self, ColorMode};
+use crate::undo;
+use crate::wsl;
+use crate::{escape, escape_comment};
+
+// Everything one invocation of basrs was asked to do
+#[derive(Default)]
+pub struct Options {
+    pub config: Option<PathBuf>,
+    // A profile of the config, given with --profile
+    pub profile: Option<String>,
+    // Output of `set --show` from the calling fish session
+    pub fish_dump: Option<String>,
+    pub preset: Option<&'static Preset>,
+    // Let the preset hook into the fish prompt
+    pub prompt: bool,
+    pub no_cache: bool,
+    // Reuse the script generated for the same content of the sourced file
+    pub cache_output: bool,
+    // The file of `basrs source`
+    pub sourced: Option<PathBuf>,
+    // Capture the baseline anew, replacing the cached one
+    pub refresh_baseline: bool,
+    // Always run the command in bash
+    pub no_fast_path: bool,
+    pub shell: Shell,
+    pub format: Format,
+    // Show the changes on stderr instead of emitting the script
+    pub preview: bool,
+    pub pager: PagerMode,
+    // Where to write the script instead of stdout
+    pub output: Option<PathBuf>,
+    // Ask about each change before including it
+    pub interactive: bool,
+    // Explain on stderr why changes were left out
+    pub verbose: bool,
+    pub status_var: bool,
+    // Print the filtering rules instead of running anything
+    pub list_ignored: bool,
+    // Globs restricting which variables are captured at all
+    pub filters: Vec<String>,
+    // Variables left out of the script, given with --ignore
+    pub ignored: Vec<Pattern>,
+    // Drop the built-in ignored and read-only lists, as the config's
+    // replace_builtin_lists does
+    pub no_default_ignores: bool,
+    // Exempt from the built-in lists, given with --allow
+    pub allowed: Vec<Pattern>,
+    // Set as path lists, given with --path-var
+    pub path_vars: Vec<Pattern>,
+    // Drop repeated entries of path lists
+    pub dedupe_path: bool,
+    // Guard the lines so that sourcing the script twice does nothing new
+    pub idempotent: bool,
+    // Convert the paths bash leaves behind, for MSYS2 and Cygwin
+    pub path_translate: PathTranslate,
+    // Run bash in this WSL distribution, the default one when empty
+    pub wsl: Option<String>,
+    // Run bash on this host over ssh, given with `basrs ssh`
+    pub ssh: Option<String>,
+    // Leave out variables describing the host, like HOSTNAME and SSH_*
+    pub skip_host_vars: bool,
+    // Restricts the script to these, given with --only
+    pub only: Vec<Selection>,
+    // Exit with 0 whatever the status of the command
+    pub exit_zero: bool,
+    // Apply what the command changed before it ended the shell
+    pub keep_going: bool,
+    // Exit with 1 when there is nothing to apply
+    pub fail_if_unchanged: bool,
+    // Entries of the from-env dump end with NUL instead of a newline
+    pub null_data: bool,
+    pub policy: Option<PathBuf>,
+    // Command wrapping the capture, overriding the config
+    pub wrap: Option<Vec<String>>,
+    // `env -0` dump of the calling fish session
+    pub inherit_env: Option<String>,
+    // Diff variables against that dump rather than the state bash starts in
+    pub fish_baseline: bool,
+    // Run both captures in an empty environment without startup files, the
+    // way `env -i bash --noprofile --norc` does, and take that as the current
+    // environment elsewhere
+    pub clean_env: bool,
+    // Rerun the command's directory hooks whenever fish changes directory
+    pub on_cd: bool,
+    // Interface version of the fish function running basrs
+    pub wrapper_protocol: Option<u32>,
+    // Commands given with -c, run one after the other until one fails
+    pub commands: Vec<String>,
+    // How long background jobs started by the command get to finish
+    pub wait_for_jobs: Duration,
+    // Save the fish functions and aliases the script redefines
+    pub backup_functions: bool,
+    // What happens to aliases and functions shadowing a fish command
+    pub on_collision: CollisionMode,
+    // Names of the fish functions and builtins, fish is asked without them
+    pub fish_commands: Option<String>,
+    // Where to write the fish script reverting the output
+    pub undo_file: Option<PathBuf>,
+    // Annotate variable changes with the file and line that made them
+    pub provenance: bool,
+    // Also capture the variables the shell does not export, set without -x
+    pub all_vars: bool,
+    pub path_mode: PathMode,
+    // Scope of the variables the policy doesn't give one, overriding the
+    // profile's
+    pub scope: Option<Scope>,
+    // Run the command on a pseudo-terminal, for installers and other prompts
+    pub pty: bool,
+    // Start the shell as a login or interactive shell, for both captures
+    pub login: bool,
+    pub interactive_shell: bool,
+    // Passed to the shell as they are, given with --shell-arg
+    pub shell_args: Vec<String>,
+    pub stderr: StderrMode,
+    // Kill the command when it runs longer
+    pub timeout: Option<Duration>,
+    // Overrides the alias mode of the profile
+    pub alias_mode: Option<AliasMode>,
+    pub exported_functions: ExportedFunctionMode,
+    // Put in front of the names of the aliases and functions defined in fish
+    pub prefix: Option<String>,
+    // Overrides the locale policy of the config
+    pub locale_policy: Option<LocaleMode>,
+    // Print the command given to the shell, the raw captures and timings
+    pub debug: bool,
+    // Leave comments and blank lines out of the script
+    pub no_comments: bool,
+    // Set variables fish cannot name with the rejected characters replaced
+    pub sanitize_names: bool,
+    // Count and list the changes on stderr
+    pub summary: bool,
+    // Print timings and change counts on stderr
+    pub stats: bool,
+    // Describe the changes in comments only, nothing in the script applies
+    pub dry_run: bool,
+    pub color: ColorMode,
+}
+
+pub struct Generated {
+    pub script: String,
+    // The diff was empty
+    pub unchanged: bool,
+    // Exit status of the command, 0 when none ran
+    pub status: i32,
+}
+
+pub fn gen_script(options: &Options, args: &[String]) -> io::Result<Generated> {
+    let commands = commands(options, args)?;
+    let command = match options.preset {
+        Some(_) if !options.commands.is_empty() => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "-c cannot be combined with a preset",
+            ))
+        }
+        Some(preset) => (preset.command)(args)?,
+        None => chain(&commands),
+    };
+    if options.on_cd && (options.shell != Shell::Bash || options.format != Format::Fish) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--on-cd needs bash and fish output",
+        ));
+    }
+    if options.pty && options.stderr != StderrMode::Inherit {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--stderr cannot be combined with --pty, the output goes to the terminal",
+        ));
+    }
+    if options.provenance && options.shell != Shell::Bash {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--provenance needs bash",
+        ));
+    }
+    if options.ssh.is_some() && (options.wsl.is_some() || options.fish_baseline) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "ssh cannot be combined with --wsl or --fish-baseline, the remote environment is its own",
+        ));
+    }
+    if options.fish_baseline && options.inherit_env.is_none() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--fish-baseline needs --inherit-fish-env",
+        ));
+    }
+    if options.fish_baseline && (options.login || options.interactive_shell) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--fish-baseline cannot be combined with --login or --interactive-shell, \
+             what the startup files set would be changes",
+        ));
+    }
+    if options.clean_env
+        && (options.fish_baseline
+            || options.login
+            || options.interactive_shell
+            || options.wsl.is_some()
+            || options.ssh.is_some())
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--clean-env cannot be combined with --fish-baseline, --login, --interactive-shell, \
+             --wsl or ssh, which all start from an environment of their own",
+        ));
+    }
+    if options.all_vars && options.shell != Shell::Bash {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--all-vars needs bash",
+        ));
+    }
+    let cacheable = cfg!(feature = "cache")
+        && !options.no_cache
+        && !options.on_cd
+        && !options.dry_run
+        && options.wsl.is_none()
+        && options.ssh.is_none();
+    let preset_cache = options
+        .preset
+        .and_then(|p| p.cache_inputs)
+        .filter(|_| cacheable)
+        .and_then(|inputs| inputs(args))
+        .and_then(|files| OutputCache::new(&command, &files));
+    let config = load_config(options)?;
+    let selected = selector(options);
+    let inherited = inherited_env(options)?;
+    // Answers to --interactive and the undo file are not part of the script
+    let cache = preset_cache.or_else(|| {
+        let script = options.sourced.as_ref().filter(|_| {
+            cacheable && options.cache_output && !options.interactive && options.undo_file.is_none()
+        })?;
+        let read = |path: Option<PathBuf>| path.and_then(|path| fs::read(path).ok());
+        let config_file = read(options.config.clone().or_else(config::default_path));
+        let policy_file = read(options.policy.clone());
+        let mut env: Vec<_> = capture::parse_dump(&inherited, true, &selected)
+            .into_iter()
+            .collect();
+        env.sort_unstable();
+        let env: Vec<u8> = env
+            .iter()
+            .flat_map(|(k, v)| [k.as_bytes(), b"=", v.as_bytes(), b"\0"].concat())
+            .collect();
+        let inputs = [
+            config_file.as_deref().unwrap_or_default(),
+            policy_file.as_deref().unwrap_or_default(),
+            options.fish_dump.as_deref().unwrap_or_default().as_bytes(),
+            &env,
+        ];
+        OutputCache::for_script(&command, script, &options.shell.locate().ok()?, &inputs)
+    });
+    if let Some(script) = cache.as_ref().and_then(OutputCache::get) {
+        tracing::debug!("output read from the cache");
+        return Ok(Generated {
+            script,
+            unchanged: false,
+            status: 0,
+        });
+    }
+
+    // What the command is called in messages
+    let label = options
+        .preset
+        .map_or_else(|| progress::label(&command), |p| p.name.to_string());
+    // Only the command runs wrapped, whatever the wrapper provides shows up as
+    // changes. The fast path only knows exported variables.
+    let wrapper = options.wrap.as_ref().unwrap_or(&config.wrap);
+    let fast_path_applies = options.preset.is_none()
+        && !options.no_fast_path
+        && wrapper.is_empty()
+        && !options.all_vars
+        && !options.clean_env
+        && options.wsl.is_none()
+        && options.ssh.is_none();
+    // Only parsed when used, in a large session it takes a while
+    let current_env = if fast_path_applies || options.fish_baseline {
+        capture::parse_dump(&inherited, true, &selected)
+    } else {
+        Env::default()
+    };
+    let fast_path = fast_path_applies
+        .then(|| fast_path::apply(&command, &current_env))
+        .flatten();
+    debug(options, "command evaluated by the shell", &command);
+    tracing::debug!(fast_path = fast_path.is_some(), "running {}", label);
+    if fast_path.is_some() {
+        debug(
+            options,
+            "applied by the fast path, the shell is not started",
+            "",
+        );
+    }
+    let captures;
+    let (old, new) = match fast_path {
+        Some(new_env) => (
+            Snapshot {
+                env: current_env,
+                ..Default::default()
+            },
+            Snapshot {
+                env: new_env,
+                ..Default::default()
+            },
+        ),
+        None => {
+            let program = match (&options.ssh, &options.wsl) {
+                (Some(_), _) => ssh::locate()?,
+                (None, Some(_)) => wsl::locate()?,
+                (None, None) => options.shell.locate()?,
+            };
+            // The spinner would draw over the command's terminal
+            let _progress = (!options.pty).then(|| Progress::start(&label));
+            let extra_env = options.preset.map_or(&[][..], |p| p.capture_env);
+            // Unfiltered, the shell has to start with everything
+            let base_env = (options.inherit_env.is_some() || options.clean_env)
+                .then(|| capture::parse_dump(&inherited, true, &|_| true));
+            let runner = Runner {
+                shell: options.shell,
+                base_env: base_env.as_ref(),
+                extra_env,
+                limit: config.capture_limit(),
+                settle: options.wait_for_jobs,
+                trace: options.provenance,
+                all_vars: options.all_vars,
+                pty: options.pty,
+                login: options.login,
+                interactive: options.interactive_shell,
+                shell_args: &options.shell_args,
+                no_startup_files: options.clean_env,
+                stderr: options.stderr,
+                timeout: options.timeout,
+                wsl: options.wsl.as_deref(),
+                ssh: options.ssh.as_deref(),
+                keep_going: options.keep_going,
+            };
+            // The distribution's or the host's state is not tracked
+            let baseline_cache = (cfg!(feature = "cache")
+                && !options.no_cache
+                && options.wsl.is_none()
+                && options.ssh.is_none())
+            .then(|| {
+                BaselineCache::new(
+                    options.shell,
+                    &program,
+                    &inherited,
+                    extra_env,
+                    &runner.shell_flags(),
+                    runner.all_vars,
+                )
+            })
+            .flatten();
+            let started = Instant::now();
+            let cached = baseline_cache
+                .as_ref()
+                .filter(|_| !options.refresh_baseline)
+                .and_then(BaselineCache::get);
+            let from_cache = cached.is_some();
+            // bash captures the baseline on the way, the same run unless the
+            // command is wrapped
+            let (baseline, after) = match cached {
+                Some(baseline) => {
+                    debug(options, "baseline read from the cache", "");
+                    (baseline, None)
+                }
+                None if options.shell == Shell::Bash && wrapper.is_empty() => {
+                    let (baseline, after) = Capture::run_with_baseline(&command, &runner)?;
+                    debug_timing(options, "baseline and command capture", started);
+                    (baseline, Some(after))
+                }
+                None => {
+                    // Nothing to interact with
+                    let baseline = Capture::run(
+                        "",
+                        &Runner {
+                            pty: false,
+                            stderr: StderrMode::Inherit,
+                            keep_going: false,
+                            ..runner
+                        },
+                        &[],
+                    )?;
+                    debug_timing(options, "baseline capture", started);
+                    (baseline, None)
+                }
+            };
+            if let Some(cache) = baseline_cache.as_ref().filter(|_| !from_cache) {
+                if let Err(e) = cache.store(&baseline) {
+                    eprintln!("Basrs warning: could not cache the baseline: {}", e);
+                }
+            }
+            let after = match after {
+                Some(after) => after,
+                None => {
+                    let started = Instant::now();
+                    let after = Capture::run(&command, &runner, wrapper)?;
+                    debug_timing(options, "command capture", started);
+                    after
+                }
+            };
+            captures = (baseline, after);
+            let mut old = captures.0.snapshot(&selected);
+            // Aliases, functions and shell state still come from bash, fish
+            // has none of its own to compare
+            if options.fish_baseline {
+                old.env = current_env;
+                for (name, value) in extra_env
+                    .iter()
+                    .filter(|(name, _)| selected(name.as_bytes()))
+                {
+                    old.env.insert((*name).into(), (*value).into());
+                }
+            }
+            (old, captures.1.snapshot(&selected))
+        }
+    };
+    debug_snapshot(options, "before", &old);
+    debug_snapshot(options, "after", &new);
+    if new.jobs > 0 {
+        eprintln!(
+            "Basrs warning: {} background job(s) of the command were still running, \
+             changes they make later are missing; --wait-for-jobs <seconds> waits for them",
+            new.jobs
+        );
+    }
+    // The changes up to the failed command are still applied
+    let label = match new.step.filter(|_| new.status != 0 && commands.len() > 1) {
+        Some(step) => {
+            eprintln!(
+                "Basrs: command {} of {} failed with status {}: {}",
+                step + 1,
+                commands.len(),
+                new.status,
+                commands[step]
+            );
+            progress::label(&commands[step])
+        }
+        None => label,
+    };
+    if options.stderr == StderrMode::Report && new.status != 0 && !new.stderr.is_empty() {
+        eprintln!("Basrs: output of {}:", label);
+        eprint!("{}", new.stderr);
+        if !new.stderr.ends_with('\n') {
+            eprintln!();
+        }
+    }
+    let started = Instant::now();
+    let generated = diff_script(options, &config, &label, Some(&command), &old, &new)?;
+    debug_timing(options, "diff and script generation", started);
+    // A failed initialization runs again next time. Only scripts of a
+    // successful command that changed something are kept, which is what a
+    // cache hit reports for --fail-if-unchanged and the exit status.
+    if let Some(cache) = cache
+        .as_ref()
+        .filter(|_| generated.status == 0 && !generated.unchanged)
+    {
+        if let Err(e) = cache.store(&generated.script) {
+            eprintln!("Basrs warning: could not cache output: {}", e);
+        }
+    }
+    Ok(generated)
+}
+
+// --debug output on stderr, each line of the details indented below the
+// heading. Logged at the trace level as well.
+fn debug(options: &Options, heading: &str, details: &str) {
+    tracing::trace!(details, "{}", heading);
+    if !options.debug {
+        return;
+    }
+    eprintln!("Basrs debug: {}", heading);
+    for line in details.lines() {
+        eprintln!("    {}", line);
+    }
+}
+
+// Also shown with --stats
+fn debug_timing(options: &Options, phase: &str, started: Instant) {
+    let timing = format!("{} took {:.1?}", phase, started.elapsed());
+    if options.stats && !options.debug {
+        eprintln!("Basrs stats: {}", timing);
+    }
+    debug(options, &timing, "");
+}
+
+// The raw sections of a capture, variables and shell state sorted by name
+fn debug_snapshot(options: &Options, name: &str, snapshot: &Snapshot) {
+    if !options.debug && !tracing::enabled!(tracing::Level::TRACE) {
+        return;
+    }
+    let mut env: Vec<_> = snapshot
+        .env
+        .iter()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect();
+    env.sort_unstable();
+    let mut state: Vec<_> = snapshot
+        .state
+        .iter()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect();
+    state.sort_unstable();
+    debug(
+        options,
+        &format!("{} (status {}): environment", name, snapshot.status),
+        &env.join("\n"),
+    );
+    debug(options, &format!("{}: aliases", name), &snapshot.aliases);
+    debug(
+        options,
+        &format!("{}: functions", name),
+        &snapshot.functions,
+    );
+    debug(
+        options,
+        &format!("{}: shell state", name),
+        &state.join("\n"),
+    );
+}
+
+// The -c commands followed by the one after the options, which is read from
+// stdin when it is `-`
+fn commands(options: &Options, args: &[String]) -> io::Result<Vec<String>> {
+    let mut commands = options.commands.clone();
+    match args {
+        [] => {}
+        [stdin] if stdin == "-" => {
+            let script = String::from_utf8(read_input("-")?).map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "The script on stdin is not valid UTF-8",
+                )
+            })?;
+            commands.push(script);
+        }
+        args => commands.push(preset::command_line(args)),
+    }
+    Ok(commands)
+}
+
+// Runs the commands in order, stopping at the first failure. The capture
+// reports how many of them succeeded.
+fn chain(commands: &[String]) -> String {
+    if let [command] = commands {
+        return command.clone();
+    }
+    let mut chained = "__basrs_step=0".to_string();
+    for (i, command) in commands.iter().enumerate() {
+        chained.push_str(&format!(
+            " && eval {} && __basrs_step={}",
+            preset::bash_quote(command),
+            i + 1
+        ));
+    }
+    chained
+}
+
+// Variables outside of --filter are dropped before any diffing
+fn selector(options: &Options) -> impl Fn(&[u8]) -> bool + '_ {
+    |name: &[u8]| {
+        options.filters.is_empty()
+            || options
+                .filters
+                .iter()
+                .any(|glob| filter::glob_match(glob, &String::from_utf8_lossy(name)))
+    }
+}
+
+// The state after the commands as a snapshot file, for `diff` or `apply` later
+pub fn snapshot(options: &Options, args: &[String]) -> io::Result<Generated> {
+    if options.preset.is_some() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "snapshot cannot be combined with a preset",
+        ));
+    }
+    let command = chain(&commands(options, args)?);
+    let config = load_config(options)?;
+    let selected = selector(options);
+    let inherited = inherited_env(options)?;
+    options.shell.locate()?;
+    let base_env = (options.inherit_env.is_some() || options.clean_env)
+        .then(|| capture::parse_dump(&inherited, true, &|_| true));
+    let runner = Runner {
+        shell: options.shell,
+        base_env: base_env.as_ref(),
+        extra_env: &[],
+        limit: config.capture_limit(),
+        settle: options.wait_for_jobs,
+        trace: false,
+        all_vars: options.all_vars,
+        pty: options.pty,
+        login: options.login,
+        interactive: options.interactive_shell,
+        shell_args: &options.shell_args,
+        no_startup_files: options.clean_env,
+        stderr: options.stderr,
+        timeout: options.timeout,
+        wsl: options.wsl.as_deref(),
+        ssh: options.ssh.as_deref(),
+        keep_going: options.keep_going,
+    };
+    let wrapper = options.wrap.as_ref().unwrap_or(&config.wrap);
+    let _progress =
+        (!command.is_empty() && !options.pty).then(|| Progress::start(&progress::label(&command)));
+    let capture = Capture::run(&command, &runner, wrapper)?;
+    let snapshot = capture.snapshot(&selected);
+    let file = SnapshotFile::new(&snapshot);
+    Ok(Generated {
+        script: format!("{}\n", file.to_json()),
+        unchanged: false,
+        status: snapshot.status,
+    })
+}
+
+// Replays an `env` or `printenv` dump, taken as the state after the command
+pub fn from_env(options: &Options, file: &str) -> io::Result<Generated> {
+    compare_dumps(options, None, file)
+}
+
+// Compares two dumps, the first one taken as the state before the command
+pub fn diff(options: &Options, before: &str, after: &str) -> io::Result<Generated> {
+    compare_dumps(options, Some(before), after)
+}
+
+// Without a dump of the state before, the current environment is used. Either
+// side may be a snapshot file instead of a dump.
+fn compare_dumps(options: &Options, before: Option<&str>, after: &str) -> io::Result<Generated> {
+    let old_dump = match before {
+        Some(file) => read_input(file)?,
+        None => inherited_env(options)?,
+    };
+    let old_file = match before {
+        Some(file) => SnapshotFile::parse(&old_dump, file)?,
+        None => None,
+    };
+    let dump = read_input(after)?;
+    let new_file = SnapshotFile::parse(&dump, after)?;
+    let config = load_config(options)?;
+    let selected = selector(options);
+    // The inherited environment is always an `env -0` dump
+    let old_null = before.is_none() || options.null_data;
+    let mut old = match &old_file {
+        Some(file) => file.snapshot(&selected),
+        None => Snapshot {
+            env: capture::parse_dump(&old_dump, old_null, &selected),
+            ..Default::default()
+        },
+    };
+    let mut new = match &new_file {
+        Some(file) => file.snapshot(&selected),
+        None => Snapshot {
+            env: capture::parse_dump(&dump, options.null_data, &selected),
+            ..Default::default()
+        },
+    };
+    // A snapshot of a fresh bash against a dump without aliases and functions
+    // would remove them all, the side holding them is taken as both
+    if before.is_some() && old_file.is_none() != new_file.is_none() {
+        let (with, without) = if old_file.is_some() {
+            (&old, &mut new)
+        } else {
+            (&new, &mut old)
+        };
+        without.aliases = with.aliases.clone();
+        without.aliases_hash = with.aliases_hash;
+        without.functions = with.functions.clone();
+        without.functions_hash = with.functions_hash;
+    }
+    let label = progress::label(&format!("source {}", after));
+    diff_script(options, &config, &label, None, &old, &new)
+}
+
+// Renders a JSON diff, applied on top of the current environment
+pub fn emit(options: &Options, file: &str) -> io::Result<Generated> {
+    let diff = EnvDiff::from_json(&read_input(file)?).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid diff {}: {}", file, e),
+        )
+    })?;
+    let config = load_config(options)?;
+    let selected = selector(options);
+    let inherited = inherited_env(options)?;
+    let current_env = capture::parse_dump(&inherited, true, &selected);
+    let (old, new) = diff.apply(current_env);
+    diff_script(options, &config, file, None, &old, &new)
+}
+
+// The config file with the --profile applied, and the flags standing in for
+// its settings
+pub fn load_config(options: &Options) -> io::Result<Config> {
+    let mut config =
+        Config::load(options.config.as_deref())?.with_profile(options.profile.as_deref())?;
+    config.sanitize_names |= options.sanitize_names;
+    config.replace_builtin_lists |= options.no_default_ignores;
+    Ok(config)
+}
+
+// The environment of the calling shell as an `env -0` dump, the one passed
+// with --inherit-fish-env or else the one basrs was started with. Nothing at
+// all with --clean-env.
+fn inherited_env(options: &Options) -> io::Result<Vec<u8>> {
+    if options.clean_env {
+        return Ok(Vec::new());
+    }
+    match &options.inherit_env {
+        Some(file) => read_input(file),
+        None => Ok(capture::process_env_dump()),
+    }
+}
+
+// Contents of a file, or of stdin for -
+pub fn read_input(file: &str) -> io::Result<Vec<u8>> {
+    if file == "-" {
+        let mut input = Vec::new();
+        io::stdin().read_to_end(&mut input)?;
+        return Ok(input);
+    }
+    fs::read(file).map_err(|e| io::Error::new(e.kind(), format!("Cannot read {}: {}", file, e)))
+}
+
+// The fish script turning the `old` state into the `new` one, `command` is what
+// ran in bash if anything did
+pub fn diff_script(
+    options: &Options,
+    config: &Config,
+    label: &str,
+    command: Option<&str>,
+    old: &Snapshot,
+    new: &Snapshot,
+) -> io::Result<Generated> {
+    if options.undo_file.is_some() && options.format != Format::Fish {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--undo-file needs fish output",
+        ));
+    }
+    if options.dry_run && options.format != Format::Fish {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--dry-run needs fish output",
+        ));
+    }
+    if options.path_mode != PathMode::Replace && options.format != Format::Fish {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--path-mode user-paths needs fish output",
+        ));
+    }
+    let diff_span = tracing::info_span!("diff", label).entered();
+    let mut policy = Policy::load(options.policy.as_deref())?;
+    policy.default_scope = options.scope.or(config.scope).unwrap_or_default();
+    policy.path_vars = config.path_vars.iter().map(|p| Pattern::parse(p)).collect();
+    policy.path_vars.extend(options.path_vars.iter().cloned());
+    policy.dedupe_paths = options.dedupe_path;
+    // Inside WSL, fish takes the Linux paths as they are
+    policy.windows_paths = options.wsl.is_some() && !cfg!(target_os = "linux");
+    policy.skip_deferred = options.format != Format::Fish;
+    // The command may have run over ssh or in WSL
+    policy.dir_exists = new.state.get("pwd_exists").map(|exists| exists == "1");
+    let mut scopes = ScopeTracker::load(options.fish_dump.as_deref());
+    let mut filter = Filter::new(options.preset, config, &policy, &options.ignored);
+    filter.add_read_only(scopes.read_only(), "--fish-dump");
+    filter.allow(&options.allowed);
+    if options.skip_host_vars {
+        filter.add_ignored(ssh::HOST_VARS, "--skip-host-vars");
+    }
+    let translated = cygpath::translate(options.path_translate, &old.env, &new.env, &|name| {
+        policy.path_var(name)
+    })?;
+    let (old_env, new_env) = (&old.env, translated.as_ref().unwrap_or(&new.env));
+    let emitter = options.format.emitter();
+
+    let mut func_skipped = Vec::new();
+    let prefix = options.prefix.as_deref().unwrap_or("");
+    // Aliases and functions are parsed alongside the variable diff, unless the
+    // command left their sections untouched or the build leaves them out
+    let ((mut changes, mut skipped), alias_changes, (func_changes, definitions)) =
+        thread::scope(|s| {
+            let aliases =
+                (cfg!(feature = "aliases") && old.aliases_hash != new.aliases_hash).then(|| {
+                    s.spawn(|| {
+                        process_alias_changes(
+                            &old.aliases,
+                            &new.aliases,
+                            options.alias_mode.or(config.alias_mode).unwrap_or_default(),
+                            prefix,
+                        )
+                    })
+                });
+            let funcs = (cfg!(feature = "functions") && old.functions_hash != new.functions_hash)
+                .then(|| {
+                    s.spawn(|| {
+                        process_func_changes(
+                            &old.functions,
+                            &new.functions,
+                            options.preset,
+                            options.shell,
+                            prefix,
+                            &mut func_skipped,
+                        )
+                    })
+                });
+            let changes = process_env_changes(old_env, new_env, &filter, config, &policy, &scopes);
+            let aliases =
+                aliases.map_or_else(Vec::new, |w| w.join().expect("alias parser panicked"));
+            let funcs = funcs.map_or_else(Default::default, |w| {
+                w.join().expect("function parser panicked")
+            });
+            (changes, aliases, funcs)
+        });
+    // Arrays can't be exported, like other unexported variables they are
+    // only taken with --all-vars. Tools keep their internal state in them.
+    if options.all_vars {
+        let (array_changes, array_skipped) =
+            process_array_changes(&old.state, &new.state, &filter, &policy);
+        changes.extend(array_changes);
+        skipped.extend(array_skipped);
+    }
+    if let Some(preset) = options.preset {
+        changes.retain(|change| {
+            let name = change.name.as_str();
+            let reorders = change.category == Category::Variable
+                && preset.only_reorders(
+                    name,
+                    old_env.get(name).map(|v| v.as_ref()),
+                    new_env.get(name).map(|v| v.as_ref()),
+                );
+            if reorders {
+                skipped.push(Skipped {
+                    category: Category::Variable,
+                    name: change.name.clone(),
+                    reason: format!("only reordered by the {} preset", preset.name),
+                });
+            }
+            !reorders
+        });
+    }
+    changes.extend(alias_changes);
+    let mut exported_definitions = None;
+    if options.exported_functions == ExportedFunctionMode::Function {
+        let defined: Vec<&str> = func_changes
+            .iter()
+            .filter_map(|c| c.name.strip_prefix(prefix))
+            .collect();
+        let (exported, definitions) =
+            process_exported_functions(old_env, new_env, &defined, prefix);
+        changes.extend(exported);
+        exported_definitions = definitions;
+    }
+    changes.extend(func_changes);
+    changes.extend(process_setting_changes(&old.state, &new.state));
+    changes.extend(process_umask_change(&old.state, &new.state));
+    changes.extend(process_limit_changes(&old.state, &new.state));
+    changes.extend(process_directory_changes(&old.state, &new.state));
+    changes.extend(process_completion_changes(
+        &old.state,
+        &new.state,
+        definitions.as_ref(),
+    ));
+    skipped.extend(func_skipped);
+    if !options.only.is_empty() {
+        changes.retain(|change| {
+            let selected = options
+                .only
+                .iter()
+                .any(|only| only.selects(change.category, &change.name));
+            if !selected {
+                skipped.push(Skipped {
+                    category: change.category,
+                    name: change.name.clone(),
+                    reason: "not selected by --only".to_string(),
+                });
+            }
+            selected
+        });
+    }
+    match options
+        .locale_policy
+        .or(config.locale_policy)
+        .unwrap_or_default()
+    {
+        LocaleMode::Export => {}
+        LocaleMode::Ignore => changes.retain(|change| {
+            let locale = change.category == Category::Variable && is_locale_var(&change.name);
+            if locale {
+                skipped.push(Skipped {
+                    category: Category::Variable,
+                    name: change.name.clone(),
+                    reason: "locale variable, the locale policy is ignore".to_string(),
+                });
+            }
+            !locale
+        }),
+        LocaleMode::Warn => {
+            let locale = changes
+                .iter()
+                .filter(|c| c.category == Category::Variable && is_locale_var(&c.name));
+            for change in locale {
+                match &change.value {
+                    Some(value) => eprintln!(
+                        "Basrs warning: {} sets {} to '{}', fish picks up the new locale",
+                        label, change.name, value
+                    ),
+                    None => eprintln!(
+                        "Basrs warning: {} removes {}, fish picks up the new locale",
+                        label, change.name
+                    ),
+                }
+            }
+        }
+    }
+    options.format.retain_supported(&mut changes, &mut skipped);
+    if options.interactive {
+        changes = confirm::confirm(changes, &mut skipped)?;
+    }
+    if options.verbose {
+        for skip in &skipped {
+            eprintln!(
+                "Basrs: skipped {} {}: {}",
+                skip.category.name(),
+                skip.name,
+                skip.reason
+            );
+        }
+    }
+    if options.stats {
+        eprintln!(
+            "Basrs stats: {}",
+            summary::stats(&changes, skipped.len(), old_env)
+        );
+    }
+    if options.summary {
+        eprint!(
+            "{}",
+            summary::render(label, &changes, old_env, options.color)
+        );
+    }
+
+    if options.format == Format::Json {
+        let mut diff = EnvDiff::between(old, new);
+        diff.retain(&changes);
+        return Ok(Generated {
+            script: format!("{}\n", diff.to_json()),
+            unchanged: changes.is_empty(),
+            status: new.status,
+        });
+    }
+    if !options.format.is_script() {
+        return Ok(Generated {
+            script: options.format.render(&changes),
+            unchanged: changes.is_empty(),
+            status: new.status,
+        });
+    }
+
+    for change in &changes {
+        if change.category != Category::Variable || change.name == "PWD" {
+            continue;
+        }
+        if change.erases {
+            scopes.forget(&change.name);
+        } else {
+            scopes.record(&change.name, policy.scope(&change.name));
+        }
+    }
+
+    // Captured with --all-vars, the shell had them unexported
+    let shell_vars: Vec<&str> = new
+        .state
+        .get("shellvars")
+        .map_or_else(Vec::new, |names| names.split(' ').collect());
+    for change in &mut changes {
+        let shell_var = change.category == Category::Variable
+            && change.name != "PWD"
+            && shell_vars.contains(&change.name.as_str());
+        if let Some(value) = change.value.as_ref().filter(|_| shell_var) {
+            change.actions = vec![Action::SetShellVar {
+                name: change.name.clone(),
+                value: value.clone(),
+                scope: policy.scope(&change.name),
+            }];
+        }
+    }
+
+    if options.path_mode == PathMode::UserPaths {
+        let old_path = old_env.get("PATH").map_or("", |v| v.as_ref());
+        let new_path = new_env.get("PATH").map_or("", |v| v.as_ref());
+        let path_change = changes.iter_mut().find(|change| {
+            change.category == Category::Variable && change.name == "PATH" && !change.erases
+        });
+        if let Some(change) = path_change {
+            let (prepended, appended, removed) = path_edits(old_path, new_path);
+            let owned = |entries: Vec<&str>| entries.into_iter().map(str::to_string).collect();
+            change.actions.clear();
+            if !prepended.is_empty() {
+                change.actions.push(Action::AddUserPaths {
+                    entries: owned(prepended),
+                    append: false,
+                });
+            }
+            if !appended.is_empty() {
+                change.actions.push(Action::AddUserPaths {
+                    entries: owned(appended),
+                    append: true,
+                });
+            }
+            change.actions.extend(
+                removed
+                    .into_iter()
+                    .map(|e| Action::RemoveUserPath(e.to_string())),
+            );
+        }
+    }
+
+    for skip in &skipped {
+        tracing::debug!(name = %skip.name, reason = %skip.reason, "skipped");
+    }
+    tracing::debug!(changes = changes.len(), skipped = skipped.len(), "compared");
+    diff_span.exit();
+    let _emit_span = tracing::info_span!("emit", format = ?options.format).entered();
+    for change in &mut changes {
+        change.emit(emitter);
+    }
+
+    // An alias or function named like a fish function or builtin replaces it
+    // for the whole session. fish is only asked when something is defined.
+    let mut collision_lines = Vec::new();
+    if options.format == Format::Fish
+        && options.on_collision != CollisionMode::Force
+        && changes.iter().any(collision::defines_function)
+    {
+        match collision::fish_commands(options.fish_commands.as_deref())? {
+            Some(commands) => changes.retain(|change| {
+                if !collision::defines_function(change) || !commands.contains(&change.name) {
+                    return true;
+                }
+                let kind = change.category.name();
+                if options.on_collision == CollisionMode::Warn {
+                    eprintln!(
+                        "Basrs warning: {} {} shadows the fish command of the same name",
+                        kind, change.name
+                    );
+                    return true;
+                }
+                eprintln!(
+                    "Basrs warning: {} {} left out, it would shadow the fish command of the same name; \
+                     --on-collision force defines it",
+                    kind, change.name
+                );
+                collision_lines.push(format!(
+                    "# Skipping {} {}, it would shadow the fish command of the same name:",
+                    kind,
+                    escape_comment(&change.name)
+                ));
+                collision_lines.extend(change.lines.iter().map(|line| format!("#   {}", line)));
+                false
+            }),
+            None if options.on_collision == CollisionMode::Skip => eprintln!(
+                "Basrs warning: fish not found, aliases and functions are not checked for \
+                 collisions; --fish-commands lists the commands to check against"
+            ),
+            None => {}
+        }
+    }
+
+    // fish_add_path and removing entries are no-ops the second time already
+    let user_paths = options.path_mode == PathMode::UserPaths;
+    for change in changes.iter_mut().filter(|_| options.idempotent) {
+        if change.lines.is_empty() {
+            continue;
+        }
+        let lines = mem::take(&mut change.lines);
+        change.lines = match change.category {
+            _ if user_paths && change.name == "PATH" => lines,
+            Category::Variable if change.erases => emitter.if_set(&change.name, lines),
+            Category::Variable => match &change.value {
+                Some(value) if change.name != "PWD" => {
+                    emitter.unless_value(&change.name, value, lines)
+                }
+                _ => lines,
+            },
+            Category::Alias | Category::Function if !change.erases => {
+                emitter.unless_function(&change.name, lines)
+            }
+            _ => lines,
+        };
+    }
+
+    let home = env::var("HOME").ok().filter(|home| !home.is_empty());
+    for change in &mut changes {
+        let origin = new.origins.get(&change.name);
+        if let Some(origin) = origin.filter(|_| change.category == Category::Variable) {
+            let origin = match home
+                .as_ref()
+                .and_then(|home| origin.strip_prefix(home.as_str()))
+            {
+                Some(rest) if rest.starts_with('/') => format!("~{}", rest),
+                _ => origin.clone(),
+            };
+            change
+                .lines
+                .insert(0, format!("# from {}", escape_comment(&origin)));
+        }
+    }
+
+    // The backups, preset epilogues and status variables are fish code
+    let fish = options.format == Format::Fish;
+    let mut epilogue_lines = Vec::new();
+    if options.backup_functions && fish {
+        let mut backed_up = false;
+        for change in &mut changes {
+            if matches!(change.category, Category::Alias | Category::Function) {
+                let mut lines = backup::backup_lines(&change.name);
+                lines.append(&mut change.lines);
+                change.lines = lines;
+                backed_up = true;
+            }
+        }
+        if backed_up {
+            epilogue_lines.push(backup::RESTORE_FUNCTION.to_string());
+        }
+    }
+    if let Some(epilogue) = options.preset.and_then(|p| p.epilogue).filter(|_| fish) {
+        let mut changed: Vec<_> = new_env
+            .iter()
+            .filter(|(k, v)| *k != "PWD" && !filter.ignores(k) && old_env.get(*k) != Some(v))
+            .map(|(k, v)| (k.as_ref(), old_env.get(k).map(|v| v.as_ref()), v.as_ref()))
+            .collect();
+        changed.sort_unstable_by_key(|(name, _, _)| *name);
+        epilogue_lines.push(epilogue(&PresetContext {
+            changed,
+            prompt: options.prompt,
+        }));
+    }
+
+    // Nothing gets applied in preview mode or on a dry run
+    let applies = !options.preview && !options.dry_run;
+    let proxied = changes.iter().any(|c| {
+        matches!(c.category, Category::Function | Category::Completion) && !c.lines.is_empty()
+    });
+    let written = [definitions, exported_definitions];
+    for definitions in written.iter().flatten().filter(|_| proxied && applies) {
+        definitions.write()?;
+    }
+    if let Some(path) = options.undo_file.as_ref().filter(|_| applies) {
+        let undo = undo::script(
+            &changes,
+            old_env,
+            &old.state,
+            &policy,
+            options.backup_functions,
+            emitter,
+        );
+        fs::write(path, undo).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("Cannot write undo file {}: {}", path.display(), e),
+            )
+        })?;
+    }
+    if applies {
+        if let Err(e) = scopes.save() {
+            eprintln!("Basrs warning: could not save variable scopes: {}", e);
+        }
+    }
+
+    if options.dry_run {
+        return Ok(Generated {
+            script: dry_run_report(label, &changes, new.status),
+            unchanged: changes.is_empty(),
+            status: new.status,
+        });
+    }
+
+    // With --no-comments only the commands of the changes are left, the
+    // comments go with the changes they describe rather than by line: values
+    // and function bodies can hold lines starting with #
+    let comments = !(options.no_comments || config.no_comments);
+
+    // For prompts showing whether the last environment sync worked
+    if options.status_var && fish {
+        epilogue_lines.push(format!("set -g __basrs_last_status {}", new.status));
+        epilogue_lines.push(format!("set -g __basrs_last_script {}", escape(label)));
+    }
+    if let Some(command) = command.filter(|_| options.on_cd) {
+        epilogue_lines.push(hook::pwd_handler(command, label));
+    }
+    // Sourcing the script fails like the command did, it has to be the last line
+    if new.status != 0 && fish {
+        epilogue_lines.push(format!("return {}", new.status));
+    } else if new.status != 0 && comments {
+        epilogue_lines.push(format!(
+            "# {} failed with status {}",
+            escape_comment(label),
+            new.status
+        ));
+    }
+
+    let mut script = String::new();
+    if comments {
+        // Every line commented out, nothing the command printed runs in fish
+        if options.stderr == StderrMode::Comment && !new.stderr.is_empty() {
+            script.push_str(&format!("# Output of {}:\n", escape_comment(label)));
+            for line in new.stderr.lines() {
+                script.push_str(&format!("#   {}\n", escape_comment(line)));
+            }
+        }
+        // Shown without -v too, the variable is gone from fish without a trace
+        for skip in skipped
+            .iter()
+            .filter(|s| s.category == Category::Variable && filter.rejects_name(&s.name))
+        {
+            script.push_str(&format!(
+                "# Skipping {}, not a {} variable name; --sanitize-names sets it as {}\n",
+                escape_comment(&skip.name),
+                emitter.name(),
+                sanitize_name(&skip.name)
+            ));
+        }
+        // A `set` of these would fail in the middle of sourcing
+        for skip in skipped
+            .iter()
+            .filter(|s| fish && s.category == Category::Variable && filter.read_only(&s.name))
+        {
+            script.push_str(&format!(
+                "# Skipping {}, read-only in fish\n",
+                escape_comment(&skip.name)
+            ));
+        }
+        // Nushell would get other characters in their place
+        for skip in skipped.iter().filter(|s| s.reason.starts_with(NOT_UTF8)) {
+            script.push_str(&format!(
+                "# Skipping {}, {}\n",
+                escape_comment(&skip.name),
+                skip.reason
+            ));
+        }
+        for skip in skipped.iter().filter(|s| s.reason == ASSOCIATIVE_ARRAY) {
+            script.push_str(&format!(
+                "# Skipping {}, {}\n",
+                escape_comment(&skip.name),
+                skip.reason
+            ));
+        }
+        for line in &collision_lines {
+            script.push_str(line);
+            script.push('\n');
+        }
+    }
+    let size: usize = changes
+        .iter()
+        .flat_map(|c| c.comment.iter().chain(&c.lines))
+        .map(|line| line.len() + 3)
+        .sum();
+    script.reserve(size + 16);
+    for category in [
+        Category::Variable,
+        Category::Setting,
+        Category::Alias,
+        Category::Function,
+        Category::Completion,
+    ] {
+        change::render_into(&mut script, &changes, category, comments);
+    }
+    for line in &epilogue_lines {
+        script.push_str(line);
+        script.push('\n');
+    }
+    if epilogue_lines.is_empty() && comments {
+        script.push('\n');
+    }
+    Ok(Generated {
+        script,
+        unchanged: changes.is_empty(),
+        status: new.status,
+    })
+}
+
+// The descriptions of the changes, every line a comment so that sourcing the
+// report does nothing
+fn dry_run_report(label: &str, changes: &[Change], status: i32) -> String {
+    let mut report = format!(
+        "# Dry run of {}, nothing below is applied\n",
+        escape_comment(label)
+    );
+    if changes.is_empty() {
+        report.push_str("# No changes\n");
+    }
+    for category in [
+        Category::Variable,
+        Category::Setting,
+        Category::Alias,
+        Category::Function,
+        Category::Completion,
+    ] {
+        for change in changes.iter().filter(|c| c.category == category) {
+            match &change.comment {
+                Some(comment) => report.push_str(&format!("# {}\n", escape_comment(comment))),
+                None => report.push_str(&format!(
+                    "# Changing {} {}\n",
+                    category.name(),
+                    escape_comment(&change.name)
+                )),
+            }
+        }
+    }
+    if status != 0 {
+        report.push_str(&format!("# The command failed with status {}\n", status));
+    }
+    report
+}
@@ -0,0 +1,139 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::filter::Pattern;
+use crate::scope::Scope;
+
+// A team-wide description of how variables are treated, loaded with --policy:
+//
+//   [[rule]]
+//   pattern = "JAVA_*"
+//   skip = true
+//
+// The first rule whose pattern matches a variable decides its treatment.
+#[derive(Default)]
+pub struct Policy {
+    pub rules: Vec<Treatment>,
+    // File the policy was loaded from, shown as the source of its rules
+    pub source: String,
+    // Scope of the variables no rule gives one, set with --scope
+    pub default_scope: Scope,
+    // Path variables on top of the names ending in PATH, unless a rule says
+    // otherwise, from the config and --path-var
+    pub path_vars: Vec<Pattern>,
+    // Keep only the first of repeated path list entries, set with --dedupe-path
+    pub dedupe_paths: bool,
+    // Write paths under /mnt/<drive> as Windows ones, for --wsl
+    pub windows_paths: bool,
+    // Leave out the values computed by a fish command, for scripts that are
+    // not sourced by fish
+    pub skip_deferred: bool,
+    // Whether the new working directory still exists where the command ran,
+    // as the capture found. None to look for it on this host.
+    pub dir_exists: Option<bool>,
+}
+
+pub struct Treatment {
+    pub pattern: Pattern,
+    // Leave the variable out of the script
+    pub skip: bool,
+    pub scope: Option<Scope>,
+    // Set as a fish path variable, one list element per colon-separated entry,
+    // by default only names ending in PATH are
+    pub path_var: Option<bool>,
+    // Fish command recomputing the value whenever the script is sourced
+    pub deferred: Option<String>,
+    pub transform: Option<Transform>,
+}
+
+// Replaces every occurrence of `replace` in the value by `with`
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Transform {
+    pub replace: String,
+    pub with: String,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct PolicyFile {
+    rule: Vec<RuleEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct RuleEntry {
+    pattern: String,
+    #[serde(default)]
+    skip: bool,
+    scope: Option<String>,
+    path_var: Option<bool>,
+    deferred: Option<String>,
+    transform: Option<Transform>,
+}
+
+impl Policy {
+    pub fn load(path: Option<&Path>) -> io::Result<Policy> {
+        let Some(path) = path else {
+            return Ok(Policy::default());
+        };
+        let invalid = |message: String| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid policy {}: {}", path.display(), message),
+            )
+        };
+        let content = fs::read_to_string(path).map_err(|e| {
+            io::Error::new(e.kind(), format!("Cannot read {}: {}", path.display(), e))
+        })?;
+        let file: PolicyFile = toml::from_str(&content).map_err(|e| invalid(e.to_string()))?;
+        let mut rules = Vec::new();
+        for entry in file.rule {
+            let scope = match &entry.scope {
+                Some(name) => Some(Scope::from_code(name).ok_or_else(|| {
+                    invalid(format!(
+                        "unknown scope {} for {} (available: local, global, universal)",
+                        name, entry.pattern
+                    ))
+                })?),
+                None => None,
+            };
+            rules.push(Treatment {
+                pattern: Pattern::parse(&entry.pattern),
+                skip: entry.skip,
+                scope,
+                path_var: entry.path_var,
+                deferred: entry.deferred,
+                transform: entry.transform,
+            });
+        }
+        Ok(Policy {
+            rules,
+            source: format!("policy {}", path.display()),
+            ..Default::default()
+        })
+    }
+
+    pub fn treatment(&self, name: &str) -> Option<&Treatment> {
+        self.rules.iter().find(|rule| rule.pattern.matches(name))
+    }
+
+    // Like fish itself, which splits variables ending in PATH on colons when
+    // it imports them
+    pub fn path_var(&self, name: &str) -> bool {
+        self.treatment(name)
+            .and_then(|treatment| treatment.path_var)
+            .unwrap_or_else(|| {
+                name.ends_with("PATH") || self.path_vars.iter().any(|p| p.matches(name))
+            })
+    }
+
+    pub fn scope(&self, name: &str) -> Scope {
+        self.treatment(name)
+            .and_then(|treatment| treatment.scope)
+            .unwrap_or(self.default_scope)
+    }
+}
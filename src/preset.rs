@@ -0,0 +1,519 @@
+use std::collections::BTreeSet;
+use std::env;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::{escape, escape_comment};
+
+// Canned handling for well-known environment scripts
+pub struct Preset {
+    pub name: &'static str,
+    // Variables that are pure bookkeeping of the script and never worth exporting
+    pub ignored: &'static [&'static str],
+    pub ignored_prefixes: &'static [&'static str],
+    // Prefixes of the script's internal bash functions, not reported in the diff
+    pub hidden_functions: &'static [&'static str],
+    // Functions left out by their exact name, which the epilogue defines for fish
+    pub hidden_function_names: &'static [&'static str],
+    // Environment both bash captures run with
+    pub capture_env: &'static [(&'static str, &'static str)],
+    // Builds the bash command from the arguments following the preset
+    pub command: fn(&[String]) -> io::Result<String>,
+    // Fish code appended after the diff
+    pub epilogue: Option<fn(&PresetContext) -> String>,
+    pub cache_inputs: Option<CacheInputs>,
+    // Colon-separated lists the script re-prepends to on every run, changes
+    // that only reorder or repeat existing entries are not worth emitting
+    pub stable_lists: &'static [&'static str],
+}
+
+// Files the output depends on, None when this invocation must not be cached
+type CacheInputs = fn(&[String]) -> Option<Vec<PathBuf>>;
+
+pub struct PresetContext<'a> {
+    // Variables the diff set, with their previous value if they existed before
+    // and the new one
+    pub changed: Vec<(&'a str, Option<&'a str>, &'a str)>,
+    pub prompt: bool,
+}
+
+const PRESETS: &[Preset] = &[
+    Preset {
+        name: "venv",
+        ignored: &[
+            "_OLD_VIRTUAL_PATH",
+            "_OLD_VIRTUAL_PYTHONHOME",
+            "_OLD_VIRTUAL_PS1",
+        ],
+        ignored_prefixes: &[],
+        hidden_functions: &[],
+        hidden_function_names: &[],
+        // Skip the PS1 rewriting, fish gets its own prompt hook
+        capture_env: &[("VIRTUAL_ENV_DISABLE_PROMPT", "1")],
+        command: venv_command,
+        epilogue: Some(venv_epilogue),
+        cache_inputs: None,
+        stable_lists: &[],
+    },
+    Preset {
+        name: "rvm",
+        ignored: &[],
+        // rvm's own settings and platform detection results
+        ignored_prefixes: &["rvm_", "_system_"],
+        hidden_functions: &["__rvm_", "rvm_", "__function_on_stack"],
+        hidden_function_names: &["rvm"],
+        capture_env: &[],
+        command: rvm_command,
+        epilogue: Some(rvm_epilogue),
+        cache_inputs: None,
+        stable_lists: &[],
+    },
+    Preset {
+        name: "sdkman",
+        ignored: &[],
+        ignored_prefixes: &[],
+        hidden_functions: &["__sdk", "_sdkman_", "__sdkman_"],
+        hidden_function_names: &["sdk"],
+        capture_env: &[],
+        command: sdkman_command,
+        epilogue: Some(sdkman_epilogue),
+        cache_inputs: Some(sdkman_cache_inputs),
+        stable_lists: &[],
+    },
+    Preset {
+        name: "opam",
+        ignored: &[],
+        ignored_prefixes: &[],
+        hidden_functions: &[],
+        hidden_function_names: &[],
+        capture_env: &[],
+        command: opam_command,
+        epilogue: None,
+        cache_inputs: None,
+        stable_lists: &[],
+    },
+    Preset {
+        name: "asdf",
+        ignored: &[],
+        ignored_prefixes: &[],
+        hidden_functions: &["_asdf_"],
+        hidden_function_names: &["asdf"],
+        capture_env: &[],
+        command: asdf_command,
+        epilogue: Some(asdf_epilogue),
+        cache_inputs: None,
+        stable_lists: &["PATH"],
+    },
+    Preset {
+        name: "mise",
+        // Tells mise which shell syntax to print, bash here but not in fish
+        ignored: &["MISE_SHELL"],
+        ignored_prefixes: &[],
+        hidden_functions: &["_mise_", "__mise_"],
+        hidden_function_names: &["mise"],
+        capture_env: &[],
+        command: mise_command,
+        epilogue: Some(mise_epilogue),
+        cache_inputs: None,
+        stable_lists: &["PATH"],
+    },
+    Preset {
+        name: "module",
+        // The _ModuleTable*, __LMOD_REF_COUNT_* and __MODULES_* bookkeeping is
+        // kept, later `module unload`s read it from the environment
+        ignored: &[],
+        ignored_prefixes: &[],
+        hidden_functions: &[
+            "_module",
+            "__module",
+            "_ml",
+            "clearMT",
+            "clearLmod",
+            "xSetTitleLmod",
+            "switchml",
+            "_mlshopt",
+        ],
+        hidden_function_names: &["module", "ml"],
+        capture_env: &[],
+        command: module_command,
+        epilogue: Some(module_epilogue),
+        cache_inputs: None,
+        stable_lists: &[],
+    },
+    Preset {
+        name: "aws-vault",
+        // Marks the shell as being inside an aws-vault session, which would
+        // make later `aws-vault exec` calls from fish refuse to run
+        ignored: &["AWS_VAULT"],
+        ignored_prefixes: &[],
+        hidden_functions: &[],
+        hidden_function_names: &[],
+        capture_env: &[],
+        command: aws_vault_command,
+        epilogue: Some(aws_vault_epilogue),
+        cache_inputs: None,
+        stable_lists: &[],
+    },
+    Preset {
+        name: "gcloud",
+        ignored: &[],
+        ignored_prefixes: &[],
+        hidden_functions: &[],
+        hidden_function_names: &[],
+        capture_env: &[],
+        command: gcloud_command,
+        epilogue: None,
+        cache_inputs: None,
+        stable_lists: &[],
+    },
+    Preset {
+        name: "az",
+        ignored: &[],
+        ignored_prefixes: &[],
+        hidden_functions: &[],
+        hidden_function_names: &[],
+        capture_env: &[],
+        command: az_command,
+        epilogue: Some(az_epilogue),
+        cache_inputs: None,
+        stable_lists: &[],
+    },
+];
+
+impl Preset {
+    pub fn hides_function(&self, name: &str) -> bool {
+        self.hidden_function_names.contains(&name)
+            || self.hidden_functions.iter().any(|p| name.starts_with(p))
+    }
+
+    // The change of `name` keeps the same set of list entries
+    pub fn only_reorders(&self, name: &str, old: Option<&str>, new: Option<&str>) -> bool {
+        let (Some(old), Some(new)) = (old, new) else {
+            return false;
+        };
+        self.stable_lists.contains(&name)
+            && old.split(':').collect::<BTreeSet<_>>() == new.split(':').collect::<BTreeSet<_>>()
+    }
+}
+
+pub fn find(name: &str) -> io::Result<&'static Preset> {
+    PRESETS.iter().find(|p| p.name == name).ok_or_else(|| {
+        let names: Vec<_> = PRESETS.iter().map(|p| p.name).collect();
+        invalid(format!(
+            "Unknown preset {} (available: {})",
+            name,
+            names.join(", ")
+        ))
+    })
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+// Quotes a word for bash
+pub fn bash_quote(word: &str) -> String {
+    format!("'{}'", word.replace('\'', "'\\''"))
+}
+
+// Bash code from the words of a command line, as given to `basrs <command>`.
+// The first word and those after a separate `;`, `&&`, `||`, `|` or `&` are
+// code, as in `source nvm.sh --no-use ';' nvm use 20`; all other words are
+// arguments passed on as they are, so `export FOO='a b'` keeps its value.
+pub fn command_line(words: &[String]) -> String {
+    let mut line = Vec::with_capacity(words.len());
+    let mut code = true;
+    for word in words {
+        let operator = matches!(word.as_str(), ";" | "&&" | "||" | "|" | "&");
+        let plain = word.chars().all(|c| {
+            c.is_ascii_alphanumeric()
+                || matches!(c, '-' | '_' | '.' | '/' | ':' | '=' | '@' | '%' | '+' | ',')
+        });
+        line.push(if code || operator || (plain && !word.is_empty()) {
+            word.clone()
+        } else {
+            bash_quote(word)
+        });
+        code = operator;
+    }
+    line.join(" ")
+}
+
+// Quotes the arguments as one bash command line
+fn bash_command(args: &[String]) -> String {
+    args.iter()
+        .map(|arg| bash_quote(arg))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn venv_command(args: &[String]) -> io::Result<String> {
+    let [path] = args else {
+        return Err(invalid(
+            "venv preset expects the virtualenv directory or its activate script".to_string(),
+        ));
+    };
+    let path = Path::new(path);
+    let script = if path.is_dir() {
+        path.join("bin").join("activate")
+    } else {
+        path.to_path_buf()
+    };
+    if !script.is_file() {
+        return Err(invalid(format!(
+            "No activate script at {}",
+            script.display()
+        )));
+    }
+    Ok(format!("source {}", bash_quote(&script.to_string_lossy())))
+}
+
+fn venv_epilogue(context: &PresetContext) -> String {
+    let mut lines = Vec::new();
+    if context.prompt {
+        lines.push(
+            "if not functions -q _basrs_venv_fish_prompt
+    functions -q fish_prompt; and functions -c fish_prompt _basrs_venv_fish_prompt
+    function fish_prompt
+        set -l old_status $status
+        printf '(%s) ' (path basename $VIRTUAL_ENV)
+        echo \"exit $old_status\" | source
+        functions -q _basrs_venv_fish_prompt; and _basrs_venv_fish_prompt
+    end
+end"
+            .to_string(),
+        );
+    }
+
+    // Mirrors the bash deactivate function using the values from before activation
+    lines.push(
+        "function deactivate --description \"Deactivate the virtual environment\"".to_string(),
+    );
+    for (name, old_value, _) in &context.changed {
+        lines.push(match old_value {
+            Some(old_value) => format!("    set -g -x {} {}", name, escape(old_value)),
+            None => format!("    set -e {}", name),
+        });
+    }
+    lines.push(
+        "    if functions -q _basrs_venv_fish_prompt
+        functions -e fish_prompt
+        functions -c _basrs_venv_fish_prompt fish_prompt
+        functions -e _basrs_venv_fish_prompt
+    end
+    functions -e deactivate
+end"
+        .to_string(),
+    );
+    lines.join("\n")
+}
+
+// rvm lives entirely in bash functions, so every call goes through a fresh capture
+fn rvm_command(args: &[String]) -> io::Result<String> {
+    let mut command = "source ${rvm_path:-$HOME/.rvm}/scripts/rvm".to_string();
+    if !args.is_empty() {
+        command.push_str(" && rvm ");
+        command.push_str(&bash_command(args));
+    }
+    Ok(command)
+}
+
+fn rvm_epilogue(_context: &PresetContext) -> String {
+    "function rvm --description \"Ruby Version Manager (through basrs)\"
+    basrs --preset rvm $argv | source
+end"
+    .to_string()
+}
+
+fn sdkman_command(args: &[String]) -> io::Result<String> {
+    let mut command = "source ${SDKMAN_DIR:-$HOME/.sdkman}/bin/sdkman-init.sh".to_string();
+    if !args.is_empty() {
+        command.push_str(" && sdk ");
+        command.push_str(&bash_command(args));
+    }
+    Ok(command)
+}
+
+fn sdkman_epilogue(_context: &PresetContext) -> String {
+    "function sdk --description \"SDKMAN! (through basrs)\"
+    basrs --preset sdkman $argv | source
+end"
+    .to_string()
+}
+
+// Only the bare initialization is cached, it is what runs on every fish startup
+// and only changes when sdkman or the set of installed candidates does
+fn sdkman_cache_inputs(args: &[String]) -> Option<Vec<PathBuf>> {
+    if !args.is_empty() {
+        return None;
+    }
+    let dir = env::var_os("SDKMAN_DIR")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".sdkman")))?;
+    let mut inputs = vec![
+        dir.join("bin").join("sdkman-init.sh"),
+        dir.join("etc").join("config"),
+        dir.join("candidates"),
+    ];
+    // Switching the default version of a candidate re-points its `current` link
+    if let Ok(candidates) = dir.join("candidates").read_dir() {
+        inputs.extend(candidates.flatten().map(|c| c.path().join("current")));
+    }
+    Some(inputs)
+}
+
+// Extra arguments go to `opam env`, e.g. `--switch=4.14 --set-switch`. opam
+// reverts the previous switch's changes itself based on OPAM_SWITCH_PREFIX
+// and OPAM_LAST_ENV, so switching shows up as regular updates and removals.
+fn opam_command(args: &[String]) -> io::Result<String> {
+    let mut command = "eval \"$(opam env --shell=bash".to_string();
+    for arg in args {
+        command.push(' ');
+        command.push_str(&bash_quote(arg));
+    }
+    command.push_str(")\"");
+    Ok(command)
+}
+
+// asdf is a bash function around the asdf binary, only `asdf shell` changes
+// the environment of the calling shell
+fn asdf_command(args: &[String]) -> io::Result<String> {
+    let mut command = "source ${ASDF_DIR:-$HOME/.asdf}/asdf.sh".to_string();
+    if !args.is_empty() {
+        command.push_str(" && asdf ");
+        command.push_str(&bash_command(args));
+    }
+    Ok(command)
+}
+
+fn asdf_epilogue(_context: &PresetContext) -> String {
+    "function asdf --description \"asdf version manager (through basrs)\"
+    if test \"$argv[1]\" = shell
+        basrs --preset asdf $argv | source
+    else
+        command asdf $argv
+    end
+end"
+    .to_string()
+}
+
+// There is no prompt in the capture, so the hook mise normally runs before
+// each prompt is run by hand after activating and running mise
+fn mise_command(args: &[String]) -> io::Result<String> {
+    let mut command = "eval \"$(mise activate bash)\"".to_string();
+    if !args.is_empty() {
+        command.push_str(" && mise ");
+        command.push_str(&bash_command(args));
+    }
+    command.push_str(" && eval \"$(mise hook-env -s bash)\"");
+    Ok(command)
+}
+
+fn mise_epilogue(_context: &PresetContext) -> String {
+    "function mise --description \"mise-en-place (through basrs)\"
+    switch \"$argv[1]\"
+        case shell sh deactivate
+            basrs --preset mise $argv | source
+        case '*'
+            command mise $argv
+    end
+end"
+    .to_string()
+}
+
+// Lmod and Environment Modules both define `module` through an init script,
+// which a login shell would have sourced already
+fn module_command(args: &[String]) -> io::Result<String> {
+    if args.is_empty() {
+        return Err(invalid(
+            "module preset expects a module command, e.g. load gcc".to_string(),
+        ));
+    }
+    Ok(format!(
+        "if ! type module >/dev/null 2>&1; then \
+         for init in ${{MODULESHOME:+$MODULESHOME/init/bash}} ${{LMOD_PKG:+$LMOD_PKG/init/bash}} \
+         /usr/share/lmod/lmod/init/bash /usr/share/modules/init/bash /etc/profile.d/modules.sh; do \
+         if [ -r $init ]; then . $init; break; fi; done; fi; module {}",
+        bash_command(args)
+    ))
+}
+
+fn module_epilogue(_context: &PresetContext) -> String {
+    "function module --description \"Environment modules (through basrs)\"
+    basrs module $argv | source
+end
+function ml --description \"Environment modules (through basrs)\"
+    if set -q argv[1]
+        basrs module load $argv | source
+    else
+        basrs module list | source
+    end
+end"
+    .to_string()
+}
+
+// aws-vault only provides credentials to a subprocess, which prints the AWS_*
+// variables back in a form bash can evaluate. Arguments go to `aws-vault exec`,
+// e.g. `--no-session work`.
+fn aws_vault_command(args: &[String]) -> io::Result<String> {
+    if args.is_empty() {
+        return Err(invalid(
+            "aws-vault preset expects the profile to use".to_string(),
+        ));
+    }
+    Ok(format!(
+        "eval \"$(aws-vault exec {} -- bash -c 'for v in \"${{!AWS_@}}\"; do \
+         printf \"export %s=%q\\n\" \"$v\" \"${{!v}}\"; done')\"",
+        bash_command(args)
+    ))
+}
+
+fn aws_vault_epilogue(context: &PresetContext) -> String {
+    expiry_comment(
+        context,
+        &["AWS_CREDENTIAL_EXPIRATION", "AWS_SESSION_EXPIRATION"],
+    )
+}
+
+// Evaluates the output of a gcloud command printing exports, e.g.
+// `beta emulators pubsub env-init`
+fn gcloud_command(args: &[String]) -> io::Result<String> {
+    if args.is_empty() {
+        return Err(invalid(
+            "gcloud preset expects a gcloud command printing exports, e.g. \
+             beta emulators pubsub env-init"
+                .to_string(),
+        ));
+    }
+    Ok(format!("eval \"$(gcloud {})\"", bash_command(args)))
+}
+
+// An access token for the signed in account, arguments go to
+// `az account get-access-token`, e.g. `--resource https://vault.azure.net`
+fn az_command(args: &[String]) -> io::Result<String> {
+    let mut command = "__basrs_token=$(az account get-access-token".to_string();
+    for arg in args {
+        command.push(' ');
+        command.push_str(&bash_quote(arg));
+    }
+    command.push_str(
+        " --query '[accessToken, expiresOn]' -o tsv) && \
+         export AZURE_ACCESS_TOKEN=${__basrs_token%%$'\\n'*} \
+         AZURE_ACCESS_TOKEN_EXPIRES_ON=${__basrs_token#*$'\\n'}",
+    );
+    Ok(command)
+}
+
+fn az_epilogue(context: &PresetContext) -> String {
+    expiry_comment(context, &["AZURE_ACCESS_TOKEN_EXPIRES_ON"])
+}
+
+// Notes in the script when the credentials it sets stop working
+fn expiry_comment(context: &PresetContext, expiry_vars: &[&str]) -> String {
+    context
+        .changed
+        .iter()
+        .find(|(name, _, _)| expiry_vars.contains(name))
+        .map(|(_, _, expiry)| format!("# Credentials expire at {}", escape_comment(expiry)))
+        .unwrap_or_default()
+}
@@ -0,0 +1,76 @@
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// Captures faster than this don't get a progress line at all
+const DELAY: Duration = Duration::from_millis(300);
+const TICK: Duration = Duration::from_millis(100);
+const FRAMES: &[char] = &['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+
+// Spinner on stderr while a slow capture runs, erased again when dropped
+pub struct Progress {
+    done: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Progress {
+    pub fn start(label: &str) -> Progress {
+        let done = Arc::new(AtomicBool::new(false));
+        let thread = io::stderr().is_terminal().then(|| {
+            let label = label.to_string();
+            let done = done.clone();
+            thread::spawn(move || spin(&label, &done))
+        });
+        Progress { done, thread }
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        self.done.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+    }
+}
+
+fn spin(label: &str, done: &AtomicBool) {
+    let start = Instant::now();
+    thread::park_timeout(DELAY);
+    let mut shown = false;
+    let mut frames = FRAMES.iter().cycle();
+    while !done.load(Ordering::Relaxed) {
+        shown = true;
+        let elapsed = start.elapsed().as_secs_f32();
+        let frame = frames.next().unwrap();
+        eprint!("\r\x1b[K{} sourcing {}… {:.1}s", frame, label, elapsed);
+        let _ = io::stderr().flush();
+        thread::park_timeout(TICK);
+    }
+    if shown {
+        eprint!("\r\x1b[K");
+        let _ = io::stderr().flush();
+    }
+}
+
+// Name of the sourced file, or the command itself shortened to fit a line
+pub fn label(command: &str) -> String {
+    let command = command.trim();
+    let sourced = command
+        .strip_prefix("source ")
+        .or_else(|| command.strip_prefix(". "))
+        .and_then(|rest| rest.split_whitespace().next())
+        .and_then(|file| Path::new(file.trim_matches(['"', '\''])).file_name())
+        .map(|name| name.to_string_lossy().into_owned());
+    sourced.unwrap_or_else(|| {
+        let mut label: String = command.chars().take(40).collect();
+        if label.len() < command.len() {
+            label.push('…');
+        }
+        label
+    })
+}
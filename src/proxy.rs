@@ -0,0 +1,79 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use crate::capture;
+use crate::change::Action;
+use crate::compare;
+use crate::dirs;
+use crate::shell::Shell;
+
+// Bash or zsh function definitions saved for the fish functions calling
+// them. The file is named after its content, so regenerating the same script
+// refers to the same file.
+pub struct Definitions {
+    path: PathBuf,
+    text: String,
+    shell: Shell,
+}
+
+impl Definitions {
+    pub fn new(text: String, shell: Shell) -> Option<Definitions> {
+        let name = format!(
+            "{:016x}.{}",
+            capture::hash(text.as_bytes()),
+            shell.program()
+        );
+        Some(Definitions {
+            path: dirs::data_dir()?.join("functions").join(name),
+            text,
+            shell,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn defines(&self, function: &str) -> bool {
+        compare::parse_funcs(&self.text)
+            .iter()
+            .any(|(name, _)| *name == function)
+    }
+
+    pub fn write(&self) -> io::Result<()> {
+        if self.path.is_file() {
+            return Ok(());
+        }
+        let dir = self
+            .path
+            .parent()
+            .expect("definitions path has a directory");
+        fs::create_dir_all(dir)?;
+        // Written aside and renamed so a proxy never sources half a file
+        let partial = dir.join(format!(".{}", process::id()));
+        fs::write(&partial, &self.text)?;
+        fs::rename(&partial, &self.path)
+    }
+
+    // Function running the function `name` through basrs, named `name` after
+    // `prefix` in fish
+    pub fn proxy(&self, name: &str, prefix: &str) -> Action {
+        let call = format!(
+            "source {} && {}",
+            crate::preset::bash_quote(&self.path.to_string_lossy()),
+            name
+        );
+        let basrs_args = match self.shell {
+            Shell::Bash => String::new(),
+            shell => format!("--shell {}", shell.program()),
+        };
+        Action::DefineFunction {
+            name: format!("{}{}", prefix, name),
+            description: format!("{} function {} (through basrs)", self.shell.program(), name),
+            basrs_args,
+            call,
+        }
+    }
+}
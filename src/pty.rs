@@ -0,0 +1,151 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::os::fd::FromRawFd;
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::ptr;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+// How long output still buffered in the pty gets after the shell exited.
+// Background jobs may keep the pty open, what they print later is lost.
+const DRAIN: Duration = Duration::from_millis(100);
+
+// A pseudo-terminal for the command to run on, relayed to the terminal basrs
+// was started from
+pub struct Pty {
+    master: File,
+    slave: File,
+}
+
+impl Pty {
+    // Takes over the settings and window size of the user's terminal, so the
+    // command can't tell the difference
+    pub fn open() -> io::Result<Pty> {
+        let (mut master, mut slave) = (0, 0);
+        // SAFETY: zeroed termios and winsize are valid values, and both are
+        // only passed on when the calls filling them succeeded
+        let (mut termios, mut size): (libc::termios, libc::winsize) =
+            unsafe { (mem::zeroed(), mem::zeroed()) };
+        let termp = match unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut termios) } {
+            0 => &mut termios as *mut libc::termios,
+            _ => ptr::null_mut(),
+        };
+        let winp = match unsafe { libc::ioctl(libc::STDERR_FILENO, libc::TIOCGWINSZ, &mut size) } {
+            0 => &mut size as *mut libc::winsize,
+            _ => ptr::null_mut(),
+        };
+        // SAFETY: the out pointers are valid, openpty fills both fds on success
+        if unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                ptr::null_mut(),
+                termp as _,
+                winp as _,
+            )
+        } != 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: freshly opened and owned by nothing else
+        let (master, slave) = unsafe { (File::from_raw_fd(master), File::from_raw_fd(slave)) };
+        Ok(Pty { master, slave })
+    }
+
+    // The shell reads from and writes its messages to the pty, in a session of
+    // its own with the pty as controlling terminal so /dev/tty reaches it too.
+    // Its stdout stays as configured, the helper sends the command's output to
+    // stderr.
+    pub fn attach(&self, command: &mut Command) -> io::Result<()> {
+        command
+            .stdin(Stdio::from(self.slave.try_clone()?))
+            .stderr(Stdio::from(self.slave.try_clone()?));
+        // SAFETY: only async-signal-safe calls between fork and exec
+        unsafe {
+            command.pre_exec(|| {
+                if libc::setsid() == -1
+                    || libc::ioctl(libc::STDIN_FILENO, libc::TIOCSCTTY as _, 0) == -1
+                {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        Ok(())
+    }
+
+    // Relays the pty to stderr and stdin to the pty while `run` runs the
+    // attached command, with the user's terminal in raw mode so keys reach the
+    // command as typed. `run` has to drop the command, which holds the slave
+    // side open.
+    pub fn relay<T>(self, run: impl FnOnce() -> T) -> io::Result<T> {
+        let Pty { master, slave } = self;
+        drop(slave);
+        let mut output = master.try_clone()?;
+        let mut input = master;
+        let (done, finished) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buffer = [0; 4096];
+            let mut stderr = io::stderr();
+            // Reading fails with EIO once no process has the slave side open
+            while let Ok(n @ 1..) = output.read(&mut buffer) {
+                if stderr.write_all(&buffer[..n]).and(stderr.flush()).is_err() {
+                    break;
+                }
+            }
+            let _ = done.send(());
+        });
+        // Still blocked reading stdin when the command is done, it ends with
+        // basrs
+        thread::spawn(move || {
+            let mut buffer = [0; 1024];
+            let mut stdin = io::stdin();
+            while let Ok(n @ 1..) = stdin.read(&mut buffer) {
+                if input.write_all(&buffer[..n]).is_err() {
+                    break;
+                }
+            }
+        });
+        let raw = RawMode::enable();
+        let result = run();
+        let _ = finished.recv_timeout(DRAIN);
+        drop(raw);
+        Ok(result)
+    }
+}
+
+// The user's terminal without line editing, echo or signal keys, all of which
+// the pty does for the command. Restored when dropped.
+struct RawMode(Option<libc::termios>);
+
+impl RawMode {
+    fn enable() -> RawMode {
+        // SAFETY: termios is only used after tcgetattr filled it
+        unsafe {
+            let mut saved: libc::termios = mem::zeroed();
+            if libc::isatty(libc::STDIN_FILENO) == 0
+                || libc::tcgetattr(libc::STDIN_FILENO, &mut saved) != 0
+            {
+                return RawMode(None);
+            }
+            let mut raw = saved;
+            libc::cfmakeraw(&mut raw);
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw);
+            RawMode(Some(saved))
+        }
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        if let Some(saved) = &self.0 {
+            // SAFETY: restores settings tcgetattr returned
+            unsafe {
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, saved);
+            }
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+use crate::dirs;
+
+// Fish variable scope used when setting a variable
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    Local,
+    #[default]
+    Global,
+    Universal,
+}
+
+impl Scope {
+    pub fn from_code(code: &str) -> Option<Scope> {
+        match code {
+            "l" | "local" => Some(Scope::Local),
+            "g" | "global" => Some(Scope::Global),
+            "U" | "universal" => Some(Scope::Universal),
+            _ => None,
+        }
+    }
+
+    pub fn code(self) -> &'static str {
+        match self {
+            Scope::Local => "l",
+            Scope::Global => "g",
+            Scope::Universal => "U",
+        }
+    }
+}
+
+// Remembers which scope each variable was set in, so removals erase the right one
+#[derive(Default)]
+pub struct ScopeTracker {
+    // Scopes reported by fish itself (`set --show` dump), most authoritative
+    dumped: HashMap<String, Vec<Scope>>,
+    // Variables the dump marks as read-only, catching ones a newer fish adds
+    read_only: Vec<String>,
+    // Scopes basrs used for variables in previous runs
+    recorded: HashMap<String, Scope>,
+    state_file: Option<PathBuf>,
+}
+
+impl ScopeTracker {
+    pub fn load(fish_dump: Option<&str>) -> ScopeTracker {
+        let mut tracker = ScopeTracker {
+            state_file: dirs::state_dir().map(|dir| dir.join("scopes")),
+            ..Default::default()
+        };
+        if let Some(path) = &tracker.state_file {
+            if let Ok(content) = fs::read_to_string(path) {
+                tracker.recorded = parse_state(&content);
+            }
+        }
+        if let Some(content) = fish_dump {
+            tracker.dumped = parse_fish_dump(content);
+            tracker.read_only = parse_read_only(content);
+        }
+        tracker
+    }
+
+    pub fn read_only(&self) -> &[String] {
+        &self.read_only
+    }
+
+    pub fn record(&mut self, name: &str, scope: Scope) {
+        self.recorded.insert(name.to_string(), scope);
+    }
+
+    pub fn forget(&mut self, name: &str) {
+        self.recorded.remove(name);
+    }
+
+    // The scopes a removed variable has to be erased from, None for the
+    // first one fish finds
+    pub fn erase_scopes(&self, name: &str) -> Vec<Option<Scope>> {
+        if let Some(scopes) = self.dumped.get(name) {
+            return scopes.iter().copied().map(Some).collect();
+        }
+        // Unknown origin, let fish erase whichever scope it finds first
+        vec![self.recorded.get(name).copied()]
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.state_file else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut names: Vec<_> = self.recorded.iter().collect();
+        names.sort_by_key(|(name, _)| *name);
+        let content: String = names
+            .into_iter()
+            .map(|(name, scope)| format!("{} {}\n", name, scope.code()))
+            .collect();
+        fs::write(path, content)
+    }
+}
+
+// "NAME g" per line
+fn parse_state(content: &str) -> HashMap<String, Scope> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let (name, code) = line.split_once(' ')?;
+            Some((name.to_string(), Scope::from_code(code.trim())?))
+        })
+        .collect()
+}
+
+// Output of fish's `set --show`, e.g. "$PATH: set in global scope, exported, ..."
+fn parse_fish_dump(content: &str) -> HashMap<String, Vec<Scope>> {
+    let mut scopes: HashMap<String, Vec<Scope>> = HashMap::new();
+    for line in content.lines() {
+        let Some(rest) = line.strip_prefix('$') else {
+            continue;
+        };
+        let Some((name, info)) = rest.split_once(": set in ") else {
+            continue;
+        };
+        let Some(scope) = info.split_whitespace().next().and_then(Scope::from_code) else {
+            continue;
+        };
+        // A variable may exist in several scopes, all of them have to be erased
+        scopes.entry(name.to_string()).or_default().push(scope);
+    }
+    scopes
+}
+
+// Variables `set --show` flags as read-only, e.g. "$status: set in global
+// scope, unexported, with 1 elements (read-only)"
+fn parse_read_only(content: &str) -> Vec<String> {
+    let mut names: Vec<String> = content
+        .lines()
+        .filter_map(|line| {
+            let (name, info) = line.strip_prefix('$')?.split_once(": set in ")?;
+            info.contains("read-only").then(|| name.to_string())
+        })
+        .collect();
+    names.dedup();
+    names
+}
@@ -0,0 +1,104 @@
+use std::env;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+// POSIX shells tried in turn for `sh`, minimal systems may only have dash or
+// busybox's ash
+const POSIX_SHELLS: [&str; 3] = ["sh", "dash", "ash"];
+
+// Interpreter evaluating the command
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Shell {
+    #[default]
+    Bash,
+    Zsh,
+    // Plain POSIX sh, environment variables and aliases can be captured
+    Sh,
+}
+
+impl Shell {
+    pub fn from_name(name: &str) -> Option<Shell> {
+        match name {
+            "bash" => Some(Shell::Bash),
+            "zsh" => Some(Shell::Zsh),
+            "sh" => Some(Shell::Sh),
+            _ => None,
+        }
+    }
+
+    pub fn program(self) -> &'static str {
+        match self {
+            Shell::Bash => "bash",
+            Shell::Zsh => "zsh",
+            Shell::Sh => {
+                static FOUND: OnceLock<&str> = OnceLock::new();
+                FOUND.get_or_init(|| {
+                    POSIX_SHELLS
+                        .into_iter()
+                        .find(|name| find_program(name).is_some())
+                        .unwrap_or("sh")
+                })
+            }
+        }
+    }
+
+    // Script printing env, aliases, functions and the exit status after
+    // evaluating "$1"
+    pub fn capture_script(self) -> &'static str {
+        match self {
+            Shell::Bash => include_str!("capture.bash"),
+            Shell::Zsh => include_str!("capture.zsh"),
+            Shell::Sh => include_str!("capture.sh"),
+        }
+    }
+
+    // Where the capture script is installed
+    pub fn helper_name(self) -> &'static str {
+        match self {
+            Shell::Bash => "capture.bash",
+            Shell::Zsh => "capture.zsh",
+            Shell::Sh => "capture.sh",
+        }
+    }
+
+    // Checks the interpreter is installed before anything is spawned
+    pub fn locate(self) -> io::Result<PathBuf> {
+        if let Some(path) = find_program(self.program()) {
+            return Ok(path);
+        }
+        let hint = match self {
+            Shell::Bash => {
+                "; install bash, or use `--shell sh` to evaluate the command with sh \
+                 instead (environment variables and aliases, no functions)"
+            }
+            Shell::Sh => ", nor dash or ash; install dash or busybox",
+            Shell::Zsh => "",
+        };
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} not found in PATH{}", self.program(), hint),
+        ))
+    }
+}
+
+// With the .exe suffix too on Windows, where bash is bash.exe
+pub fn find_program(name: &str) -> Option<PathBuf> {
+    let path = env::var_os("PATH")?;
+    let suffixed = format!("{}{}", name, env::consts::EXE_SUFFIX);
+    env::split_paths(&path)
+        .flat_map(|dir| [dir.join(name), dir.join(&suffixed)])
+        .find(|candidate| is_executable(candidate))
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
@@ -0,0 +1,74 @@
+use std::sync::atomic::{AtomicI32, Ordering};
+
+const FORWARDED: [libc::c_int; 3] = [libc::SIGINT, libc::SIGTERM, libc::SIGHUP];
+
+// Where the handler sends signals: the child's pid, negated when it leads a
+// process group of its own
+static TARGET: AtomicI32 = AtomicI32::new(0);
+// The last signal received while forwarding
+static RECEIVED: AtomicI32 = AtomicI32::new(0);
+
+extern "C" fn forward(signal: libc::c_int) {
+    RECEIVED.store(signal, Ordering::Relaxed);
+    let target = TARGET.load(Ordering::Relaxed);
+    // A child in basrs's group got the terminal's Ctrl-C already, a second
+    // one would interrupt what it runs to handle the first
+    if target < 0 || (target > 0 && signal != libc::SIGINT) {
+        // SAFETY: kill is async-signal-safe
+        unsafe {
+            libc::kill(target, signal);
+        }
+    }
+}
+
+// Sends INT, TERM and HUP on to a running child instead of leaving it behind
+// when basrs is killed. A child in basrs's process group gets a Ctrl-C from
+// the terminal itself and only TERM and HUP through basrs, the others all
+// three. Dropped once the child
+// exited, after which `reraise` lets basrs die of the signal as well.
+pub struct Forwarding {
+    previous: Vec<libc::sighandler_t>,
+}
+
+impl Forwarding {
+    pub fn start(pid: u32) -> Forwarding {
+        let pid = pid as libc::pid_t;
+        // SAFETY: getpgid only reads, the handler only touches atomics and
+        // calls kill
+        unsafe {
+            let own_group = libc::getpgid(pid) == pid;
+            TARGET.store(if own_group { -pid } else { pid }, Ordering::Relaxed);
+            let previous = FORWARDED
+                .iter()
+                .map(|&signal| libc::signal(signal, forward as *const () as libc::sighandler_t))
+                .collect();
+            Forwarding { previous }
+        }
+    }
+}
+
+impl Drop for Forwarding {
+    fn drop(&mut self) {
+        // SAFETY: puts back the handlers `start` replaced
+        unsafe {
+            for (&signal, &previous) in FORWARDED.iter().zip(&self.previous) {
+                libc::signal(signal, previous);
+            }
+        }
+        TARGET.store(0, Ordering::Relaxed);
+    }
+}
+
+// Terminates basrs with the signal forwarded last, if any, so whoever sent it
+// sees basrs killed by it. Called once the terminal is restored and nothing
+// else needs cleaning up.
+pub fn reraise() {
+    let signal = RECEIVED.swap(0, Ordering::Relaxed);
+    if signal != 0 {
+        // SAFETY: the default action terminates the process
+        unsafe {
+            libc::signal(signal, libc::SIG_DFL);
+            libc::raise(signal);
+        }
+    }
+}
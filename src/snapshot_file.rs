@@ -0,0 +1,95 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::io;
+
+use serde::{Deserialize, Serialize};
+
+use crate::capture::{self, Snapshot};
+
+// Bumped whenever a field changes meaning, older files are then rejected
+// rather than misread
+const VERSION: u32 = 1;
+
+// The shell state written by `basrs snapshot`, diffed later against another
+// snapshot or the current environment. Aliases and functions are kept in the
+// listing formats of `alias -p` and `declare -f`, variables sorted by name.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SnapshotFile {
+    pub version: u32,
+    pub env: BTreeMap<String, String>,
+    #[serde(default)]
+    pub aliases: String,
+    #[serde(default)]
+    pub functions: String,
+    #[serde(default)]
+    pub state: BTreeMap<String, String>,
+}
+
+impl SnapshotFile {
+    pub fn new(snapshot: &Snapshot) -> SnapshotFile {
+        SnapshotFile {
+            version: VERSION,
+            env: snapshot
+                .env
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            aliases: snapshot.aliases.to_string(),
+            functions: snapshot.functions.to_string(),
+            state: snapshot
+                .state
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect(),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("snapshot serializes")
+    }
+
+    // None for anything else, e.g. an `env` dump, which can't start with a
+    // brace as no variable name does
+    pub fn parse(input: &[u8], name: &str) -> io::Result<Option<SnapshotFile>> {
+        if input.trim_ascii_start().first() != Some(&b'{') {
+            return Ok(None);
+        }
+        let invalid = |e: String| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid snapshot {}: {}", name, e),
+            )
+        };
+        let file: SnapshotFile =
+            serde_json::from_slice(input).map_err(|e| invalid(e.to_string()))?;
+        if file.version != VERSION {
+            return Err(invalid(format!(
+                "version {} is not supported, only {}",
+                file.version, VERSION
+            )));
+        }
+        Ok(Some(file))
+    }
+
+    pub fn snapshot(&self, select: &dyn Fn(&[u8]) -> bool) -> Snapshot<'_> {
+        Snapshot {
+            env: self
+                .env
+                .iter()
+                .filter(|(k, _)| select(k.as_bytes()))
+                .map(|(k, v)| (Cow::Borrowed(k.as_str()), Cow::Borrowed(v.as_str())))
+                .collect(),
+            aliases: Cow::Borrowed(&self.aliases),
+            aliases_hash: capture::hash(self.aliases.as_bytes()),
+            functions: Cow::Borrowed(&self.functions),
+            functions_hash: capture::hash(self.functions.as_bytes()),
+            state: self
+                .state
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect(),
+            ..Default::default()
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use std::io;
+use std::path::PathBuf;
+use std::process::Command;
+
+use crate::preset::bash_quote;
+use crate::shell::{find_program, Shell};
+
+// Variables describing the machine or the login session rather than the
+// environment a script sets up, left out with --skip-host-vars
+pub const HOST_VARS: &[&str] = &[
+    "HOSTNAME",
+    "HOST",
+    "SSH_*",
+    "DISPLAY",
+    "MAIL",
+    "TMPDIR",
+    "KRB5CCNAME",
+    "DBUS_SESSION_BUS_ADDRESS",
+    "XDG_RUNTIME_DIR",
+    "XDG_SESSION_*",
+];
+
+pub fn locate() -> io::Result<PathBuf> {
+    find_program("ssh").ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            "ssh not found in PATH, basrs ssh needs an OpenSSH client",
+        )
+    })
+}
+
+// Runs the helper with `shell` on `host`, through the wrapper if there is one
+// there. ssh joins its arguments into one command line for the remote login
+// shell, so every word is quoted, the helper passed inline as nothing of
+// basrs is installed on the other side.
+pub fn command(
+    host: &str,
+    wrapper: &[String],
+    shell: Shell,
+    shell_flags: &[&str],
+    helper_args: &[String],
+) -> Command {
+    let words: Vec<String> = wrapper
+        .iter()
+        .map(String::as_str)
+        .chain([shell.program()])
+        .chain(shell_flags.iter().copied())
+        .chain(["-c", shell.capture_script(), "basrs"])
+        .chain(helper_args.iter().map(String::as_str))
+        .map(bash_quote)
+        .collect();
+    let mut command = Command::new("ssh");
+    command.arg("-T").arg("--").arg(host).arg(words.join(" "));
+    command
+}
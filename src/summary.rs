@@ -0,0 +1,121 @@
+use std::env;
+use std::io::{self, IsTerminal};
+
+use crate::capture::Env;
+use crate::change::{Category, Change};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorMode {
+    // Colored when stderr is a terminal and NO_COLOR is not set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorMode {
+    pub fn from_name(name: &str) -> Option<ColorMode> {
+        match name {
+            "auto" => Some(ColorMode::Auto),
+            "always" => Some(ColorMode::Always),
+            "never" => Some(ColorMode::Never),
+            _ => None,
+        }
+    }
+
+    fn enabled(self) -> bool {
+        match self {
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+            ColorMode::Auto => {
+                io::stderr().is_terminal() && env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+            }
+        }
+    }
+}
+
+const GREEN: &str = "32";
+const YELLOW: &str = "33";
+const RED: &str = "31";
+const BOLD: &str = "1";
+
+// Variables added, updated and removed by the changes
+fn count_variables(changes: &[Change], old_env: &Env) -> (usize, usize, usize) {
+    let (mut added, mut updated, mut removed) = (0, 0, 0);
+    for change in changes.iter().filter(|c| c.category == Category::Variable) {
+        if change.erases {
+            removed += 1;
+        } else if old_env.contains_key(change.name.as_str()) {
+            updated += 1;
+        } else {
+            added += 1;
+        }
+    }
+    (added, updated, removed)
+}
+
+// The counts of --stats on one line
+pub fn stats(changes: &[Change], skipped: usize, old_env: &Env) -> String {
+    let (added, updated, removed) = count_variables(changes, old_env);
+    let count = |category| changes.iter().filter(|c| c.category == category).count();
+    format!(
+        "variables {} added, {} updated, {} removed; {} aliases, {} functions, {} settings, \
+         {} completions; {} skipped",
+        added,
+        updated,
+        removed,
+        count(Category::Alias),
+        count(Category::Function),
+        count(Category::Setting),
+        count(Category::Completion),
+        skipped
+    )
+}
+
+// What the changes do at a glance, for stderr: variables counted as added,
+// updated or removed, everything else listed by name
+pub fn render(label: &str, changes: &[Change], old_env: &Env, color: ColorMode) -> String {
+    let paint = |code: &str, text: &str| {
+        if color.enabled() {
+            format!("\x1b[{}m{}\x1b[0m", code, text)
+        } else {
+            text.to_string()
+        }
+    };
+    let (added, updated, removed) = count_variables(changes, old_env);
+    let mut summary = format!("{}\n", paint(BOLD, &format!("Basrs: {}", label)));
+    if changes.is_empty() {
+        summary.push_str("  nothing changed\n");
+        return summary;
+    }
+    if added + updated + removed > 0 {
+        summary.push_str(&format!(
+            "  variables: {}, {}, {}\n",
+            paint(GREEN, &format!("{} added", added)),
+            paint(YELLOW, &format!("{} updated", updated)),
+            paint(RED, &format!("{} removed", removed)),
+        ));
+    }
+    for (category, heading) in [
+        (Category::Alias, "aliases"),
+        (Category::Function, "functions"),
+        (Category::Setting, "settings"),
+        (Category::Completion, "completions"),
+    ] {
+        let names: Vec<String> = changes
+            .iter()
+            .filter(|c| c.category == category)
+            .map(|c| {
+                if c.erases {
+                    paint(RED, &c.name)
+                } else {
+                    c.name.clone()
+                }
+            })
+            .collect();
+        if !names.is_empty() {
+            summary.push_str(&format!("  {}: {}\n", heading, names.join(", ")));
+        }
+    }
+    summary
+}
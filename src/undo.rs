@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+
+use crate::capture::Env;
+use crate::change::{Action, Category, Change};
+use crate::compare::{array_elements, dir_stack};
+use crate::emitter::ShellEmitter;
+use crate::policy::Policy;
+
+// Fish script reverting the variable, shell option, umask, resource limit and
+// directory stack changes of a generated one, back to the values they had
+// before the command
+pub fn script(
+    changes: &[Change],
+    old_env: &Env,
+    old_state: &HashMap<String, String>,
+    policy: &Policy,
+    backups: bool,
+    emitter: &dyn ShellEmitter,
+) -> String {
+    let mut actions = Vec::new();
+    // Captured with --all-vars, these were not exported before the command
+    let shell_vars: Vec<&str> = old_state
+        .get("shellvars")
+        .map_or_else(Vec::new, |names| names.split(' ').collect());
+    for change in changes {
+        if change.category == Category::Setting {
+            let old = old_state.get(&change.name).cloned();
+            actions.extend(match change.name.strip_prefix("ulimit -") {
+                _ if change.name == "umask" => old.map(Action::Umask),
+                _ if change.name == "OLDPWD" => {
+                    old.filter(|dir| !dir.is_empty()).map(Action::PreviousDir)
+                }
+                _ if change.name == "DIRSTACK" => Some(Action::DirStack(
+                    dir_stack(old_state)
+                        .into_iter()
+                        .map(str::to_string)
+                        .collect(),
+                )),
+                Some(flag) => old.map(|limit| Action::Ulimit {
+                    flag: flag.to_string(),
+                    limit,
+                }),
+                None => Some(Action::SetOption {
+                    name: change.name.clone(),
+                    enabled: change.erases,
+                }),
+            });
+            continue;
+        }
+        if change.category != Category::Variable {
+            continue;
+        }
+        let (name, scope) = (change.name.clone(), policy.scope(&change.name));
+        if let Some(old) = old_state.get(&format!("array {}", name)) {
+            let elements = array_elements(old).unwrap_or_default();
+            actions.push(Action::SetList {
+                name,
+                values: elements.into_iter().map(|e| e.into_owned()).collect(),
+                scope,
+            });
+            continue;
+        }
+        actions.push(match old_env.get(name.as_str()) {
+            Some(old) if name == "PWD" => Action::ChangeDir {
+                dir: old.to_string(),
+                exists: None,
+            },
+            Some(old) if policy.path_var(&name) => Action::SetPathVar {
+                entries: old.split(':').map(str::to_string).collect(),
+                name,
+                scope,
+            },
+            Some(old) if shell_vars.contains(&name.as_str()) => Action::SetShellVar {
+                name,
+                value: old.to_string(),
+                scope,
+            },
+            Some(old) => Action::SetVar {
+                name,
+                value: old.to_string(),
+                scope,
+            },
+            None => Action::UnsetVar { name, scope: None },
+        });
+    }
+    let mut lines = vec!["# Undoes the changes of basrs, source it to go back".to_string()];
+    lines.extend(actions.iter().flat_map(|action| action.emit(emitter)));
+    let redefines = changes
+        .iter()
+        .any(|c| matches!(c.category, Category::Alias | Category::Function));
+    if redefines && backups {
+        lines.push("basrs_restore_functions".to_string());
+    } else if redefines {
+        lines.push(
+            "# Aliases and functions are not restored, generate with --backup-functions for that"
+                .to_string(),
+        );
+    }
+    lines.push(String::new());
+    lines.join("\n")
+}
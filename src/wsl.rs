@@ -0,0 +1,47 @@
+use std::borrow::Cow;
+use std::io;
+use std::path::PathBuf;
+use std::process::Command;
+
+use crate::shell::{find_program, Shell};
+
+// Reachable from Windows and, through interop, from inside WSL as well
+const WSL: &str = "wsl.exe";
+
+pub fn locate() -> io::Result<PathBuf> {
+    find_program(WSL).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            "wsl.exe not found in PATH, --wsl needs the Windows Subsystem for Linux",
+        )
+    })
+}
+
+// Starts `shell` in the distribution, the default one when empty, through
+// the wrapper if there is one. The helper and the environment stay on this
+// side, so the caller passes the helper inline.
+pub fn command(distro: &str, wrapper: &[String], shell: Shell) -> Command {
+    let mut command = Command::new(WSL);
+    if !distro.is_empty() {
+        command.arg("-d").arg(distro);
+    }
+    command.arg("--exec").args(wrapper).arg(shell.program());
+    command
+}
+
+// `/mnt/c/Users` as `C:\Users`, paths outside the Windows drives as they are
+pub fn windows_path(path: &str) -> Cow<'_, str> {
+    let Some(rest) = path.strip_prefix("/mnt/") else {
+        return Cow::Borrowed(path);
+    };
+    let (drive, rest) = rest.split_once('/').unwrap_or((rest, ""));
+    let mut chars = drive.chars();
+    match (chars.next(), chars.next()) {
+        (Some(letter), None) if letter.is_ascii_alphabetic() => Cow::Owned(format!(
+            "{}:\\{}",
+            letter.to_ascii_uppercase(),
+            rest.replace('/', "\\")
+        )),
+        _ => Cow::Borrowed(path),
+    }
+}